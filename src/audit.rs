@@ -0,0 +1,363 @@
+//! Audit trail of the operations run by [`MemoryHub`](crate::MemoryHub):
+//! the [`AuditSink`] trait, built-in file sinks and [`AuditQuery`].
+use crate::backend::HubResult;
+use crate::error::HubError;
+use crate::policy::Action;
+use futures::stream::{BoxStream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// One operation on a key, or a scan, with the policy decision and outcome.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditEvent {
+    /// Milliseconds since the Unix epoch at which the policy was asked.
+    pub at_millis: u64,
+    pub action: Action,
+    /// [`Principal::id`](crate::Principal::id) of the caller.
+    pub principal: String,
+    /// Key, or scan prefix or lower bound; `None` for full scans.
+    pub key: Option<String>,
+    pub namespace: Option<String>,
+    /// Value length of writes.
+    pub size: Option<u64>,
+    /// Policy decision.
+    pub allowed: bool,
+    /// [`Decision::obligations`](crate::Decision::obligations) the policy
+    /// attached to its allow.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub obligations: Vec<serde_json::Value>,
+    /// Keys a scan handed out, in order.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub returned: Vec<String>,
+    pub outcome: AuditOutcome,
+}
+
+/// What became of an audited operation.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum AuditOutcome {
+    /// Completed. Scans count as completed once the stream ends or is
+    /// dropped without yielding an error.
+    Ok,
+    /// Refused by the policy before reaching any backend.
+    Denied,
+    Failed { error: String },
+    /// The operation was dropped before it finished.
+    Cancelled,
+}
+
+/// Receives every audited operation of a hub, allowed or denied.
+///
+/// Denied operations are recorded at once; allowed ones when they finish,
+/// with their outcome. `record` runs inline on the operation's path, so
+/// sinks doing slow I/O should buffer. A sink that fails to store an event
+/// must not panic; the operation goes ahead either way.
+pub trait AuditSink: Send + Sync {
+    fn record(&self, event: &AuditEvent);
+}
+
+/// Event of an allowed operation, recorded once the operation finishes or,
+/// as [`AuditOutcome::Cancelled`], when it is dropped unfinished.
+pub(crate) struct Audit(Option<(Arc<dyn AuditSink>, AuditEvent)>);
+
+impl Audit {
+    /// Nothing to record: the hub has no sink.
+    pub(crate) fn none() -> Self {
+        Self(None)
+    }
+
+    pub(crate) fn pending(sink: Arc<dyn AuditSink>, event: AuditEvent) -> Self {
+        Self(Some((sink, AuditEvent { outcome: AuditOutcome::Cancelled, ..event })))
+    }
+
+    pub(crate) fn record<T>(mut self, res: &HubResult<T>) {
+        if let Some((_, event)) = &mut self.0 {
+            event.outcome = match res {
+                Ok(_) => AuditOutcome::Ok,
+                Err(e) => AuditOutcome::Failed { error: e.to_string() },
+            };
+        }
+    }
+
+    pub(crate) fn fail(mut self, error: String) {
+        if let Some((_, event)) = &mut self.0 {
+            event.outcome = AuditOutcome::Failed { error };
+        }
+    }
+
+    pub(crate) fn finish<T>(self, res: HubResult<T>) -> HubResult<T> {
+        self.record(&res);
+        res
+    }
+
+    /// Record when `stream` is dropped, with the keys it yielded, failed
+    /// if it yielded an error.
+    pub(crate) fn stream<T: Send + 'static>(
+        mut self,
+        stream: BoxStream<'static, HubResult<T>>,
+        key_of: fn(&T) -> &String,
+    ) -> BoxStream<'static, HubResult<T>> {
+        if let Some((_, event)) = &mut self.0 {
+            event.outcome = AuditOutcome::Ok;
+        }
+        stream.map(move |item| {
+            if let Some((_, event)) = &mut self.0 {
+                match &item {
+                    Ok(item) => event.returned.push(key_of(item).clone()),
+                    Err(e) if event.outcome == AuditOutcome::Ok => event.outcome = AuditOutcome::Failed { error: e.to_string() },
+                    Err(_) => {}
+                }
+            }
+            item
+        }).boxed()
+    }
+}
+
+impl Drop for Audit {
+    fn drop(&mut self) {
+        if let Some((sink, event)) = self.0.take() {
+            sink.record(&event);
+        }
+    }
+}
+
+/// Filter over audit events; unset criteria match everything.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AuditQuery {
+    key: Option<String>,
+    key_prefix: Option<String>,
+    principal: Option<String>,
+    from_millis: Option<u64>,
+    to_millis: Option<u64>,
+}
+
+impl AuditQuery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn key(mut self, key: impl Into<String>) -> Self {
+        self.key = Some(key.into());
+        self
+    }
+
+    pub fn key_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.key_prefix = Some(prefix.into());
+        self
+    }
+
+    pub fn principal(mut self, id: impl Into<String>) -> Self {
+        self.principal = Some(id.into());
+        self
+    }
+
+    /// Events at or after `from_millis` and before `to_millis`.
+    pub fn between(mut self, from_millis: u64, to_millis: u64) -> Self {
+        self.from_millis = Some(from_millis);
+        self.to_millis = Some(to_millis);
+        self
+    }
+
+    pub fn matches(&self, event: &AuditEvent) -> bool {
+        let key = event.key.as_deref();
+        self.key.as_deref().is_none_or(|k| key == Some(k))
+            && self.key_prefix.as_deref().is_none_or(|p| key.is_some_and(|k| k.starts_with(p)))
+            && self.principal.as_deref().is_none_or(|id| event.principal == id)
+            && self.from_millis.is_none_or(|from| event.at_millis >= from)
+            && self.to_millis.is_none_or(|to| event.at_millis < to)
+    }
+
+    /// Matching events of a JSONL audit log, as written by [`JsonlAuditSink`],
+    /// in log order.
+    pub fn read(&self, path: impl AsRef<Path>) -> HubResult<Vec<AuditEvent>> {
+        let mut out = Vec::new();
+        for (n, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+            let event: AuditEvent = serde_json::from_str(&line)
+                .map_err(|e| HubError::Corruption(format!("audit log line {}: {e}", n + 1)))?;
+            if self.matches(&event) {
+                out.push(event);
+            }
+        }
+        Ok(out)
+    }
+}
+
+fn append_only(path: &Path) -> HubResult<File> {
+    Ok(OpenOptions::new().create(true).append(true).open(path)?)
+}
+
+fn line_of(event: &AuditEvent) -> Vec<u8> {
+    let mut line = serde_json::to_vec(event).expect("audit events serialize");
+    line.push(b'\n');
+    line
+}
+
+/// Appends each event as one JSON line to a file. Each line goes out in a
+/// single write; call [`sync`](Self::sync) to make them durable.
+pub struct JsonlAuditSink {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl JsonlAuditSink {
+    pub fn open(path: impl AsRef<Path>) -> HubResult<Self> {
+        let path = path.as_ref().to_path_buf();
+        Ok(Self { file: Mutex::new(append_only(&path)?), path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn sync(&self) -> HubResult<()> {
+        Ok(self.file.lock().expect("audit lock").sync_data()?)
+    }
+
+    pub fn query(&self, query: &AuditQuery) -> HubResult<Vec<AuditEvent>> {
+        query.read(&self.path)
+    }
+}
+
+impl AuditSink for JsonlAuditSink {
+    fn record(&self, event: &AuditEvent) {
+        if let Err(e) = self.file.lock().expect("audit lock").write_all(&line_of(event)) {
+            log::warn!("audit log {}: {e}", self.path.display());
+        }
+    }
+}
+
+#[cfg(feature = "merkle_log")]
+pub use chained::MerkleAuditSink;
+
+#[cfg(feature = "merkle_log")]
+mod chained {
+    use super::*;
+    use crate::merkle;
+    use std::io::Read;
+
+    /// Tamper-evident audit log: `audit.jsonl` holds the events as in
+    /// [`JsonlAuditSink`], each line ending in a `chain` field with the hash
+    /// of the previous line's hash and the event. Editing, dropping or
+    /// reordering events breaks [`verify`](Self::verify), and the
+    /// [`root`](Self::root), a Merkle root over the chain, can be published
+    /// as a checkpoint.
+    ///
+    /// An event and its hash go out in one write; a write that fails is cut
+    /// off again, and a torn last line left by a crash is dropped on open.
+    pub struct MerkleAuditSink {
+        events: PathBuf,
+        chain: Mutex<Chain>,
+    }
+
+    struct Chain {
+        file: File,
+        /// Length of the file up to the last complete line.
+        len: u64,
+        head: [u8; 32],
+    }
+
+    /// Ahead of the hex hash that ends every line.
+    const CHAIN_FIELD: &[u8] = b",\"chain\":\"";
+
+    fn link(head: &[u8; 32], event: &[u8]) -> [u8; 32] {
+        merkle::leaf(&[head.as_slice(), event].concat())
+    }
+
+    /// Log line of the serialized `event` with its chained hash.
+    fn chained(event: &[u8], leaf: &[u8; 32]) -> Vec<u8> {
+        let mut line = event[..event.len() - 1].to_vec();
+        line.extend_from_slice(CHAIN_FIELD);
+        line.extend_from_slice(hex::encode(leaf).as_bytes());
+        line.extend_from_slice(b"\"}\n");
+        line
+    }
+
+    /// Serialized event and stored hash of a log line.
+    fn unchained(line: &[u8]) -> Option<(Vec<u8>, [u8; 32])> {
+        let body = line.strip_suffix(b"\"}")?;
+        let (event, hash) = body.split_at(body.len().checked_sub(CHAIN_FIELD.len() + 64)?);
+        let mut leaf = [0; 32];
+        hex::decode_to_slice(hash.strip_prefix(CHAIN_FIELD)?, &mut leaf).ok()?;
+        Some(([event, b"}"].concat(), leaf))
+    }
+
+    impl MerkleAuditSink {
+        /// Open or create the log in `dir`.
+        pub fn open(dir: impl AsRef<Path>) -> HubResult<Self> {
+            let dir = dir.as_ref();
+            std::fs::create_dir_all(dir)?;
+            let events = dir.join("audit.jsonl");
+            let file = append_only(&events)?;
+            let mut text = Vec::new();
+            File::open(&events)?.read_to_end(&mut text)?;
+            let len = text.iter().rposition(|&b| b == b'\n').map_or(0, |n| n + 1);
+            if len < text.len() {
+                file.set_len(len as u64)?;
+            }
+            let head = match text[..len].split(|&b| b == b'\n').rev().nth(1) {
+                Some(line) => unchained(line).ok_or_else(|| HubError::Corruption("last audit event has no chained hash".into()))?.1,
+                None => [0; 32],
+            };
+            Ok(Self { chain: Mutex::new(Chain { file, len: len as u64, head }), events })
+        }
+
+        pub fn query(&self, query: &AuditQuery) -> HubResult<Vec<AuditEvent>> {
+            query.read(&self.events)
+        }
+
+        /// Merkle root over the hashes of all recorded events.
+        pub fn root(&self) -> HubResult<[u8; 32]> {
+            let _chain = self.chain.lock().expect("audit lock");
+            let mut leaves = Vec::new();
+            for (n, line) in BufReader::new(File::open(&self.events)?).split(b'\n').enumerate() {
+                let (_, leaf) = unchained(&line?).ok_or_else(|| HubError::Corruption(format!("audit event {} has no chained hash", n + 1)))?;
+                leaves.push(leaf);
+            }
+            Ok(merkle::root_of(leaves))
+        }
+
+        /// Recompute the chain from the events and compare it with the
+        /// stored hashes. Returns the root, or [`HubError::Corruption`]
+        /// naming the first event that does not match.
+        pub fn verify(&self) -> HubResult<[u8; 32]> {
+            let _chain = self.chain.lock().expect("audit lock");
+            let mut head = [0; 32];
+            let mut leaves = Vec::new();
+            for (n, line) in BufReader::new(File::open(&self.events)?).split(b'\n').enumerate() {
+                head = match unchained(&line?) {
+                    Some((event, leaf)) if link(&head, &event) == leaf => leaf,
+                    _ => return Err(HubError::Corruption(format!("audit event {} does not match its chained hash", n + 1))),
+                };
+                leaves.push(head);
+            }
+            Ok(merkle::root_of(leaves))
+        }
+    }
+
+    impl AuditSink for MerkleAuditSink {
+        fn record(&self, event: &AuditEvent) {
+            let event = serde_json::to_vec(event).expect("audit events serialize");
+            let mut chain = self.chain.lock().expect("audit lock");
+            let leaf = link(&chain.head, &event);
+            let line = chained(&event, &leaf);
+            if let Err(e) = chain.file.write_all(&line) {
+                // a partial line would break the chain for every later event
+                let len = chain.len;
+                if let Err(e) = chain.file.set_len(len) {
+                    log::warn!("audit log {}: cannot cut off failed write: {e}", self.events.display());
+                }
+                log::warn!("audit log {}: {e}", self.events.display());
+                return;
+            }
+            chain.len += line.len() as u64;
+            chain.head = leaf;
+        }
+    }
+}
@@ -1,15 +1,181 @@
+use crate::error::HubError;
+use crate::health::{Capabilities, Health};
+use crate::record::{CasOutcome, Expected, Record, WriteMeta};
+use crate::ttl::now_millis;
+use crate::txn::Transaction;
 use async_trait::async_trait;
-use std::error::Error;
+use futures::stream::{self, BoxStream, StreamExt};
+use std::ops::Bound;
+use std::time::Duration;
 
 /// Alias for library result type.
-pub type HubResult<T> = Result<T, Box<dyn Error + Send + Sync + 'static>>;
+pub type HubResult<T> = Result<T, HubError>;
+
+/// Stream of key/value pairs produced by [`MemoryBackend::scan`], sorted by key.
+pub type EntryStream = BoxStream<'static, HubResult<(String, Vec<u8>)>>;
+
+/// Stream of keys produced by [`MemoryBackend::scan_keys`], sorted.
+pub type KeyStream = BoxStream<'static, HubResult<String>>;
+
+/// Key selection for scans.
+#[derive(Debug, Clone)]
+pub enum ScanRange {
+    /// Every key held by the backend.
+    All,
+    /// Keys starting with the given prefix.
+    Prefix(String),
+    /// Keys inside the given bounds (lexicographic order).
+    Range(Bound<String>, Bound<String>),
+}
+
+impl ScanRange {
+    /// Check whether `key` falls into the range.
+    pub fn contains(&self, key: &str) -> bool {
+        match self {
+            ScanRange::All => true,
+            ScanRange::Prefix(p) => key.starts_with(p.as_str()),
+            ScanRange::Range(start, end) => {
+                let after_start = match start {
+                    Bound::Included(s) => key >= s.as_str(),
+                    Bound::Excluded(s) => key > s.as_str(),
+                    Bound::Unbounded => true,
+                };
+                let before_end = match end {
+                    Bound::Included(e) => key <= e.as_str(),
+                    Bound::Excluded(e) => key < e.as_str(),
+                    Bound::Unbounded => true,
+                };
+                after_start && before_end
+            }
+        }
+    }
+}
 
 /// Core abstraction every storage adapter or plugin must implement.
 #[async_trait]
 pub trait MemoryBackend: Send + Sync {
+    /// Features this backend implements. The default declares none, matching
+    /// the default methods, which refuse every optional operation.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::default()
+    }
+
+    /// Probe whether the backend can serve requests. The default reports healthy.
+    async fn health(&self) -> Health {
+        Health::Healthy
+    }
+
     /// Persist or update the value associated with the given key.
     async fn write(&self, key: String, value: Vec<u8>) -> HubResult<()>;
 
-    /// Retrieve value by key. `Ok(None)` means key not found.
+    /// Retrieve value by key. `Ok(None)` means key not found or expired.
     async fn read(&self, key: String) -> HubResult<Option<Vec<u8>>>;
-} 
\ No newline at end of file
+
+    /// Persist the value and expire it after `ttl`. A later plain `write`
+    /// clears the expiry. Backends without expiry support return an error.
+    async fn write_with_ttl(&self, key: String, value: Vec<u8>, ttl: Duration) -> HubResult<()> {
+        let _ = (key, value, ttl);
+        Err(HubError::Unsupported("ttl not supported by backend".into()))
+    }
+
+    /// Reclaim space held by expired entries. Returns the number removed.
+    async fn sweep_expired(&self) -> HubResult<usize> {
+        Ok(0)
+    }
+
+    /// Persist the value with caller-supplied metadata. The backend assigns
+    /// timestamps and checksum, and the version unless `meta` sets one.
+    /// Backends that keep no metadata use the default, which stores the bare
+    /// value, through [`write_with_ttl`](Self::write_with_ttl) if `meta` sets an expiry.
+    async fn write_with_meta(&self, key: String, value: Vec<u8>, meta: WriteMeta) -> HubResult<()> {
+        match meta.expires_at {
+            Some(at) => self.write_with_ttl(key, value, Duration::from_millis(at.saturating_sub(now_millis()))).await,
+            None => self.write(key, value).await,
+        }
+    }
+
+    /// Retrieve the value together with its metadata. Backends that keep no
+    /// metadata use the default, which reports every value as [`Record::legacy`].
+    async fn read_record(&self, key: String) -> HubResult<Option<Record>> {
+        Ok(self.read(key).await?.map(Record::legacy))
+    }
+
+    /// Store `new` only if the current entry matches `expected`, atomically
+    /// with respect to other writers of this backend. A successful swap clears
+    /// any expiry. Backends without conditional writes return an error.
+    async fn compare_and_swap(&self, key: String, expected: Expected, new: Vec<u8>) -> HubResult<CasOutcome> {
+        let _ = (key, expected, new);
+        Err(HubError::Unsupported("compare-and-swap not supported by backend".into()))
+    }
+
+    /// Like [`compare_and_swap`](Self::compare_and_swap), storing `new` with
+    /// the metadata of `meta` as [`write_with_meta`](Self::write_with_meta)
+    /// does, expiry included. Backends that keep no metadata use the default,
+    /// which ignores content type, tags and version and refuses an expiry.
+    async fn compare_and_swap_with_meta(&self, key: String, expected: Expected, new: Vec<u8>, meta: WriteMeta) -> HubResult<CasOutcome> {
+        if meta.expires_at.is_some() {
+            return Err(HubError::Unsupported("conditional writes with expiry not supported by backend".into()));
+        }
+        self.compare_and_swap(key, expected, new).await
+    }
+
+    /// Store the value only if the key is absent. Returns `true` if it was stored.
+    async fn put_if_absent(&self, key: String, value: Vec<u8>) -> HubResult<bool> {
+        Ok(self.compare_and_swap(key, Expected::Absent, value).await?.is_swapped())
+    }
+
+    /// Apply every operation of `tx` or none of them. Backends without
+    /// transaction support keep the default, which returns an error.
+    async fn commit(&self, tx: Transaction) -> HubResult<()> {
+        let _ = tx;
+        Err(HubError::Unsupported("transactions not supported by backend".into()))
+    }
+
+    /// Write a batch of entries, each with the metadata of `meta` as
+    /// [`write_with_meta`](Self::write_with_meta) stores it. Backends that keep
+    /// no metadata use the default, which ignores `meta` and calls
+    /// [`write_many`](Self::write_many).
+    async fn write_many_with_meta(&self, items: &[(String, Vec<u8>)], meta: WriteMeta) -> Vec<HubResult<()>> {
+        let _ = meta;
+        self.write_many(items).await
+    }
+
+    /// Write a batch of entries. Returns one result per item, in input order,
+    /// so a bad entry does not fail the whole batch.
+    async fn write_many(&self, items: &[(String, Vec<u8>)]) -> Vec<HubResult<()>> {
+        let mut out = Vec::with_capacity(items.len());
+        for (k, v) in items {
+            out.push(self.write(k.clone(), v.clone()).await);
+        }
+        out
+    }
+
+    /// Read a batch of keys. Returns one result per key, in input order.
+    async fn read_many(&self, keys: &[String]) -> Vec<HubResult<Option<Vec<u8>>>> {
+        let mut out = Vec::with_capacity(keys.len());
+        for k in keys {
+            out.push(self.read(k.clone()).await);
+        }
+        out
+    }
+
+    /// Remove the key. Returns `true` if it was present.
+    async fn delete(&self, key: String) -> HubResult<bool>;
+
+    /// Check whether the key is present without returning its value.
+    async fn contains(&self, key: String) -> HubResult<bool> {
+        Ok(self.read(key).await?.is_some())
+    }
+
+    /// Stream key/value pairs inside `range` in ascending key order.
+    /// Backends that cannot enumerate their keys keep the default, which yields an error.
+    fn scan(&self, range: ScanRange) -> EntryStream {
+        let _ = range;
+        stream::once(async { Err(HubError::Unsupported("scan not supported by backend".into())) }).boxed()
+    }
+
+    /// Stream keys inside `range` in ascending order.
+    fn scan_keys(&self, range: ScanRange) -> KeyStream {
+        self.scan(range).map(|res| res.map(|(k, _)| k)).boxed()
+    }
+}
@@ -2,7 +2,7 @@ use std::sync::{Arc, atomic::{AtomicBool, Ordering}};
 use futures::future::Future;
 
 /// Lightweight runtime-agnostic cancellation token.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Default)]
 pub struct CancellationToken {
     cancelled: Arc<AtomicBool>,
 }
@@ -1,22 +1,398 @@
-use crate::backend::{MemoryBackend, HubResult};
+use crate::backend::{MemoryBackend, HubResult, EntryStream, ScanRange};
+use crate::error::HubError;
+use crate::health::{Capabilities, Health};
+use crate::record::{CasOutcome, Expected, Record, RecordMeta, WriteMeta};
+use crate::runtime;
+use crate::ttl::{deadline_millis, now_millis};
+use crate::txn::{Transaction, TxOp};
 use async_trait::async_trait;
+use futures::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
 use std::path::{Path, PathBuf};
-use std::fs::{self, File};
-use std::io::Write;
+use std::fs::{self, File, TryLockError};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::time::{Duration, Instant};
+#[cfg(feature = "detailmem_encrypt")] use crate::keyring::{self, Keyring, FORMAT_STREAM, HEADER_LEN};
+#[cfg(feature = "detailmem_encrypt")] use crate::keys::{KeyProvider, SecretKey};
+#[cfg(feature = "detailmem_encrypt")] use aes_gcm_siv::aead::{generic_array::GenericArray, rand_core::RngCore, stream::{DecryptorBE32, EncryptorBE32}, OsRng};
+#[cfg(feature = "detailmem_encrypt")] use arc_swap::ArcSwap;
+#[cfg(feature = "detailmem_encrypt")] use std::io::{BufReader, BufWriter};
+#[cfg(feature = "detailmem_encrypt")] use std::sync::Arc;
+#[cfg(feature = "detailmem_encrypt")] use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Give up waiting for a key lock after this long.
+const LOCK_TIMEOUT: Duration = Duration::from_secs(5);
+/// Longest pause between attempts to take a contended key lock.
+const LOCK_BACKOFF: Duration = Duration::from_millis(10);
+/// Keys are locked in this many stripes, one lock file each.
+const LOCK_STRIPES: u32 = 256;
+/// Plaintext bytes per chunk of an encrypted blob.
+#[cfg(feature = "detailmem_encrypt")]
+const STREAM_CHUNK: usize = 64 * 1024;
+/// STREAM nonce prefix: the 12-byte nonce minus counter and last-chunk flag.
+#[cfg(feature = "detailmem_encrypt")]
+const NONCE_PREFIX: usize = 7;
+#[cfg(feature = "detailmem_encrypt")]
+const TAG: usize = 16;
+/// Domain of the associated data binding blobs to their keys.
+#[cfg(feature = "detailmem_encrypt")]
+const AAD_DOMAIN: &str = "cognivault/detailmem/v1";
+
+/// OS advisory lock (`flock`) on a file under `<root>/.locks/`, released on
+/// drop. The files are never removed, and the OS drops the lock of a crashed
+/// process, so there are no stale locks to clean up. Key stripes are locked
+/// exclusively by writers and shared by readers; the transaction lock shared
+/// by commits and exclusively by recovery.
+struct FileLock(File);
+
+impl FileLock {
+    /// Wait for the lock without blocking the executor: contended attempts
+    /// back off on the runtime's timer.
+    async fn acquire(path: PathBuf, shared: bool) -> HubResult<Self> {
+        let file = Self::open(&path)?;
+        let started = Instant::now();
+        let mut delay = Duration::from_micros(100);
+        while !Self::try_take(&file, shared)? {
+            Self::check_timeout(&path, started)?;
+            runtime::sleep(delay).await;
+            delay = (delay * 2).min(LOCK_BACKOFF);
+        }
+        Ok(Self(file))
+    }
+
+    /// Like [`acquire`](Self::acquire), for [`DetailMem::open`], which is synchronous.
+    fn acquire_blocking(path: PathBuf, shared: bool) -> HubResult<Self> {
+        let file = Self::open(&path)?;
+        let started = Instant::now();
+        let mut delay = Duration::from_micros(100);
+        while !Self::try_take(&file, shared)? {
+            Self::check_timeout(&path, started)?;
+            std::thread::sleep(delay);
+            delay = (delay * 2).min(LOCK_BACKOFF);
+        }
+        Ok(Self(file))
+    }
+
+    fn open(path: &Path) -> HubResult<File> {
+        if let Some(parent) = path.parent() { fs::create_dir_all(parent)?; }
+        Ok(fs::OpenOptions::new().write(true).create(true).truncate(false).open(path)?)
+    }
+
+    fn try_take(file: &File, shared: bool) -> HubResult<bool> {
+        let res = if shared { file.try_lock_shared() } else { file.try_lock() };
+        match res {
+            Ok(()) => Ok(true),
+            Err(TryLockError::WouldBlock) => Ok(false),
+            Err(TryLockError::Error(e)) => Err(e.into()),
+        }
+    }
+
+    fn check_timeout(path: &Path, started: Instant) -> HubResult<()> {
+        if started.elapsed() > LOCK_TIMEOUT {
+            return Err(HubError::Timeout(format!("waiting for lock {}", path.display())));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        let _ = self.0.unlock();
+    }
+}
+
+/// Leading bytes of a `<key>.bin` file that starts with a [`Header`].
+const MAGIC: &[u8; 4] = b"CVD\x01";
+
+/// Per-object metadata: JSON in front of the blob in `<key>.bin`, after
+/// [`MAGIC`] and its big-endian `u32` length. Objects written before headers
+/// existed keep it in a `<key>.meta` sidecar.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Header {
+    /// Expiry deadline in epoch milliseconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires_at: Option<u64>,
+    /// Record envelope; absent for objects written before envelopes existed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    record: Option<RecordMeta>,
+    /// The blob was written encrypted; see [`DetailMem::open_encrypted`].
+    /// Unauthenticated, so only used to explain a failed read on a store
+    /// without keys: stores with keys go by the blob's own keyring header.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    encrypted: bool,
+}
+
+/// Keys and settings of an encrypted [`DetailMem`].
+#[cfg(feature = "detailmem_encrypt")]
+struct Crypto {
+    keyring: ArcSwap<Keyring>,
+    require_encryption: AtomicBool,
+}
+
+#[cfg(feature = "detailmem_encrypt")]
+impl Crypto {
+    /// Encrypt `value` of `key` into `out`: keyring header, nonce prefix,
+    /// then the STREAM chunks, so only one chunk is buffered at a time.
+    fn seal(&self, key: &str, value: &[u8], out: &mut File) -> HubResult<()> {
+        let ring = self.keyring.load();
+        let id = ring.active();
+        let mut prefix = [0u8; NONCE_PREFIX];
+        OsRng.fill_bytes(&mut prefix);
+        let mut enc = EncryptorBE32::from_aead(ring.cipher_for(id, key)?, GenericArray::from_slice(&prefix));
+        let aad = keyring::associated_data(AAD_DOMAIN, key);
+        let failed = |_| HubError::Crypto(format!("encryption of {key} failed"));
+        let mut w = BufWriter::new(out);
+        w.write_all(&keyring::header(FORMAT_STREAM, id))?;
+        w.write_all(&prefix)?;
+        let mut chunks = value.chunks(STREAM_CHUNK).peekable();
+        let mut buf = Vec::with_capacity(STREAM_CHUNK.min(value.len()) + TAG);
+        loop {
+            buf.clear();
+            buf.extend_from_slice(chunks.next().unwrap_or_default());
+            if chunks.peek().is_none() {
+                enc.encrypt_last_in_place(&aad, &mut buf).map_err(failed)?;
+                w.write_all(&buf)?;
+                break;
+            }
+            enc.encrypt_next_in_place(&aad, &mut buf).map_err(failed)?;
+            w.write_all(&buf)?;
+        }
+        w.flush()?;
+        Ok(())
+    }
+
+    /// Decrypt a blob written by [`seal`](Self::seal) chunk by chunk.
+    /// Altered, reordered, truncated or moved blobs fail with [`HubError::Integrity`].
+    /// Reads from the current position of `file` to its end.
+    fn unseal(&self, key: &str, mut file: File) -> HubResult<Vec<u8>> {
+        let failed = || HubError::Integrity(format!("{key} failed authentication"));
+        let len = file.metadata()?.len().saturating_sub(file.stream_position()?) as usize;
+        let mut left = len.checked_sub(HEADER_LEN + NONCE_PREFIX).ok_or_else(failed)?;
+        let mut r = BufReader::new(file);
+        let mut head = [0u8; HEADER_LEN + NONCE_PREFIX];
+        r.read_exact(&mut head)?;
+        let Some((FORMAT_STREAM, id, prefix)) = keyring::split_header(&head) else { return Err(failed()) };
+        let cipher = self.keyring.load().cipher_for(id, key)?;
+        let mut dec = DecryptorBE32::from_aead(cipher, GenericArray::from_slice(prefix));
+        let aad = keyring::associated_data(AAD_DOMAIN, key);
+        let mut out = Vec::with_capacity(left);
+        let mut buf = Vec::with_capacity(left.min(STREAM_CHUNK + TAG));
+        loop {
+            let n = left.min(STREAM_CHUNK + TAG);
+            buf.resize(n, 0);
+            r.read_exact(&mut buf)?;
+            left -= n;
+            if left == 0 {
+                dec.decrypt_last_in_place(&aad, &mut buf).map_err(|_| failed())?;
+                out.extend_from_slice(&buf);
+                return Ok(out);
+            }
+            dec.decrypt_next_in_place(&aad, &mut buf).map_err(|_| failed())?;
+            out.extend_from_slice(&buf);
+        }
+    }
+}
+
+/// Directory under the root holding staged transactions.
+const TXN_DIR: &str = ".txn";
+/// Directory under the root holding the lock files.
+const LOCK_DIR: &str = ".locks";
+/// Lock file under [`LOCK_DIR`] serializing recovery against commits.
+const TXN_LOCK: &str = "txn.lock";
+
+/// Keys become relative paths under the root. Refuse anything that could
+/// resolve outside its own directory (`..`, absolute paths, backslashes) or
+/// into the transaction staging area or lock files, so one namespace cannot
+/// reach another.
+fn check_key(key: &str) -> HubResult<()> {
+    let unsafe_key = key.is_empty()
+        || key.contains(['\\', '\0'])
+        || key.split('/').any(|seg| seg.is_empty() || seg == "." || seg == "..")
+        || key.split('/').next().is_some_and(|first| first == TXN_DIR || first == LOCK_DIR);
+    if unsafe_key {
+        return Err(HubError::InvalidInput(format!("key {key:?} is not a safe relative path")));
+    }
+    Ok(())
+}
+
+/// Entry of a staged transaction's manifest: the key and the slot of its
+/// staged `<slot>.bin` file, or no slot for a delete.
+#[derive(Debug, Serialize, Deserialize)]
+struct StagedOp {
+    key: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    slot: Option<usize>,
+}
 
 /// File-system based backend for storing large objects & vectors on demand.
 /// Each key maps to a file `<root>/<key>.bin`. Integrity can be checked by
 /// computing SHA-256 over content; Merkle-log/PAR2 snapshot reserved for future.
+/// Record metadata and TTL deadlines live in a header at the start of that
+/// file, so data and expiry are replaced by a single rename. Writers
+/// of a key serialize on an advisory lock on one of the stripe files in
+/// `<root>/.locks/`, which makes conditional writes safe across processes
+/// sharing the directory. Keys must be relative paths
+/// without `.` or `..` segments, so each tenant namespace stays in its own
+/// `__ns/<tenant>/` directory.
+///
+/// Transactions are staged under `<root>/.txn/<id>/` and committed by
+/// renaming that directory to `<id>.commit`; committed transactions found by
+/// [`open`](Self::open) are re-applied, abandoned stages are removed. `open`
+/// waits for commits running in other handles before it looks.
+///
+/// With feature `detailmem_encrypt`, [`open_encrypted`](Self::open_encrypted)
+/// encrypts blobs at rest with the [`Keyring`] configuration `LongMem` uses:
+/// STREAM over AES-256-GCM-SIV in 64 KiB chunks with a random nonce prefix
+/// per file, the keyring header in front, per-tenant keys for namespaces and
+/// the key name bound in as associated data. Headers stay plaintext.
 #[derive(Clone)]
 pub struct DetailMem {
     root: PathBuf,
+    #[cfg(feature = "detailmem_encrypt")]
+    crypto: Option<Arc<Crypto>>,
 }
 
 impl DetailMem {
     pub fn open<P: AsRef<Path>>(root: P) -> HubResult<Self> {
         let root = root.as_ref().to_path_buf();
         fs::create_dir_all(&root)?;
-        Ok(Self { root })
+        let this = Self {
+            root,
+            #[cfg(feature = "detailmem_encrypt")]
+            crypto: None,
+        };
+        this.recover()?;
+        Ok(this)
+    }
+
+    /// Open the store encrypting new blobs with the active key of `keyring`.
+    /// Blobs written unencrypted before are refused until
+    /// [`set_require_encryption`](Self::set_require_encryption)`(false)`,
+    /// and are encrypted when next written.
+    #[cfg(feature = "detailmem_encrypt")]
+    pub fn open_encrypted<P: AsRef<Path>>(root: P, keyring: Keyring) -> HubResult<Self> {
+        let mut this = Self::open(root)?;
+        this.crypto = Some(Arc::new(Crypto { keyring: ArcSwap::from_pointee(keyring), require_encryption: AtomicBool::new(true) }));
+        Ok(this)
+    }
+
+    /// Open the store encrypted with the keys `provider` holds for `scope`.
+    #[cfg(feature = "detailmem_encrypt")]
+    pub fn open_with_provider<P: AsRef<Path>>(root: P, provider: &dyn KeyProvider, scope: &str) -> HubResult<Self> {
+        Self::open_encrypted(root, Keyring::from_provider(provider, scope)?)
+    }
+
+    /// Snapshot of the keyring, if the store encrypts.
+    #[cfg(feature = "detailmem_encrypt")]
+    pub fn keyring(&self) -> Option<Arc<Keyring>> {
+        self.crypto.as_ref().map(|c| c.keyring.load_full())
+    }
+
+    /// Add `key` under `id` and encrypt new blobs with it; see `LongMem::rotate_key`.
+    #[cfg(feature = "detailmem_encrypt")]
+    pub fn rotate_key(&self, id: u32, key: impl Into<SecretKey>) -> HubResult<()> {
+        let crypto = self.crypto.as_ref().ok_or_else(|| HubError::Unsupported("store is not encrypted".into()))?;
+        let mut ring = Keyring::clone(&crypto.keyring.load());
+        ring.add(id, key)?;
+        ring.set_active(id)?;
+        crypto.keyring.store(Arc::new(ring));
+        Ok(())
+    }
+
+    /// Refuse unencrypted blobs with [`HubError::Integrity`], so plaintext
+    /// cannot be swapped in for an encrypted blob. On by default; turn it
+    /// off while blobs from before encryption remain. No effect unless the
+    /// store encrypts.
+    #[cfg(feature = "detailmem_encrypt")]
+    pub fn set_require_encryption(&self, required: bool) {
+        if let Some(crypto) = &self.crypto {
+            crypto.require_encryption.store(required, Ordering::Relaxed);
+        }
+    }
+
+    /// Move the value stored under `old`, a key from before keys had to be
+    /// safe relative paths, to `new`, keeping its metadata and expiry.
+    /// Returns whether `old` held a value. Keys that resolved outside the
+    /// root (`..` segments, absolute paths) are refused: move those files by
+    /// hand.
+    pub async fn migrate_key(&self, old: &str, new: &str) -> HubResult<bool> {
+        check_key(new)?;
+        if old.is_empty() || old.starts_with('/') || old.contains('\0') || old.split('/').any(|seg| seg == "..") {
+            return Err(HubError::InvalidInput(format!("key {old:?} does not resolve inside the store")));
+        }
+        let _locks = self.lock_all(&[old, new]).await?;
+        let Some((header, file)) = self.open_record(old)? else { return Ok(false) };
+        let record = self.decode_record(old, header, file)?;
+        self.store_locked(new, &record.value, record.meta)?;
+        // `a//b` and `a/b` name the same file
+        if fs::canonicalize(self.file_path(old))? != fs::canonicalize(self.file_path(new))? {
+            self.remove_locked(old)?;
+        }
+        Ok(true)
+    }
+
+    fn encrypts(&self) -> bool {
+        #[cfg(feature = "detailmem_encrypt")]
+        { self.crypto.is_some() }
+        #[cfg(not(feature = "detailmem_encrypt"))]
+        { false }
+    }
+
+    /// Redo committed transactions and drop stages abandoned by a crash.
+    /// Holds the transaction lock exclusively, so every stage found belongs
+    /// to a crashed commit rather than one another handle is still running,
+    /// and the key locks of a transaction while redoing it.
+    fn recover(&self) -> HubResult<()> {
+        let txn_dir = self.root.join(TXN_DIR);
+        if !txn_dir.exists() { return Ok(()); }
+        let _txn = FileLock::acquire_blocking(self.root.join(LOCK_DIR).join(TXN_LOCK), false)?;
+        for entry in fs::read_dir(&txn_dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "commit") {
+                let manifest = Self::read_manifest(&path)?;
+                let stripes: BTreeSet<u32> = manifest.iter().map(|op| Self::stripe(&op.key)).collect();
+                let _locks = stripes.into_iter()
+                    .map(|stripe| FileLock::acquire_blocking(self.stripe_path(stripe), false))
+                    .collect::<HubResult<Vec<_>>>()?;
+                self.apply_staged(&path, manifest)?;
+                continue;
+            }
+            fs::remove_dir_all(&path)?;
+        }
+        Ok(())
+    }
+
+    fn read_manifest(dir: &Path) -> HubResult<Vec<StagedOp>> {
+        Ok(serde_json::from_slice(&fs::read(dir.join("manifest.json"))?)?)
+    }
+
+    /// Move staged files of a committed transaction into place, then drop
+    /// the stage. Idempotent: slots already moved are skipped. Caller holds
+    /// the locks of the manifest's keys.
+    fn apply_staged(&self, dir: &Path, manifest: Vec<StagedOp>) -> HubResult<()> {
+        for op in manifest {
+            match op.slot {
+                Some(slot) => {
+                    let staged = dir.join(format!("{slot}.bin"));
+                    if staged.exists() {
+                        let target = self.file_path(&op.key);
+                        if let Some(parent) = target.parent() { fs::create_dir_all(parent)?; }
+                        fs::rename(staged, target)?;
+                    }
+                    // the header replaces any sidecar; stages from before headers carry their own
+                    let sidecar = dir.join(format!("{slot}.meta"));
+                    if sidecar.exists() {
+                        fs::rename(sidecar, self.meta_path(&op.key))?;
+                    } else {
+                        Self::remove_if_exists(&self.meta_path(&op.key))?;
+                    }
+                }
+                None => { self.remove_locked(&op.key)?; }
+            }
+        }
+        fs::remove_dir_all(dir)?;
+        Ok(())
     }
 
     fn file_path(&self, key: &str) -> PathBuf {
@@ -24,22 +400,219 @@ impl DetailMem {
         path.push(format!("{}.bin", key));
         path
     }
-}
 
-#[async_trait]
-impl MemoryBackend for DetailMem {
-    async fn write(&self, key: String, value: Vec<u8>) -> HubResult<()> {
-        let path = self.file_path(&key);
+    fn meta_path(&self, key: &str) -> PathBuf {
+        let mut path = self.root.clone();
+        path.push(format!("{}.meta", key));
+        path
+    }
+
+    /// Stripe of `key`; the same in every process sharing the directory.
+    fn stripe(key: &str) -> u32 {
+        crc32fast::hash(key.as_bytes()) % LOCK_STRIPES
+    }
+
+    fn stripe_path(&self, stripe: u32) -> PathBuf {
+        self.root.join(LOCK_DIR).join(format!("{stripe}.lock"))
+    }
+
+    async fn lock_stripe(&self, stripe: u32, shared: bool) -> HubResult<FileLock> {
+        FileLock::acquire(self.stripe_path(stripe), shared).await
+    }
+
+    async fn lock(&self, key: &str) -> HubResult<FileLock> {
+        self.lock_stripe(Self::stripe(key), false).await
+    }
+
+    /// Shared lock for reading `key`: no writer, transaction commit or
+    /// recovery changes its files meanwhile.
+    async fn read_lock(&self, key: &str) -> HubResult<FileLock> {
+        self.lock_stripe(Self::stripe(key), true).await
+    }
+
+    /// Lock the stripes of all `keys`, each once and in ascending order so
+    /// concurrent callers cannot deadlock.
+    async fn lock_all(&self, keys: &[&str]) -> HubResult<Vec<FileLock>> {
+        let stripes: BTreeSet<u32> = keys.iter().map(|k| Self::stripe(k)).collect();
+        let mut locks = Vec::with_capacity(stripes.len());
+        for stripe in stripes {
+            locks.push(self.lock_stripe(stripe, false).await?);
+        }
+        Ok(locks)
+    }
+
+    /// Recursively collect keys of all files under `dir` ending in `suffix`.
+    fn walk_keys(&self, dir: &Path, suffix: &str, out: &mut Vec<String>) -> HubResult<()> {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                if path == self.root.join(TXN_DIR) || path == self.root.join(LOCK_DIR) { continue; }
+                self.walk_keys(&path, suffix, out)?;
+                continue;
+            }
+            // skip temp files, merkle log and other non-object files
+            let Ok(rel) = path.strip_prefix(&self.root) else { continue };
+            let Some(rel) = rel.to_str() else { continue };
+            if let Some(key) = rel.strip_suffix(suffix) {
+                out.push(key.replace(std::path::MAIN_SEPARATOR, "/"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Sidecar of an object written before headers existed.
+    fn read_sidecar(&self, key: &str) -> HubResult<Option<Header>> {
+        match fs::read(self.meta_path(key)) {
+            Ok(buf) => Ok(Some(serde_json::from_slice(&buf)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Header of `file`, leaving it positioned at the blob. `None` for a
+    /// file without [`MAGIC`], i.e. a bare blob from before headers existed.
+    fn read_header(file: &mut File) -> HubResult<Option<Header>> {
+        let mut head = [0u8; 8];
+        match file.read_exact(&mut head) {
+            Ok(()) if &head[..4] == MAGIC => {}
+            Ok(()) => return Ok(None),
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+        let len = u32::from_be_bytes([head[4], head[5], head[6], head[7]]) as usize;
+        let mut buf = vec![0u8; len];
+        file.read_exact(&mut buf).map_err(|_| HubError::Corruption("truncated record header".into()))?;
+        Ok(Some(serde_json::from_slice(&buf)?))
+    }
+
+    /// Header and blob file of `key`, the file positioned at the blob.
+    /// Opening the file once makes both belong to the same version of it.
+    fn open_record(&self, key: &str) -> HubResult<Option<(Header, File)>> {
+        let mut file = match File::open(self.file_path(key)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        if let Some(header) = Self::read_header(&mut file)? {
+            return Ok(Some((header, file)));
+        }
+        file.seek(SeekFrom::Start(0))?;
+        Ok(Some((self.read_sidecar(key)?.unwrap_or_default(), file)))
+    }
+
+    fn is_expired(&self, key: &str) -> HubResult<bool> {
+        Ok(self.open_record(key)?.and_then(|(h, _)| h.expires_at).is_some_and(|at| at <= now_millis()))
+    }
+
+    /// Record metadata of `key` regardless of expiry, to continue its history.
+    fn previous(&self, key: &str) -> HubResult<Option<RecordMeta>> {
+        Ok(self.open_record(key)?.and_then(|(h, _)| h.record))
+    }
+
+    /// Write atomically: write to tmp then rename
+    fn write_atomic(path: &Path, data: &[u8]) -> HubResult<()> {
+        Self::write_atomic_with(path, |f| Ok(f.write_all(data)?))
+    }
+
+    fn write_atomic_with(path: &Path, fill: impl FnOnce(&mut File) -> HubResult<()>) -> HubResult<()> {
         // ensure parent dir exists (race-free since create_dir_all ok on exist)
         if let Some(parent) = path.parent() { fs::create_dir_all(parent)?; }
-        // Write atomically: write to tmp then rename
-        let tmp_path = path.with_extension("bin.tmp");
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
         {
             let mut f = File::create(&tmp_path)?;
-            f.write_all(&value)?;
+            fill(&mut f)?;
             f.sync_all()?;
         }
-        fs::rename(&tmp_path, &path)?;
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    /// Write header and blob of `key` to `path` in one atomic step, the blob
+    /// encrypted if the store encrypts.
+    fn write_record(&self, path: &Path, key: &str, record: RecordMeta, value: &[u8]) -> HubResult<()> {
+        let header = Header { expires_at: record.expires_at, record: Some(record), encrypted: self.encrypts() };
+        let header = serde_json::to_vec(&header)?;
+        let len = u32::try_from(header.len()).map_err(|_| HubError::InvalidInput(format!("metadata of {key} too large")))?;
+        Self::write_atomic_with(path, |f| {
+            f.write_all(MAGIC)?;
+            f.write_all(&len.to_be_bytes())?;
+            f.write_all(&header)?;
+            #[cfg(feature = "detailmem_encrypt")]
+            if let Some(crypto) = &self.crypto {
+                return crypto.seal(key, value, f);
+            }
+            Ok(f.write_all(value)?)
+        })
+    }
+
+    /// Plaintext of the blob `file` is positioned at. Stores with keys tell
+    /// encrypted blobs by their keyring header; `encrypted` is the header's
+    /// unauthenticated flag, only used to explain a read without keys.
+    fn read_blob(&self, key: &str, mut file: File, encrypted: bool) -> HubResult<Vec<u8>> {
+        #[cfg(feature = "detailmem_encrypt")]
+        if let Some(crypto) = &self.crypto {
+            let start = file.stream_position()?;
+            let mut head = Vec::with_capacity(HEADER_LEN);
+            (&mut file).take(HEADER_LEN as u64).read_to_end(&mut head)?;
+            file.seek(SeekFrom::Start(start))?;
+            if matches!(keyring::split_header(&head), Some((FORMAT_STREAM, _, _))) {
+                return crypto.unseal(key, file);
+            }
+            if crypto.require_encryption.load(Ordering::Relaxed) {
+                return Err(HubError::Integrity(format!("{key} is stored unencrypted")));
+            }
+        }
+        if encrypted {
+            return Err(HubError::Crypto(format!("{key} is encrypted but the store has no keys")));
+        }
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf)?;
+        Ok(buf)
+    }
+
+    fn remove_if_exists(path: &Path) -> HubResult<bool> {
+        match fs::remove_file(path) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Live record of `key`, or `None` if absent or expired. Caller holds
+    /// the key's lock, shared or exclusive.
+    fn load(&self, key: &str) -> HubResult<Option<Record>> {
+        check_key(key)?;
+        let Some((header, file)) = self.open_record(key)? else { return Ok(None) };
+        if header.expires_at.is_some_and(|at| at <= now_millis()) { return Ok(None); }
+        self.decode_record(key, header, file).map(Some)
+    }
+
+    /// Record of `key` from its header and the blob `file` is positioned at,
+    /// failing with [`HubError::Corruption`] if the blob does not match its
+    /// checksum.
+    fn decode_record(&self, key: &str, header: Header, file: File) -> HubResult<Record> {
+        let value = self.read_blob(key, file, header.encrypted)?;
+        let mut record = match header.record {
+            Some(meta) => Record { value, meta }.checked(key)?,
+            None => Record::legacy(value),
+        };
+        record.meta.expires_at = header.expires_at;
+        Ok(record)
+    }
+
+    /// Store the record under the key's lock.
+    async fn store(&self, key: &str, value: &[u8], meta: &WriteMeta) -> HubResult<()> {
+        check_key(key)?;
+        let _lock = self.lock(key).await?;
+        self.store_locked(key, value, RecordMeta::next(self.previous(key)?.as_ref(), value, meta))
+    }
+
+    /// Store header and blob, then drop a sidecar left from before headers
+    /// existed. Caller holds the key's lock.
+    fn store_locked(&self, key: &str, value: &[u8], record: RecordMeta) -> HubResult<()> {
+        self.write_record(&self.file_path(key), key, record, value)?;
+        Self::remove_if_exists(&self.meta_path(key))?;
 
         // compute checksum for Merkle root (placeholder)
         #[cfg(feature="merkle_log")]
@@ -48,18 +621,189 @@ impl MemoryBackend for DetailMem {
             let mut log = MerkleLog::open(self.root.join("merkle.log"))?;
             use sha2::{Sha256, Digest};
             let mut hasher = Sha256::new();
-            hasher.update(&value);
+            // of encrypted blobs the ciphertext, so the log reveals nothing about values
+            if self.encrypts() {
+                hasher.update(fs::read(self.file_path(key))?);
+            } else {
+                hasher.update(value);
+            }
             log.append(hasher.finalize().into())?;
         }
         Ok(())
     }
 
+    /// Remove the blob and any sidecar. Returns whether the blob existed.
+    async fn remove(&self, key: &str) -> HubResult<bool> {
+        check_key(key)?;
+        let _lock = self.lock(key).await?;
+        self.remove_locked(key)
+    }
+
+    fn remove_locked(&self, key: &str) -> HubResult<bool> {
+        let existed = Self::remove_if_exists(&self.file_path(key))?;
+        Self::remove_if_exists(&self.meta_path(key))?;
+        Ok(existed)
+    }
+}
+
+#[async_trait]
+impl MemoryBackend for DetailMem {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            persistent: true,
+            scan: true,
+            ttl: true,
+            compare_and_swap: true,
+            transactions: true,
+            metadata: true,
+            encrypted: self.encrypts(),
+        }
+    }
+
+    async fn health(&self) -> Health {
+        // the root must accept writes, e.g. not be read-only or full
+        let probe = self.root.join(format!(".health-{}", std::process::id()));
+        match fs::write(&probe, b"ok").and_then(|_| fs::remove_file(&probe)) {
+            Ok(()) => Health::Healthy,
+            Err(e) => Health::Unhealthy(e.to_string()),
+        }
+    }
+
+    async fn write(&self, key: String, value: Vec<u8>) -> HubResult<()> {
+        self.store(&key, &value, &WriteMeta::default()).await
+    }
+
+    async fn write_with_ttl(&self, key: String, value: Vec<u8>, ttl: Duration) -> HubResult<()> {
+        self.store(&key, &value, &WriteMeta { expires_at: Some(deadline_millis(ttl)), ..Default::default() }).await
+    }
+
+    async fn write_with_meta(&self, key: String, value: Vec<u8>, meta: WriteMeta) -> HubResult<()> {
+        self.store(&key, &value, &meta).await
+    }
+
+    async fn write_many_with_meta(&self, items: &[(String, Vec<u8>)], meta: WriteMeta) -> Vec<HubResult<()>> {
+        let mut out = Vec::with_capacity(items.len());
+        for (k, v) in items {
+            out.push(self.store(k, v, &meta).await);
+        }
+        out
+    }
+
+    async fn sweep_expired(&self) -> HubResult<usize> {
+        let mut keys = Vec::new();
+        self.walk_keys(&self.root, ".bin", &mut keys)?;
+        let mut removed = 0;
+        for key in keys {
+            if !self.is_expired(&key)? { continue; }
+            // re-check under the lock: the key may have been rewritten meanwhile
+            let _lock = self.lock(&key).await?;
+            if self.is_expired(&key)? && self.remove_locked(&key)? {
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
     async fn read(&self, key: String) -> HubResult<Option<Vec<u8>>> {
-        let path = self.file_path(&key);
-        match fs::read(path) {
-            Ok(buf) => Ok(Some(buf)),
-            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
-            Err(e) => Err(e.into()),
+        Ok(self.read_record(key).await?.map(|r| r.value))
+    }
+
+    async fn read_record(&self, key: String) -> HubResult<Option<Record>> {
+        check_key(&key)?;
+        let _lock = self.read_lock(&key).await?;
+        self.load(&key)
+    }
+
+    async fn commit(&self, tx: Transaction) -> HubResult<()> {
+        tx.keys().into_iter().try_for_each(check_key)?;
+        // keeps recovery by another handle away from this commit's stage
+        let _txn = FileLock::acquire(self.root.join(LOCK_DIR).join(TXN_LOCK), true).await?;
+        let _locks = self.lock_all(&tx.keys()).await?;
+        let meta = WriteMeta { version: tx.version(), ..Default::default() };
+        // only the last operation per key matters
+        let mut last: BTreeMap<String, Option<Vec<u8>>> = BTreeMap::new();
+        for op in tx.into_ops() {
+            match op {
+                TxOp::Put { key, value } => last.insert(key, Some(value)),
+                TxOp::Delete { key } => last.insert(key, None),
+            };
+        }
+
+        static NEXT_ID: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+        let id = format!("{}-{}-{}", std::process::id(), now_millis(), NEXT_ID.fetch_add(1, std::sync::atomic::Ordering::Relaxed));
+        let stage = self.root.join(TXN_DIR).join(&id);
+        fs::create_dir_all(&stage)?;
+        let mut manifest = Vec::with_capacity(last.len());
+        for (slot, (key, value)) in last.iter().enumerate() {
+            let Some(value) = value else {
+                manifest.push(StagedOp { key: key.clone(), slot: None });
+                continue;
+            };
+            let record = RecordMeta::next(self.previous(key)?.as_ref(), value, &meta);
+            self.write_record(&stage.join(format!("{slot}.bin")), key, record, value)?;
+            manifest.push(StagedOp { key: key.clone(), slot: Some(slot) });
+        }
+        Self::write_atomic(&stage.join("manifest.json"), &serde_json::to_vec(&manifest)?)?;
+
+        // commit point: the stage becomes visible to recovery only as a whole
+        let committed = self.root.join(TXN_DIR).join(format!("{id}.commit"));
+        fs::rename(&stage, &committed)?;
+        self.apply_staged(&committed, manifest)
+    }
+
+    async fn compare_and_swap(&self, key: String, expected: Expected, new: Vec<u8>) -> HubResult<CasOutcome> {
+        self.compare_and_swap_with_meta(key, expected, new, WriteMeta::default()).await
+    }
+
+    async fn compare_and_swap_with_meta(&self, key: String, expected: Expected, new: Vec<u8>, meta: WriteMeta) -> HubResult<CasOutcome> {
+        check_key(&key)?;
+        let _lock = self.lock(&key).await?;
+        let current = self.load(&key)?;
+        if !expected.matches(current.as_ref()) {
+            return Ok(CasOutcome::Conflict { current });
         }
+        let record = RecordMeta::next(current.as_ref().map(|r| &r.meta), &new, &meta);
+        let version = record.version;
+        self.store_locked(&key, &new, record)?;
+        Ok(CasOutcome::Swapped { version })
     }
-} 
\ No newline at end of file
+
+    async fn delete(&self, key: String) -> HubResult<bool> {
+        check_key(&key)?;
+        let expired = self.is_expired(&key)?;
+        Ok(self.remove(&key).await? && !expired)
+    }
+
+    async fn contains(&self, key: String) -> HubResult<bool> {
+        check_key(&key)?;
+        let _lock = self.read_lock(&key).await?;
+        Ok(self.open_record(&key)?.is_some_and(|(h, _)| h.expires_at.is_none_or(|at| at > now_millis())))
+    }
+
+    fn scan(&self, range: ScanRange) -> EntryStream {
+        let mut keys = Vec::new();
+        if let Err(e) = self.walk_keys(&self.root, ".bin", &mut keys) {
+            return stream::once(async move { Err(e) }).boxed();
+        }
+        keys.retain(|k| range.contains(k));
+        keys.sort();
+        // values are loaded lazily while the stream is consumed
+        let this = self.clone();
+        stream::iter(keys).filter_map(move |key| {
+            let this = this.clone();
+            async move {
+                let _lock = match this.read_lock(&key).await {
+                    Ok(lock) => lock,
+                    Err(e) => return Some(Err(e)),
+                };
+                match this.open_record(&key) {
+                    Ok(Some((header, _))) if header.expires_at.is_some_and(|at| at <= now_millis()) => None,
+                    Ok(Some((header, file))) => Some(this.decode_record(&key, header, file).map(|record| (key, record.value))),
+                    // removed between walk and read
+                    Ok(None) => None,
+                    Err(e) => Some(Err(e)),
+                }
+            }
+        }).boxed()
+    }
+}
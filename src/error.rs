@@ -0,0 +1,140 @@
+//! Error type shared by the hub and all backends.
+use crate::quota::QuotaExceeded;
+use std::error::Error;
+use std::io;
+
+/// Everything a hub or backend operation can fail with.
+///
+/// Missing keys are not errors: reads return `Ok(None)`. Use
+/// [`is_retryable`](Self::is_retryable) to decide whether repeating the call
+/// may help.
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum HubError {
+    /// Failure reported by one named backend of a fan-out.
+    #[error("backend {name}: {source}")]
+    Backend {
+        name: String,
+        #[source]
+        source: Box<HubError>,
+    },
+    /// Several backends of a fan-out failed; one [`HubError::Backend`] each.
+    #[error("{} backends failed: {}", .0.len(), join(.0))]
+    Multiple(Vec<HubError>),
+    /// Encryption failed, or the key needed for decryption is missing.
+    #[error("crypto error: {0}")]
+    Crypto(String),
+    #[error("i/o error: {0}")]
+    Io(#[from] io::Error),
+    /// Stored data is damaged, e.g. fails its checksum.
+    #[error("corrupt data: {0}")]
+    Corruption(String),
+    /// An encrypted value failed authentication: it was altered, moved from
+    /// another key or namespace, or encrypted with different key material.
+    #[error("integrity check failed: {0}")]
+    Integrity(String),
+    /// The policy engine refused the operation.
+    #[error("{action} denied: {reason}")]
+    PolicyDenied { action: String, reason: String },
+    #[error(transparent)]
+    QuotaExceeded(#[from] QuotaExceeded),
+    /// A bounded cache did not keep the value: admission was refused, or
+    /// the value was evicted by its own write.
+    #[error("rejected: {0}")]
+    Rejected(String),
+    #[error("operation cancelled")]
+    Cancelled,
+    #[error("timed out: {0}")]
+    Timeout(String),
+    /// Not enough usable backends, or a write concern or read quorum not met.
+    #[error("unavailable: {0}")]
+    Unavailable(String),
+    /// Concurrent modification or disagreeing replicas.
+    #[error("conflict: {0}")]
+    Conflict(String),
+    /// The backend, or every routed backend, lacks the needed capability.
+    #[error("unsupported: {0}")]
+    Unsupported(String),
+    /// A named backend, rule or other referenced item does not exist.
+    #[error("not found: {0}")]
+    NotFound(String),
+    /// The request itself is malformed: bad key, name or configuration.
+    #[error("invalid input: {0}")]
+    InvalidInput(String),
+    /// Error from third-party backend code that fits no other variant.
+    #[error(transparent)]
+    Other(Box<dyn Error + Send + Sync + 'static>),
+}
+
+impl HubError {
+    /// Wrap an arbitrary error, e.g. from a plugin backend.
+    pub fn other(err: impl Into<Box<dyn Error + Send + Sync + 'static>>) -> Self {
+        HubError::Other(err.into())
+    }
+
+    /// Attribute `self` to the backend registered as `name`.
+    pub fn in_backend(self, name: impl Into<String>) -> Self {
+        HubError::Backend { name: name.into(), source: Box::new(self) }
+    }
+
+    /// One error for the failures of a fan-out: the error itself if there is
+    /// exactly one, [`HubError::Multiple`] otherwise. `None` if `errors` is empty.
+    pub fn aggregate(mut errors: Vec<HubError>) -> Option<Self> {
+        match errors.len() {
+            0 => None,
+            1 => errors.pop(),
+            _ => Some(HubError::Multiple(errors)),
+        }
+    }
+
+    /// Whether repeating the operation unchanged may succeed.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            HubError::Backend { source, .. } => source.is_retryable(),
+            HubError::Multiple(errors) => errors.iter().all(HubError::is_retryable),
+            HubError::Io(e) => matches!(
+                e.kind(),
+                io::ErrorKind::Interrupted
+                    | io::ErrorKind::WouldBlock
+                    | io::ErrorKind::TimedOut
+                    | io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+            ),
+            HubError::Timeout(_) | HubError::Unavailable(_) | HubError::Conflict(_) => true,
+            _ => false,
+        }
+    }
+
+    /// The innermost error, past backend attribution.
+    pub fn root(&self) -> &HubError {
+        match self {
+            HubError::Backend { source, .. } => source.root(),
+            other => other,
+        }
+    }
+}
+
+fn join(errors: &[HubError]) -> String {
+    errors.iter().map(ToString::to_string).collect::<Vec<_>>().join("; ")
+}
+
+impl From<serde_json::Error> for HubError {
+    fn from(e: serde_json::Error) -> Self {
+        if e.is_io() {
+            return HubError::Io(e.into());
+        }
+        HubError::Corruption(e.to_string())
+    }
+}
+
+#[cfg(feature = "longmem_sled")]
+impl From<sled::Error> for HubError {
+    fn from(e: sled::Error) -> Self {
+        match e {
+            sled::Error::Io(e) => HubError::Io(e),
+            sled::Error::Corruption { .. } => HubError::Corruption(e.to_string()),
+            sled::Error::Unsupported(msg) => HubError::Unsupported(msg),
+            other => HubError::other(other),
+        }
+    }
+}
@@ -0,0 +1,244 @@
+//! Eviction bookkeeping for capacity-bounded [`ShortMem`](crate::ShortMem).
+//!
+//! The tracker only holds key metadata (size, recency, frequency); values stay
+//! in the backend's map. Every mutation returns the keys that have to be
+//! dropped to get back under the byte and entry budget.
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+/// Which entries a bounded cache gives up first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EvictionPolicy {
+    /// Least recently used.
+    #[default]
+    Lru,
+    /// Least frequently used; ties broken by recency.
+    Lfu,
+    /// Window TinyLFU: new keys enter a small LRU window and are only admitted
+    /// to the main segment if their estimated frequency beats the main victim.
+    TinyLfu,
+}
+
+/// Callback receiving entries evicted from a bounded cache, e.g. to demote
+/// them to a slower tier.
+pub type EvictionListener = Arc<dyn Fn(String, Vec<u8>) + Send + Sync>;
+
+/// Hit/miss/eviction counters and current occupancy.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Entries dropped to make room; rejected writes not included.
+    pub evictions: u64,
+    /// Writes the cache did not keep; see [`HubError::Rejected`](crate::HubError::Rejected).
+    pub rejected: u64,
+    pub entries: usize,
+    pub bytes: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Meta {
+    size: usize,
+    freq: u64,
+    rank: (u64, u64),
+    in_window: bool,
+}
+
+pub(crate) struct Tracker {
+    policy: EvictionPolicy,
+    max_bytes: usize,
+    max_entries: usize,
+    tick: u64,
+    bytes: usize,
+    entries: HashMap<String, Meta>,
+    main: BTreeMap<(u64, u64), String>,
+    window: BTreeMap<(u64, u64), String>,
+    sketch: Option<CountMinSketch>,
+}
+
+impl Tracker {
+    pub(crate) fn new(policy: EvictionPolicy, max_bytes: usize, max_entries: usize) -> Self {
+        let sketch = (policy == EvictionPolicy::TinyLfu).then(|| CountMinSketch::new(max_entries));
+        Self {
+            policy,
+            max_bytes,
+            max_entries,
+            tick: 0,
+            bytes: 0,
+            entries: HashMap::new(),
+            main: BTreeMap::new(),
+            window: BTreeMap::new(),
+            sketch,
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize { self.entries.len() }
+
+    pub(crate) fn bytes(&self) -> usize { self.bytes }
+
+    /// Record an insert or overwrite of `size` bytes. Returns keys to evict,
+    /// which may include `key` itself if it was not admitted.
+    pub(crate) fn insert(&mut self, key: &str, size: usize) -> Vec<String> {
+        if let Some(sketch) = self.sketch.as_mut() { sketch.increment(key); }
+        let old = self.detach(key);
+        let freq = old.map_or(1, |m| m.freq + 1);
+        // new keys start in the window; known keys keep their segment
+        let in_window = self.policy == EvictionPolicy::TinyLfu && old.is_none_or(|m| m.in_window);
+        self.attach(key.to_string(), Meta { size, freq, rank: (0, 0), in_window });
+        self.evict()
+    }
+
+    /// Record a read hit.
+    pub(crate) fn touch(&mut self, key: &str) {
+        if let Some(sketch) = self.sketch.as_mut() { sketch.increment(key); }
+        if let Some(mut meta) = self.detach(key) {
+            meta.freq += 1;
+            self.attach(key.to_string(), meta);
+        }
+    }
+
+    /// Record a miss; TinyLFU still counts the key so it can earn admission.
+    pub(crate) fn miss(&mut self, key: &str) {
+        if let Some(sketch) = self.sketch.as_mut() { sketch.increment(key); }
+    }
+
+    pub(crate) fn remove(&mut self, key: &str) {
+        self.detach(key);
+    }
+
+    fn rank(&self, freq: u64) -> (u64, u64) {
+        match self.policy {
+            EvictionPolicy::Lfu => (freq, self.tick),
+            _ => (self.tick, 0),
+        }
+    }
+
+    fn attach(&mut self, key: String, mut meta: Meta) {
+        // a fresh tick per attach keeps ranks unique within a segment
+        self.tick += 1;
+        meta.rank = self.rank(meta.freq);
+        self.bytes += meta.size;
+        if meta.in_window {
+            self.window.insert(meta.rank, key.clone());
+        } else {
+            self.main.insert(meta.rank, key.clone());
+        }
+        self.entries.insert(key, meta);
+    }
+
+    fn detach(&mut self, key: &str) -> Option<Meta> {
+        let meta = self.entries.remove(key)?;
+        self.bytes -= meta.size;
+        if meta.in_window {
+            self.window.remove(&meta.rank);
+        } else {
+            self.main.remove(&meta.rank);
+        }
+        Some(meta)
+    }
+
+    fn over(&self) -> bool {
+        self.entries.len() > self.max_entries || self.bytes > self.max_bytes
+    }
+
+    fn window_cap(&self) -> usize {
+        (self.max_entries / 100).max(1)
+    }
+
+    fn estimate(&self, key: &str) -> u8 {
+        self.sketch.as_ref().map_or(0, |s| s.estimate(key))
+    }
+
+    fn evict(&mut self) -> Vec<String> {
+        let mut victims = Vec::new();
+        if self.policy == EvictionPolicy::TinyLfu {
+            while self.window.len() > self.window_cap() {
+                let Some((_, candidate)) = self.window.pop_first() else { break };
+                let mut meta = self.entries.remove(&candidate).expect("window entry has metadata");
+                self.bytes -= meta.size;
+                meta.in_window = false;
+                // candidate competes with the main segment's LRU victim for a slot
+                let mut admitted = true;
+                while self.over_with(meta.size) {
+                    let Some((_, victim)) = self.main.first_key_value() else { admitted = false; break };
+                    if self.estimate(&candidate) > self.estimate(victim) {
+                        let victim = victim.clone();
+                        self.detach(&victim);
+                        victims.push(victim);
+                    } else {
+                        admitted = false;
+                        break;
+                    }
+                }
+                if admitted {
+                    self.attach(candidate, meta);
+                } else {
+                    victims.push(candidate);
+                }
+            }
+        }
+        // plain policies, or a single oversized entry still breaking the budget
+        while self.over() {
+            let next = self.main.first_key_value().or_else(|| self.window.first_key_value());
+            let Some((_, victim)) = next else { break };
+            let victim = victim.clone();
+            self.detach(&victim);
+            victims.push(victim);
+        }
+        victims
+    }
+
+    /// Whether adding one more entry of `size` bytes breaks the budget.
+    fn over_with(&self, size: usize) -> bool {
+        self.entries.len() + 1 > self.max_entries || self.bytes + size > self.max_bytes
+    }
+}
+
+/// 4-row count-min sketch with 4-bit style saturating counters and periodic
+/// halving, so old popularity fades out.
+struct CountMinSketch {
+    rows: [Vec<u8>; 4],
+    mask: usize,
+    additions: usize,
+    reset_at: usize,
+}
+
+impl CountMinSketch {
+    fn new(capacity: usize) -> Self {
+        let width = (capacity.clamp(16, 1 << 20) * 4).next_power_of_two();
+        Self {
+            rows: std::array::from_fn(|_| vec![0; width]),
+            mask: width - 1,
+            additions: 0,
+            reset_at: width * 10,
+        }
+    }
+
+    fn slot(&self, row: usize, key: &str) -> usize {
+        let mut h = DefaultHasher::new();
+        (row as u64).hash(&mut h);
+        key.hash(&mut h);
+        h.finish() as usize & self.mask
+    }
+
+    fn increment(&mut self, key: &str) {
+        for row in 0..self.rows.len() {
+            let i = self.slot(row, key);
+            let c = &mut self.rows[row][i];
+            if *c < 15 { *c += 1; }
+        }
+        self.additions += 1;
+        if self.additions >= self.reset_at {
+            for row in self.rows.iter_mut() {
+                row.iter_mut().for_each(|c| *c /= 2);
+            }
+            self.additions /= 2;
+        }
+    }
+
+    fn estimate(&self, key: &str) -> u8 {
+        (0..self.rows.len()).map(|row| self.rows[row][self.slot(row, key)]).min().unwrap_or(0)
+    }
+}
@@ -0,0 +1,60 @@
+//! Backend capability flags and health reporting used by the hub for routing.
+
+/// Features a backend implements. The hub only routes an operation to
+/// backends declaring the matching capability.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Data survives a restart.
+    pub persistent: bool,
+    /// Values are encrypted at rest.
+    pub encrypted: bool,
+    /// `scan` / `scan_keys` enumerate keys.
+    pub scan: bool,
+    /// `write_with_ttl` and `sweep_expired` are implemented.
+    pub ttl: bool,
+    /// `compare_and_swap` is implemented.
+    pub compare_and_swap: bool,
+    /// `commit` is implemented.
+    pub transactions: bool,
+    /// Record metadata is stored rather than synthesized.
+    pub metadata: bool,
+}
+
+/// Result of a backend health probe.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Health {
+    #[default]
+    Healthy,
+    /// Working, but with a problem worth reporting.
+    Degraded(String),
+    /// Not usable; the hub skips the backend until a later probe succeeds.
+    Unhealthy(String),
+}
+
+impl Health {
+    /// Whether the hub keeps routing operations to the backend.
+    pub fn is_usable(&self) -> bool {
+        !matches!(self, Health::Unhealthy(_))
+    }
+}
+
+/// State of one registered backend as seen by the hub.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackendStatus {
+    /// Name the backend was registered under.
+    pub name: String,
+    /// Position in priority order.
+    pub index: usize,
+    pub tier: u32,
+    pub capabilities: Capabilities,
+    /// Result of the latest probe; `Healthy` until the first one.
+    pub health: Health,
+}
+
+/// Overall hub readiness.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Readiness {
+    /// Enough usable backends to meet the configured write concern.
+    pub ready: bool,
+    pub backends: Vec<BackendStatus>,
+}
@@ -1,69 +1,1352 @@
-use crate::backend::{HubResult, MemoryBackend};
+use crate::audit::{Audit, AuditEvent, AuditOutcome, AuditSink};
+use crate::backend::{EntryStream, HubResult, KeyStream, MemoryBackend, ScanRange};
+use crate::error::HubError;
+use crate::cancellation::CancellationToken;
+use crate::health::{BackendStatus, Capabilities, Health, Readiness};
+use crate::repair;
+use crate::runtime;
+use crate::record::{CasOutcome, Expected, Record, WriteMeta};
+use crate::txn::{Transaction, TxOp};
+use crate::namespace::{self, Namespace};
+use crate::policy::{self, Action, Decision, PolicyEngine, Principal};
+use crate::quota::{Change, Charge, Quota, QuotaStatus, Quotas, Usage};
+use crate::routing::{RouteExplanation, Router, RoutingConfig};
+use crate::session::Session;
+use crate::strategy::{self, Named, ReadStrategy, RetryPolicy, Sequencer, WriteConcern, WriteReport};
+use crate::ttl::{deadline_millis, now_millis};
+use arc_swap::ArcSwap;
 use futures::future::join_all;
-use std::sync::Arc;
+use futures::stream::{self, BoxStream, StreamExt};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Bound;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 #[cfg(feature = "dev_metrics")] use metrics::{counter, histogram};
 
 /// Central router coordinating access to multiple memory back-ends.
 ///
 /// All operations are executed against every registered backend in parallel.
-/// For `read` the current strategy is **first backend that returns a value wins**.
-/// This is a placeholder for more advanced loser-tree merge.
+/// Backends are kept in priority order: ascending tier, then registration order.
+/// How `read` merges answers is selected with [`ReadStrategy`]; the default
+/// takes the highest-priority hit.
+///
+/// Operations only go to backends declaring the needed [`Capabilities`]
+/// (scans to scan-capable ones, TTL writes to TTL-capable ones, …) and skip
+/// backends whose latest health probe reported them unhealthy. Writes and
+/// deletes a backend missed that way, or failed on it, are replayed from the
+/// other backends by [`check_health`](Self::check_health) before the backend
+/// serves again.
+///
+/// Every backend has a unique name. Backends can be registered, replaced and
+/// removed through a shared reference while the hub is serving: each
+/// operation routes over the table as it was when the operation started, so
+/// in-flight calls finish against the old set of backends. Strategies,
+/// retries, the policy and the audit sink are changed through a shared
+/// reference too, and apply to operations started afterwards.
+///
+/// [`RoutingConfig`] rules send keys with a given prefix to named backends
+/// only; [`explain`](Self::explain) shows where a key ends up.
+///
+/// With a [`PolicyEngine`] set, every read, write, delete and scan is checked
+/// first and refused with [`HubError::PolicyDenied`]. Calls made directly on
+/// the hub run as [`Principal::anonymous`]; use a [`Session`] to act as
+/// someone else.
+#[derive(Default)]
 pub struct MemoryHub {
-    backends: Vec<Arc<dyn MemoryBackend>>,
+    /// Routing table, swapped as a whole on every change.
+    table: ArcSwap<Table>,
+    /// Compiled key-prefix rules.
+    routes: ArcSwap<Router>,
+    /// Serializes table changes; operations never take it.
+    reconfig: Mutex<()>,
+    /// Counter for generated backend names.
+    next_id: AtomicUsize,
+    /// Usage counters and limits per key prefix.
+    quotas: Quotas,
+    /// Swapped as a whole by the `set_*` methods.
+    settings: ArcSwap<Settings>,
+    /// Orders mutations per key so stale background retries are dropped.
+    sequencer: Arc<Sequencer>,
+}
+
+/// Strategies and hooks of a hub, changeable while it serves.
+#[derive(Clone, Default)]
+struct Settings {
+    read_strategy: ReadStrategy,
+    write_concern: WriteConcern,
+    write_retry: RetryPolicy,
+    read_repair: bool,
+    /// Consulted before every operation; `None` allows everything.
+    policy: Option<Arc<dyn PolicyEngine>>,
+    audit: Option<Arc<dyn AuditSink>>,
+}
+
+/// Backends in priority order.
+#[derive(Default)]
+struct Table {
+    slots: Vec<Arc<Slot>>,
+}
+
+/// A registered backend and what the hub knows about it.
+struct Slot {
+    name: String,
+    backend: Arc<dyn MemoryBackend>,
+    tier: u32,
+    /// Read at registration.
+    caps: Capabilities,
+    /// Latest probe result.
+    health: Mutex<Health>,
+    /// Keys whose latest write or delete the backend may have missed.
+    missed: Mutex<BTreeSet<String>>,
+}
+
+impl Slot {
+    fn new(name: String, backend: Arc<dyn MemoryBackend>, tier: u32) -> Self {
+        let caps = backend.capabilities();
+        Self { name, backend, tier, caps, health: Mutex::new(Health::Healthy), missed: Mutex::default() }
+    }
+
+    /// Remember that the backend may have missed the latest mutation of `key`.
+    fn miss(&self, key: &str) {
+        self.missed.lock().expect("missed lock").insert(key.to_string());
+    }
+
+    fn health(&self) -> Health {
+        self.health.lock().expect("health lock").clone()
+    }
+
+    fn named(&self) -> Named {
+        (self.name.clone(), Arc::clone(&self.backend))
+    }
+}
+
+fn named_of(slots: &[Arc<Slot>]) -> Vec<Named> {
+    slots.iter().map(|s| s.named()).collect()
+}
+
+/// Results of a fan-out over `slots`, or every failure attributed to its backend.
+fn collect_all<T>(slots: &[Arc<Slot>], results: Vec<HubResult<T>>) -> HubResult<Vec<T>> {
+    let mut values = Vec::with_capacity(results.len());
+    let mut errors = Vec::new();
+    for (slot, res) in slots.iter().zip(results) {
+        match res {
+            Ok(v) => values.push(v),
+            Err(e) => errors.push(e.in_backend(slot.name.as_str())),
+        }
+    }
+    HubError::aggregate(errors).map_or(Ok(values), Err)
+}
+
+/// Key and inclusiveness of one side of a scan range in policy contexts.
+fn bound_context(bound: &Bound<String>) -> (Option<&str>, bool) {
+    match bound {
+        Bound::Included(key) => (Some(key), true),
+        Bound::Excluded(key) => (Some(key), false),
+        Bound::Unbounded => (None, false),
+    }
+}
+
+/// Key a scan is checked against: its prefix or lower bound.
+fn scan_key(range: &ScanRange) -> Option<&str> {
+    match range {
+        ScanRange::All => None,
+        ScanRange::Prefix(prefix) => Some(prefix),
+        ScanRange::Range(Bound::Included(start) | Bound::Excluded(start), _) => Some(start),
+        ScanRange::Range(Bound::Unbounded, _) => None,
+    }
+}
+
+/// Usable backends declaring what `need` asks for that `key` is routed to,
+/// in priority order, and whether they come from the rule's fallback chain.
+fn pick(table: &Table, router: &Router, key: &str, need: fn(&Capabilities) -> bool) -> (Vec<Arc<Slot>>, bool) {
+    let qualifies = |s: &Slot| need(&s.caps) && s.health().is_usable();
+    let Some(rule) = router.lookup(key) else {
+        return (table.slots.iter().filter(|s| qualifies(s)).cloned().collect(), false);
+    };
+    let chosen: Vec<Arc<Slot>> = table.slots.iter().filter(|s| rule.backends.contains(&s.name) && qualifies(s)).cloned().collect();
+    if !chosen.is_empty() {
+        return (chosen, false);
+    }
+    let fallback = rule.fallback.iter().find_map(|name| table.slots.iter().find(|s| s.name == *name && qualifies(s)));
+    match fallback {
+        Some(slot) => (vec![Arc::clone(slot)], true),
+        None => (Vec::new(), false),
+    }
+}
+
+/// Usable fallback backends of the rule for `key` declaring what `need` asks
+/// for, in the rule's order, leaving out those [`pick`] already chose. Reads
+/// consult them on a miss: they may hold values written while the routed
+/// backends were down.
+fn fallbacks(table: &Table, router: &Router, key: &str, need: fn(&Capabilities) -> bool) -> Vec<Arc<Slot>> {
+    let Some(rule) = router.lookup(key) else { return Vec::new() };
+    let (chosen, _) = pick(table, router, key, need);
+    rule.fallback.iter()
+        .filter_map(|name| table.slots.iter().find(|s| s.name == *name))
+        .filter(|s| need(&s.caps) && s.health().is_usable() && !chosen.iter().any(|c| Arc::ptr_eq(c, s)))
+        .cloned()
+        .collect()
+}
+
+/// How the routing rule for `key` treats backend `name`: `Some(0)` if the key
+/// is routed to it (or has no rule), `Some(1)` if it is a fallback, `None` if
+/// the key does not belong there.
+fn placement(router: &Router, name: &str, key: &str) -> Option<u8> {
+    match router.lookup(key) {
+        None => Some(0),
+        Some(rule) if rule.backends.iter().any(|b| b == name) => Some(0),
+        Some(rule) if rule.fallback.iter().any(|b| b == name) => Some(1),
+        Some(_) => None,
+    }
+}
+
+/// Backends declaring what `need` asks for that a mutation skips because
+/// they are unhealthy: with a `key`, those of its rule if it has one.
+fn down(table: &Table, router: &Router, key: Option<&str>, need: fn(&Capabilities) -> bool) -> Vec<Arc<Slot>> {
+    let rule = key.and_then(|key| router.lookup(key));
+    table.slots.iter()
+        .filter(|s| need(&s.caps) && !s.health().is_usable())
+        .filter(|s| rule.is_none_or(|r| r.backends.contains(&s.name)))
+        .cloned()
+        .collect()
 }
 
 impl MemoryHub {
     /// Create an empty hub. Register at least one backend before use.
     pub fn new() -> Self {
-        Self { backends: Vec::new() }
+        Self::default()
     }
 
-    /// Register a backend implementation. Can be called at runtime during init.
-    pub fn register_backend(&mut self, backend: Box<dyn MemoryBackend>) {
-        self.backends.push(backend.into());
+    /// Register a backend implementation in tier 0 under a generated name,
+    /// which is returned.
+    pub fn register_backend(&self, backend: Box<dyn MemoryBackend>) -> String {
+        self.register_backend_with_tier(backend, 0)
     }
 
-    /// Store the value in **all** back-ends.
-    pub async fn write(&self, key: String, value: Vec<u8>) -> HubResult<()> {
-        let mut futures_vec = Vec::with_capacity(self.backends.len());
-        for be in &self.backends {
-            let k = key.clone();
-            let v = value.clone();
-            let be = Arc::clone(be);
-            futures_vec.push(async move { be.write(k, v).await });
-        }
-
-        // Wait for all backends to finish.
-        // Collect first error (if any).
-        let results = join_all(futures_vec).await;
-        #[cfg(feature = "dev_metrics")] counter!("memory_hub.write.total", 1);
-        for res in results {
-            res?;
-        }
-        #[cfg(feature = "dev_metrics")] histogram!("memory_hub.write.latency_ms", 0.0); // placeholder
+    /// Register a backend in the given tier under a generated name, which is
+    /// returned. Lower tiers take priority on reads.
+    pub fn register_backend_with_tier(&self, backend: Box<dyn MemoryBackend>, tier: u32) -> String {
+        let _guard = self.reconfig.lock().expect("reconfig lock");
+        let table = self.table.load();
+        let name = loop {
+            let name = format!("backend-{}", self.next_id.fetch_add(1, Ordering::Relaxed));
+            if !table.slots.iter().any(|s| s.name == name) {
+                break name;
+            }
+        };
+        self.insert(&table, Slot::new(name.clone(), backend.into(), tier));
+        name
+    }
+
+    /// Register a backend under `name` in the given tier. Fails if the name is taken.
+    pub fn register_named(&self, name: impl Into<String>, backend: Box<dyn MemoryBackend>, tier: u32) -> HubResult<()> {
+        let name = name.into();
+        let _guard = self.reconfig.lock().expect("reconfig lock");
+        let table = self.table.load();
+        if table.slots.iter().any(|s| s.name == name) {
+            return Err(HubError::InvalidInput(format!("backend {name} already registered")));
+        }
+        self.insert(&table, Slot::new(name, backend.into(), tier));
         Ok(())
     }
 
+    /// Publish `table` plus `slot`. Callers hold the reconfig lock.
+    fn insert(&self, table: &Table, slot: Slot) {
+        let mut slots = table.slots.clone();
+        let pos = slots.partition_point(|s| s.tier <= slot.tier);
+        slots.insert(pos, Arc::new(slot));
+        self.table.store(Arc::new(Table { slots }));
+    }
+
+    /// Remove the backend registered under `name` and return it. Operations
+    /// already running keep using it until they finish.
+    pub fn deregister(&self, name: &str) -> Option<Arc<dyn MemoryBackend>> {
+        let _guard = self.reconfig.lock().expect("reconfig lock");
+        let table = self.table.load();
+        let pos = table.slots.iter().position(|s| s.name == name)?;
+        let mut slots = table.slots.clone();
+        let removed = slots.remove(pos);
+        self.table.store(Arc::new(Table { slots }));
+        Some(Arc::clone(&removed.backend))
+    }
+
+    /// Swap the backend registered under `name` for `backend`, keeping its
+    /// name and tier, and return the previous one. The new backend starts out
+    /// healthy; its capabilities are read again.
+    pub fn replace(&self, name: &str, backend: Box<dyn MemoryBackend>) -> HubResult<Arc<dyn MemoryBackend>> {
+        let _guard = self.reconfig.lock().expect("reconfig lock");
+        let table = self.table.load();
+        let Some(pos) = table.slots.iter().position(|s| s.name == name) else {
+            return Err(HubError::NotFound(format!("no backend named {name}")));
+        };
+        let mut slots = table.slots.clone();
+        let old = std::mem::replace(&mut slots[pos], Arc::new(Slot::new(name.to_string(), backend.into(), table.slots[pos].tier)));
+        self.table.store(Arc::new(Table { slots }));
+        Ok(Arc::clone(&old.backend))
+    }
+
+    /// The backend registered under `name`.
+    pub fn backend(&self, name: &str) -> Option<Arc<dyn MemoryBackend>> {
+        self.table.load().slots.iter().find(|s| s.name == name).map(|s| Arc::clone(&s.backend))
+    }
+
+    /// State of every registered backend in priority order, as of the latest probes.
+    pub fn inspect(&self) -> Vec<BackendStatus> {
+        self.table.load().slots.iter().enumerate().map(|(index, slot)| BackendStatus {
+            name: slot.name.clone(),
+            index,
+            tier: slot.tier,
+            capabilities: slot.caps,
+            health: slot.health(),
+        }).collect()
+    }
+
+    /// Handle scoping operations to the tenant `name`; see [`Namespace`].
+    /// The namespace becomes a quota scope, unlimited until a quota is set.
+    pub fn namespace(self: &Arc<Self>, name: &str) -> HubResult<Namespace> {
+        self.namespace_as(name, Principal::anonymous())
+    }
+
+    pub(crate) fn namespace_as(self: &Arc<Self>, name: &str, principal: Principal) -> HubResult<Namespace> {
+        namespace::validate_name(name)?;
+        self.quotas.track(&namespace::prefix_of(name));
+        Ok(Namespace::new(Arc::clone(self), name.to_string(), principal))
+    }
+
+    /// Handle running operations as `principal`; see [`Session`].
+    pub fn session(self: &Arc<Self>, principal: Principal) -> Session {
+        Session::new(Arc::clone(self), principal)
+    }
+
+    /// Change settings; operations already running keep the old ones.
+    fn configure(&self, change: impl Fn(&mut Settings)) {
+        self.settings.rcu(|settings| {
+            let mut settings = Settings::clone(settings);
+            change(&mut settings);
+            settings
+        });
+    }
+
+    /// Check every later operation against `policy`.
+    pub fn set_policy(&self, policy: Arc<dyn PolicyEngine>) {
+        self.configure(|s| s.policy = Some(Arc::clone(&policy)));
+    }
+
+    /// Report every keyed operation and scan to `sink` with its policy
+    /// decision and outcome, including the implicit allows when no policy
+    /// is set.
+    pub fn set_audit_sink(&self, sink: Arc<dyn AuditSink>) {
+        self.configure(|s| s.audit = Some(Arc::clone(&sink)));
+    }
+
+    /// Ask the policy whether `who` may run `action` on `key`. Denials go
+    /// to the audit sink at once; allowed operations hand the returned
+    /// [`Audit`] their result. `size` is the value length of writes.
+    fn authorize(&self, who: &Principal, action: Action, key: Option<&str>, size: Option<usize>) -> HubResult<Audit> {
+        self.authorize_redacted(who, action, key, size).map(|(audit, _)| audit)
+    }
+
+    /// [`authorize`](Self::authorize) for operations handing out values,
+    /// which also get the fields to [`redact`](policy::redact) from them.
+    fn authorize_redacted(&self, who: &Principal, action: Action, key: Option<&str>, size: Option<usize>) -> HubResult<(Audit, Vec<String>)> {
+        self.check(who, action, key, size, None)
+    }
+
+    /// [`authorize_redacted`](Self::authorize_redacted) for a scan of `range`.
+    fn authorize_scan(&self, who: &Principal, range: &ScanRange) -> HubResult<(Audit, Vec<String>)> {
+        let bounds = match range {
+            ScanRange::All => serde_json::json!({ "start": null, "end": null }),
+            ScanRange::Prefix(prefix) => serde_json::json!({ "prefix": prefix }),
+            ScanRange::Range(start, end) => {
+                let (start, start_inclusive) = bound_context(start);
+                let (end, end_inclusive) = bound_context(end);
+                serde_json::json!({ "start": start, "start_inclusive": start_inclusive, "end": end, "end_inclusive": end_inclusive })
+            }
+        };
+        self.check(who, Action::Scan, scan_key(range), None, Some(bounds))
+    }
+
+    fn check(&self, who: &Principal, action: Action, key: Option<&str>, size: Option<usize>, range: Option<serde_json::Value>) -> HubResult<(Audit, Vec<String>)> {
+        let settings = self.settings.load();
+        if settings.policy.is_none() && settings.audit.is_none() {
+            return Ok((Audit::none(), Vec::new()));
+        }
+        let namespace = key.and_then(namespace::tenant_of);
+        let decision = settings.policy.as_ref().map_or_else(|| Decision { allow: true, ..Default::default() }, |policy| {
+            let mut ctx = serde_json::json!({ "key": key, "namespace": namespace, "size": size, "principal": who });
+            if let Some(range) = range {
+                ctx["range"] = range;
+            }
+            policy.evaluate(action.as_str(), &ctx)
+        });
+        let Decision { allow: allowed, redact, obligations } = decision;
+        let event = settings.audit.as_ref().map(|sink| (sink, AuditEvent {
+            at_millis: now_millis(),
+            action,
+            principal: who.id.clone(),
+            key: key.map(str::to_string),
+            namespace: namespace.map(str::to_string),
+            size: size.map(|n| n as u64),
+            allowed,
+            obligations,
+            returned: Vec::new(),
+            outcome: AuditOutcome::Denied,
+        }));
+        if allowed {
+            let audit = event.map_or_else(Audit::none, |(sink, event)| Audit::pending(Arc::clone(sink), event));
+            return Ok((audit, redact));
+        }
+        if let Some((sink, event)) = event {
+            sink.record(&event);
+        }
+        #[cfg(feature = "dev_metrics")] counter!("memory_hub.policy.denied", "action" => action.as_str()).increment(1);
+        let target = key.map(|k| format!(" on {k}")).unwrap_or_default();
+        Err(HubError::PolicyDenied { action: action.to_string(), reason: format!("policy refused principal {:?}{target}", who.id) })
+    }
+
+    /// Limit keys and bytes stored under `prefix`. Usage of a new scope
+    /// starts at zero; run [`rebuild_usage`](Self::rebuild_usage) to count
+    /// data already stored.
+    pub fn set_quota(&self, prefix: impl Into<String>, quota: Quota) {
+        self.quotas.set(prefix.into(), quota);
+    }
+
+    /// Stop counting `prefix`. Returns whether it was a scope.
+    pub fn remove_quota(&self, prefix: &str) -> bool {
+        self.quotas.remove(prefix)
+    }
+
+    pub fn quota(&self, prefix: &str) -> Option<Quota> {
+        self.quotas.get(prefix).map(|s| s.quota)
+    }
+
+    /// Current usage of the scope `prefix`.
+    pub fn usage(&self, prefix: &str) -> Option<Usage> {
+        self.quotas.get(prefix).map(|s| s.usage)
+    }
+
+    /// Every scope with its limits and usage, ordered by prefix.
+    pub fn quotas(&self) -> Vec<QuotaStatus> {
+        self.quotas.list()
+    }
+
+    /// Recount every scope from scans of the scan-capable backends. Call it
+    /// at startup, after registering backends and scopes; it also corrects
+    /// drift from expired keys and background write retries. Writes running
+    /// meanwhile may be missed until the next rebuild.
+    pub async fn rebuild_usage(&self) -> HubResult<()> {
+        for prefix in self.quotas.prefixes() {
+            let (mut usage, mut sizes) = (Usage::default(), Vec::new());
+            let mut entries = self.scan_unchecked(ScanRange::Prefix(prefix.clone()));
+            while let Some(item) = entries.next().await {
+                let (key, value) = item?;
+                usage.keys += 1;
+                usage.bytes += value.len() as u64;
+                sizes.push((key, value.len()));
+            }
+            self.quotas.reset(&prefix, usage, sizes);
+        }
+        Ok(())
+    }
+
+    /// Book the size change of each `(key, new length)` pair, `None` meaning
+    /// removal, against the quota scopes containing the key. The previous
+    /// size comes from the sizes the quotas keep, so nothing is read.
+    fn charge(&self, changes: &[Change<'_>]) -> HubResult<Charge> {
+        Ok(self.quotas.reserve(changes)?)
+    }
+
+    /// Give `charge` back if the operation stored nothing on any backend.
+    /// The one rule for every mutation: one that reached some backend, or
+    /// may still reach it in the background, keeps the new size.
+    fn settle(&self, charge: Charge, stored: bool) {
+        if !stored {
+            self.quotas.refund(charge);
+        }
+    }
+
+    /// Replace the routing rules. Operations already running keep the rules
+    /// they started with. Rules may name backends that are not registered
+    /// (yet); such names are skipped.
+    pub fn set_routing(&self, config: RoutingConfig) -> HubResult<()> {
+        self.routes.store(Arc::new(Router::new(config)?));
+        Ok(())
+    }
+
+    /// The routing rules in effect.
+    pub fn routing(&self) -> RoutingConfig {
+        self.routes.load().config().clone()
+    }
+
+    /// Which rule matches `key` and which backends it would be routed to right now.
+    pub fn explain(&self, key: &str) -> RouteExplanation {
+        let router = self.routes.load();
+        let (slots, via_fallback) = pick(&self.table.load(), &router, key, |_| true);
+        RouteExplanation {
+            rule: router.lookup(key).map(|r| r.pattern.clone()),
+            backends: slots.iter().map(|s| s.name.clone()).collect(),
+            via_fallback,
+        }
+    }
+
+    /// Usable backends declaring what `need` asks for, in priority order.
+    /// With a `key`, only the backends its routing rule selects.
+    fn route(&self, key: Option<&str>, need: fn(&Capabilities) -> bool) -> Vec<Arc<Slot>> {
+        match key {
+            Some(key) => pick(&self.table.load(), &self.routes.load(), key, need).0,
+            None => self.table.load().slots.iter()
+                .filter(|s| need(&s.caps) && s.health().is_usable())
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// Fallback backends reads of `key` consult on a miss; see [`fallbacks`].
+    fn fallbacks(&self, key: &str) -> Vec<Arc<Slot>> {
+        fallbacks(&self.table.load(), &self.routes.load(), key, |_| true)
+    }
+
+    /// Usable backends in priority order.
+    /// Like [`route`](Self::route), failing if backends are registered but
+    /// none of them qualifies.
+    fn targets(&self, key: Option<&str>, need: fn(&Capabilities) -> bool, what: &str) -> HubResult<Vec<Arc<Slot>>> {
+        let targets = self.route(key, need);
+        if targets.is_empty() && !self.table.load().slots.is_empty() {
+            return Err(match key {
+                Some(key) => HubError::Unsupported(format!("no usable backend supports {what} for {key}")),
+                None => HubError::Unsupported(format!("no usable backend supports {what}")),
+            });
+        }
+        Ok(targets)
+    }
+
+    /// Split keys by the backends they are routed to: one group per usable
+    /// backend receiving any of them, in priority order, holding key indices.
+    fn partition<'a>(&self, keys: impl Iterator<Item = &'a str>) -> Vec<(Arc<Slot>, Vec<usize>)> {
+        let (table, router) = (self.table.load(), self.routes.load());
+        let mut groups: Vec<(Arc<Slot>, Vec<usize>)> = table.slots.iter().map(|s| (Arc::clone(s), Vec::new())).collect();
+        for (idx, key) in keys.enumerate() {
+            for slot in pick(&table, &router, key, |_| true).0 {
+                if let Some((_, members)) = groups.iter_mut().find(|(s, _)| Arc::ptr_eq(s, &slot)) {
+                    members.push(idx);
+                }
+            }
+        }
+        groups.retain(|(_, members)| !members.is_empty());
+        groups
+    }
+
+    /// Remember that a mutation of `key` did not reach the unhealthy backends
+    /// it skipped, nor the backends named in `failed`.
+    fn missed<'a>(&self, key: &str, need: fn(&Capabilities) -> bool, routed: bool, failed: impl IntoIterator<Item = &'a str>) {
+        let table = self.table.load();
+        for slot in down(&table, &self.routes.load(), routed.then_some(key), need) {
+            slot.miss(key);
+        }
+        for name in failed {
+            if let Some(slot) = table.slots.iter().find(|s| s.name == name) {
+                slot.miss(key);
+            }
+        }
+    }
+
+    /// Remember the backends a write of `key` skipped or did not reach yet.
+    fn missed_write(&self, key: &str, need: fn(&Capabilities) -> bool, report: &WriteReport) {
+        let failed = report.failed.iter().map(|(name, _)| name.as_str());
+        self.missed(key, need, true, failed.chain(report.pending.iter().map(String::as_str)));
+    }
+
+    /// Probe every backend, remember the results for routing and return the
+    /// resulting readiness. A usable backend that missed mutations first gets
+    /// them replayed and stays unhealthy until none is left.
+    pub async fn check_health(&self) -> Readiness {
+        let table = self.table.load_full();
+        let probes = join_all(table.slots.iter().map(|s| s.backend.health())).await;
+        for (slot, mut health) in table.slots.iter().zip(probes) {
+            if health.is_usable() {
+                let left = self.replay(&table, slot).await;
+                if left > 0 {
+                    health = Health::Unhealthy(format!("{left} missed mutations not replayed yet"));
+                }
+            }
+            *slot.health.lock().expect("health lock") = health;
+        }
+        self.readiness()
+    }
+
+    /// Bring the keys `slot` missed up to date with the newest record among
+    /// the other usable backends the key is routed to, fallbacks included:
+    /// copy it unless the slot holds the same or a newer version, or delete
+    /// the slot's copy if none of them holds the key. Keys with a mutation in
+    /// flight, keys that fail and keys without another backend to compare
+    /// with are kept for the next round. Returns how many are left.
+    async fn replay(&self, table: &Table, slot: &Arc<Slot>) -> usize {
+        let keys = std::mem::take(&mut *slot.missed.lock().expect("missed lock"));
+        if keys.is_empty() {
+            return 0;
+        }
+        let router = self.routes.load();
+        for key in keys {
+            let rule = router.lookup(&key);
+            let sources: Vec<Named> = table.slots.iter()
+                .filter(|s| !Arc::ptr_eq(s, slot) && s.health().is_usable())
+                .filter(|s| rule.is_none_or(|r| r.backends.contains(&s.name) || r.fallback.contains(&s.name)))
+                .map(|s| s.named())
+                .collect();
+            let done = !sources.is_empty() && self.sequencer.is_idle(&key) && async {
+                let ticket = self.sequencer.issue(&key);
+                let winner = strategy::read_record(ReadStrategy::NewestWins, &sources, key.clone()).await?;
+                let current = slot.backend.read_record(key.clone()).await?;
+                match (winner, current) {
+                    (Some(w), Some(c)) if c.meta.version > w.meta.version || (c.meta.version == w.meta.version && c.value == w.value) => {}
+                    (Some(w), _) => slot.backend.write_with_meta(key.clone(), w.value, WriteMeta::of(&w.meta)).await?,
+                    (None, Some(_)) => { slot.backend.delete(key.clone()).await?; }
+                    (None, None) => {}
+                }
+                // a mutation issued meanwhile may have landed before the copy
+                HubResult::Ok(ticket.is_latest())
+            }.await.unwrap_or(false);
+            if !done {
+                slot.miss(&key);
+            }
+        }
+        slot.missed.lock().expect("missed lock").len()
+    }
+
+    /// Readiness according to the latest probes: ready when enough backends
+    /// are usable to meet the configured [`WriteConcern`].
+    pub fn readiness(&self) -> Readiness {
+        let backends = self.inspect();
+        let usable = backends.iter().filter(|b| b.health.is_usable()).count();
+        let needed = match self.settings.load().write_concern {
+            WriteConcern::Quorum(n) => n.max(1),
+            _ => 1,
+        };
+        Readiness { ready: usable >= needed, backends }
+    }
+
+    /// Run [`check_health`](Self::check_health) every `interval` until the returned token is cancelled.
+    pub fn spawn_health_checker(self: &Arc<Self>, interval: Duration) -> CancellationToken {
+        let token = CancellationToken::new();
+        let (hub, stop) = (Arc::clone(self), token.clone());
+        runtime::spawn(async move {
+            while !stop.is_cancelled() {
+                runtime::sleep(interval).await;
+                if stop.is_cancelled() { break; }
+                hub.check_health().await;
+            }
+        });
+        token
+    }
+
+    /// Select how reads merge answers from several backends.
+    pub fn set_read_strategy(&self, strategy: ReadStrategy) {
+        self.configure(|s| s.read_strategy = strategy);
+    }
+
+    /// When enabled, every successful read copies the winning record in the
+    /// background to the compare-and-swap capable backends that miss it or
+    /// hold a stale copy: an older version under [`ReadStrategy::NewestWins`],
+    /// a different value under [`ReadStrategy::Quorum`]. Copies are written
+    /// conditionally on what was read from them, with the winner's version,
+    /// metadata and expiry. Other strategies cannot tell a stale copy from a
+    /// newer one, so they never repair.
+    pub fn set_read_repair(&self, enabled: bool) {
+        self.configure(|s| s.read_repair = enabled);
+    }
+
+    /// Select when `write` is acknowledged.
+    pub fn set_write_concern(&self, concern: WriteConcern) {
+        self.configure(|s| s.write_concern = concern);
+    }
+
+    /// Configure background retries of failed or still-pending writes.
+    pub fn set_write_retry(&self, retry: RetryPolicy) {
+        self.configure(|s| s.write_retry = retry);
+    }
+
+    /// Store the value in back-ends according to the configured [`WriteConcern`].
+    /// Returns the first backend error if the concern is not met, or
+    /// [`QuotaExceeded`](crate::QuotaExceeded) if the key's scope is full.
+    pub async fn write(&self, key: String, value: Vec<u8>) -> HubResult<()> {
+        self.write_as(&Principal::anonymous(), key, value).await
+    }
+
+    pub(crate) async fn write_as(&self, who: &Principal, key: String, value: Vec<u8>) -> HubResult<()> {
+        self.write_with_as(who, key, value, self.settings.load().write_concern).await?.into_result()
+    }
+
+    /// Store the value with an explicit concern and report per-backend outcome.
+    /// Failed and pending writes keep going in the background under the retry policy.
+    /// Unhealthy backends are skipped and do not count towards the concern;
+    /// they get the write once they recover.
+    /// Fails without touching any backend if a quota would be exceeded.
+    pub async fn write_with(&self, key: String, value: Vec<u8>, concern: WriteConcern) -> HubResult<WriteReport> {
+        self.write_with_as(&Principal::anonymous(), key, value, concern).await
+    }
+
+    pub(crate) async fn write_with_as(&self, who: &Principal, key: String, value: Vec<u8>, concern: WriteConcern) -> HubResult<WriteReport> {
+        #[cfg(feature = "dev_metrics")] counter!("memory_hub.write.total").increment(1);
+        let audit = self.authorize(who, Action::Write, Some(&key), Some(value.len()))?;
+        let res = async {
+            let charge = self.charge(&[(&key, Some(value.len()))])?;
+            let targets = match self.targets(Some(&key), |_| true, "writes") {
+                Ok(targets) => targets,
+                Err(e) => {
+                    self.settle(charge, false);
+                    return Err(e);
+                }
+            };
+            let ticket = self.sequencer.issue(&key);
+            let meta = WriteMeta { version: Some(ticket.version()), ..Default::default() };
+            let report = strategy::write(concern, self.settings.load().write_retry, &named_of(&targets), key.clone(), value, meta, ticket).await;
+            self.missed_write(&key, |_| true, &report);
+            self.settle(charge, report.stored());
+            #[cfg(feature = "dev_metrics")] histogram!("memory_hub.write.latency_ms").record(0.0); // placeholder
+            Ok(report)
+        }.await;
+        match &res {
+            Ok(report) if !report.satisfied => {
+                let failed: Vec<&str> = report.failed.iter().map(|(name, _)| name.as_str()).collect();
+                audit.fail(format!("write concern not met; failed on {failed:?}"));
+            }
+            _ => audit.record(&res),
+        }
+        res
+    }
+
+    /// Store the value so that it expires after `ttl`, honouring the configured
+    /// [`WriteConcern`] among the TTL-capable backends the key is routed to.
+    pub async fn write_with_ttl(&self, key: String, value: Vec<u8>, ttl: Duration) -> HubResult<()> {
+        self.write_with_ttl_as(&Principal::anonymous(), key, value, ttl).await
+    }
+
+    pub(crate) async fn write_with_ttl_as(&self, who: &Principal, key: String, value: Vec<u8>, ttl: Duration) -> HubResult<()> {
+        #[cfg(feature = "dev_metrics")] counter!("memory_hub.write.total").increment(1);
+        let audit = self.authorize(who, Action::Write, Some(&key), Some(value.len()))?;
+        audit.finish(async {
+            let targets = named_of(&self.targets(Some(&key), |c| c.ttl, "ttl")?);
+            let charge = self.charge(&[(&key, Some(value.len()))])?;
+            let ticket = self.sequencer.issue(&key);
+            let meta = WriteMeta { version: Some(ticket.version()), expires_at: Some(deadline_millis(ttl)), ..Default::default() };
+            let settings = self.settings.load_full();
+            let report = strategy::write(settings.write_concern, settings.write_retry, &targets, key.clone(), value, meta, ticket).await;
+            self.missed_write(&key, |c| c.ttl, &report);
+            self.settle(charge, report.stored());
+            report.into_result()
+        }.await)
+    }
+
+    /// Store the value with content type and tags, honouring the configured
+    /// [`WriteConcern`]. The hub assigns the version, the same on every
+    /// backend, replacing one set in `meta`; each backend assigns timestamps
+    /// and checksum.
+    pub async fn write_with_meta(&self, key: String, value: Vec<u8>, meta: WriteMeta) -> HubResult<()> {
+        self.write_with_meta_as(&Principal::anonymous(), key, value, meta).await
+    }
+
+    pub(crate) async fn write_with_meta_as(&self, who: &Principal, key: String, value: Vec<u8>, meta: WriteMeta) -> HubResult<()> {
+        #[cfg(feature = "dev_metrics")] counter!("memory_hub.write.total").increment(1);
+        let audit = self.authorize(who, Action::Write, Some(&key), Some(value.len()))?;
+        audit.finish(async {
+            let targets = named_of(&self.route(Some(&key), |_| true));
+            let charge = self.charge(&[(&key, Some(value.len()))])?;
+            let ticket = self.sequencer.issue(&key);
+            let meta = WriteMeta { version: Some(ticket.version()), ..meta };
+            let settings = self.settings.load_full();
+            let report = strategy::write(settings.write_concern, settings.write_retry, &targets, key.clone(), value, meta, ticket).await;
+            self.missed_write(&key, |_| true, &report);
+            self.settle(charge, report.stored());
+            report.into_result()
+        }.await)
+    }
+
+    /// Conditional write. The highest-priority usable backend supporting
+    /// compare-and-swap among those the key is routed to decides: the swap runs
+    /// there atomically, and only on success is the new value written to the
+    /// remaining routed backends. The configured [`WriteConcern`] counts the primary
+    /// as one acknowledgement; propagation the concern does not wait for
+    /// continues in the background. Propagation of concurrent swaps is not
+    /// ordered, so secondaries may briefly hold an older value. An error after
+    /// a successful swap means the value is stored on the primary but the
+    /// concern was not met. Room for the new value is reserved against quotas
+    /// up front and given back unless the swap happens.
+    pub async fn compare_and_swap(&self, key: String, expected: Expected, new: Vec<u8>) -> HubResult<CasOutcome> {
+        self.compare_and_swap_as(&Principal::anonymous(), key, expected, new).await
+    }
+
+    pub(crate) async fn compare_and_swap_as(&self, who: &Principal, key: String, expected: Expected, new: Vec<u8>) -> HubResult<CasOutcome> {
+        let audit = self.authorize(who, Action::Write, Some(&key), Some(new.len()))?;
+        audit.finish(async {
+            let charge = self.charge(&[(&key, Some(new.len()))])?;
+            let res = self.swap(key, expected, new).await;
+            self.settle(charge, res.as_ref().is_ok_and(|(outcome, _)| outcome.is_swapped()));
+            let (outcome, propagated) = res?;
+            propagated.map(|()| outcome)
+        }.await)
+    }
+
+    /// The primary's outcome and, once it swapped, whether propagation met
+    /// the concern. Fails only if the primary stored nothing.
+    async fn swap(&self, key: String, expected: Expected, new: Vec<u8>) -> HubResult<(CasOutcome, HubResult<()>)> {
+        let settings = self.settings.load_full();
+        let Some(primary) = self.targets(Some(&key), |c| c.compare_and_swap, "compare-and-swap")?.into_iter().next() else {
+            return Err(HubError::Unavailable("no backends registered".into()));
+        };
+        let rest: Vec<Named> = self.route(Some(&key), |_| true).iter().filter(|s| s.name != primary.name).map(|s| s.named()).collect();
+        let meta = WriteMeta { version: Some(self.sequencer.stamp()), ..Default::default() };
+        let outcome = primary.backend.compare_and_swap_with_meta(key.clone(), expected, new.clone(), meta).await?;
+        let CasOutcome::Swapped { version } = outcome else { return Ok((outcome, Ok(()))) };
+        self.missed(&key, |_| true, true, []);
+        if rest.is_empty() {
+            return Ok((outcome, Ok(())));
+        }
+        let ticket = self.sequencer.issue(&key);
+        // copies carry the version the primary stored
+        let meta = WriteMeta { version: Some(version), ..Default::default() };
+        let awaited = match settings.write_concern {
+            WriteConcern::All => Some(WriteConcern::All),
+            WriteConcern::Quorum(n) if n > 1 => Some(WriteConcern::Quorum(n - 1)),
+            // the primary alone satisfies Any, PrimaryAsync and Quorum(1)
+            _ => None,
+        };
+        match awaited {
+            Some(concern) => {
+                let report = strategy::write(concern, settings.write_retry, &rest, key.clone(), new, meta, ticket).await;
+                self.missed_write(&key, |_| true, &report);
+                return Ok((outcome, report.into_result()));
+            }
+            None => {
+                let (retry, table) = (settings.write_retry, self.table.load_full());
+                runtime::spawn(async move {
+                    let report = strategy::write(WriteConcern::All, retry, &rest, key.clone(), new, meta, ticket).await;
+                    for (name, _) in &report.failed {
+                        if let Some(slot) = table.slots.iter().find(|s| s.name == *name) {
+                            slot.miss(&key);
+                        }
+                    }
+                });
+            }
+        }
+        Ok((outcome, Ok(())))
+    }
+
+    /// Store the value only if the primary backend does not hold the key;
+    /// see [`compare_and_swap`](Self::compare_and_swap). Returns `true` if stored.
+    pub async fn put_if_absent(&self, key: String, value: Vec<u8>) -> HubResult<bool> {
+        Ok(self.compare_and_swap(key, Expected::Absent, value).await?.is_swapped())
+    }
+
+    /// Start an empty transaction for [`commit`](Self::commit).
+    pub fn begin(&self) -> Transaction {
+        Transaction::new()
+    }
+
+    /// Whether every usable backend can commit transactions.
+    pub fn supports_transactions(&self) -> bool {
+        let targets = self.route(None, |_| true);
+        !targets.is_empty() && targets.iter().all(|s| s.caps.transactions)
+    }
+
+    /// Commit `tx` on every usable backend its keys are routed to. Each backend
+    /// applies it atomically, but the backends commit independently: on error
+    /// some may hold the result while others do not. Fails up front, without
+    /// touching any backend, if the keys are routed to different backends or
+    /// a target does not declare transaction support, or if the policy
+    /// refuses any of its operations.
+    pub async fn commit(&self, tx: Transaction) -> HubResult<()> {
+        self.commit_as(&Principal::anonymous(), tx).await
+    }
+
+    pub(crate) async fn commit_as(&self, who: &Principal, tx: Transaction) -> HubResult<()> {
+        // only the last operation on a key decides its final size
+        let mut last: BTreeMap<&str, Option<usize>> = BTreeMap::new();
+        let mut audits = Vec::with_capacity(tx.ops().len());
+        for op in tx.ops() {
+            let new_len = match op {
+                TxOp::Put { value, .. } => Some(value.len()),
+                TxOp::Delete { .. } => None,
+            };
+            let action = if new_len.is_some() { Action::Write } else { Action::Delete };
+            audits.push(self.authorize(who, action, Some(op.key()), new_len)?);
+            last.insert(op.key(), new_len);
+        }
+        let _tickets: Vec<_> = last.keys().map(|key| self.sequencer.issue(key)).collect();
+        let res = async {
+            let changes: Vec<Change<'_>> = last.into_iter().collect();
+            let charge = self.charge(&changes)?;
+            let res = self.commit_routed(&tx).await;
+            self.settle(charge, res.as_ref().is_ok_and(|(stored, _)| *stored));
+            res?.1
+        }.await;
+        for audit in audits {
+            audit.record(&res);
+        }
+        res
+    }
+
+    /// Whether any backend committed `tx`, and the combined result. Fails
+    /// without touching a backend if `tx` cannot be committed as routed.
+    async fn commit_routed(&self, tx: &Transaction) -> HubResult<(bool, HubResult<()>)> {
+        let keys = tx.keys();
+        let targets = match keys.first() {
+            Some(first) => self.route(Some(first), |_| true),
+            None => self.route(None, |_| true),
+        };
+        for key in keys.iter().skip(1) {
+            let other = self.route(Some(key), |_| true);
+            if other.len() != targets.len() || other.iter().zip(&targets).any(|(a, b)| !Arc::ptr_eq(a, b)) {
+                return Err(HubError::InvalidInput(format!("transaction keys {} and {key} are routed to different backends", keys[0])));
+            }
+        }
+        if let Some(slot) = targets.iter().find(|s| !s.caps.transactions) {
+            return Err(HubError::Unsupported("transactions not supported by backend".into()).in_backend(&slot.name));
+        }
+        // every backend stores the puts under the same version
+        let tx = tx.clone().with_version(self.sequencer.stamp());
+        let results = join_all(targets.iter().map(|s| s.backend.commit(tx.clone()))).await;
+        let stored = results.iter().any(Result::is_ok);
+        if stored {
+            let failed: Vec<&str> = targets.iter().zip(&results).filter(|(_, r)| r.is_err()).map(|(s, _)| s.name.as_str()).collect();
+            for key in &keys {
+                self.missed(key, |_| true, true, failed.iter().copied());
+            }
+        }
+        Ok((stored, collect_all(&targets, results).map(drop)))
+    }
+
+    /// Purge expired entries from every TTL-capable backend. Returns the total number removed.
+    pub async fn sweep_expired(&self) -> HubResult<usize> {
+        let targets = self.route(None, |c| c.ttl);
+        let results = join_all(targets.iter().map(|s| s.backend.sweep_expired())).await;
+        Ok(collect_all(&targets, results)?.into_iter().sum())
+    }
+
+    /// Run [`sweep_expired`](Self::sweep_expired) every `interval` until the returned token is cancelled.
+    pub fn spawn_sweeper(self: &Arc<Self>, interval: Duration) -> CancellationToken {
+        let token = CancellationToken::new();
+        let (hub, stop) = (Arc::clone(self), token.clone());
+        runtime::spawn(async move {
+            while !stop.is_cancelled() {
+                runtime::sleep(interval).await;
+                if stop.is_cancelled() { break; }
+                // a failed pass is retried on the next tick
+                let _ = hub.sweep_expired().await;
+            }
+        });
+        token
+    }
+
     /// Retrieve value by key from back-ends concurrently.
-    /// Returns the first successful hit or `None` if nobody has the key.
+    /// The answer is merged according to the configured [`ReadStrategy`];
+    /// `None` means no backend has the key. On a miss the fallbacks of the
+    /// key's routing rule are asked in order, as they hold writes made while
+    /// the routed backends were down; with read repair on, a copy found there
+    /// is written back to the routed backends.
     pub async fn read(&self, key: String) -> HubResult<Option<Vec<u8>>> {
-        #[cfg(feature = "dev_metrics")] counter!("memory_hub.read.total", 1);
-        let mut futures_vec = Vec::with_capacity(self.backends.len());
-        for be in &self.backends {
-            let k = key.clone();
-            let be = Arc::clone(be);
-            futures_vec.push(async move { be.read(k).await });
+        self.read_as(&Principal::anonymous(), key).await
+    }
+
+    pub(crate) async fn read_as(&self, who: &Principal, key: String) -> HubResult<Option<Vec<u8>>> {
+        #[cfg(feature = "dev_metrics")] counter!("memory_hub.read.total").increment(1);
+        let (audit, redact) = self.authorize_redacted(who, Action::Read, Some(&key), None)?;
+        let name = key.clone();
+        let res = async {
+            let settings = self.settings.load_full();
+            let targets = named_of(&self.route(Some(&key), |_| true));
+            let repairs = matches!(settings.read_strategy, ReadStrategy::NewestWins | ReadStrategy::Quorum(_));
+            if !(settings.read_repair && repairs) {
+                return match strategy::read(settings.read_strategy, &targets, key.clone()).await? {
+                    Some(value) => Ok(Some(value)),
+                    None => strategy::read(ReadStrategy::Priority, &named_of(&self.fallbacks(&key)), key).await,
+                };
+            }
+            let winner = match strategy::read_record(settings.read_strategy, &targets, key.clone()).await? {
+                Some(record) => Some(record),
+                // repairing with a fallback's copy moves it back to the routed backends
+                None => strategy::read_record(ReadStrategy::Priority, &named_of(&self.fallbacks(&key)), key.clone()).await?,
+            };
+            let value = winner.as_ref().map(|r| r.value.clone());
+            if let Some(winner) = winner {
+                let replicas = named_of(&self.route(Some(&key), |c| c.compare_and_swap));
+                repair::spawn_read_repair(replicas, key, winner, settings.read_strategy);
+            }
+            Ok(value)
+        }.await;
+        audit.finish(res.and_then(|value| value.map(|v| policy::redact(Action::Read, &name, v, &redact)).transpose()))
+    }
+
+    /// Retrieve the value with its metadata envelope, merged according to the
+    /// configured [`ReadStrategy`], asking the key's fallbacks on a miss as
+    /// [`read`](Self::read) does. Values written before envelopes existed
+    /// report version `0`.
+    pub async fn read_with_meta(&self, key: String) -> HubResult<Option<Record>> {
+        self.read_with_meta_as(&Principal::anonymous(), key).await
+    }
+
+    pub(crate) async fn read_with_meta_as(&self, who: &Principal, key: String) -> HubResult<Option<Record>> {
+        #[cfg(feature = "dev_metrics")] counter!("memory_hub.read.total").increment(1);
+        let (audit, redact) = self.authorize_redacted(who, Action::Read, Some(&key), None)?;
+        let targets = named_of(&self.route(Some(&key), |_| true));
+        let res = match strategy::read_record(self.settings.load().read_strategy, &targets, key.clone()).await {
+            Ok(None) => strategy::read_record(ReadStrategy::Priority, &named_of(&self.fallbacks(&key)), key.clone()).await,
+            res => res,
+        };
+        audit.finish(res.and_then(|record| record.map(|Record { value, mut meta }| {
+            if redact.is_empty() {
+                return Ok(Record { value, meta });
+            }
+            let value = policy::redact(Action::Read, &key, value, &redact)?;
+            meta.checksum = crc32fast::hash(&value);
+            Ok(Record { value, meta })
+        }).transpose()))
+    }
+
+    /// Run one anti-entropy pass: compare per-prefix Merkle digests of all
+    /// usable scan-capable backends and rewrite keys whose copies differ with the record chosen by
+    /// the read strategy, as read-repair does. Buckets are the first `prefix_len` characters of keys.
+    /// Keys are only compared and repaired on the backends they are routed to.
+    #[cfg(feature = "merkle_log")]
+    pub async fn anti_entropy(&self, prefix_len: usize) -> HubResult<repair::RepairReport> {
+        let targets = self.route(None, |c| c.scan);
+        let (table, router) = (self.table.load_full(), self.routes.load_full());
+        let owns = |idx: usize, key: &str| {
+            pick(&table, &router, key, |c| c.scan).0.iter().any(|s| Arc::ptr_eq(s, &targets[idx]))
+        };
+        repair::anti_entropy(&named_of(&targets), self.settings.load().read_strategy, prefix_len, &owns).await
+    }
+
+    /// Run [`anti_entropy`](Self::anti_entropy) every `interval` until the returned token is cancelled.
+    #[cfg(feature = "merkle_log")]
+    pub fn spawn_anti_entropy(self: &Arc<Self>, interval: Duration, prefix_len: usize) -> CancellationToken {
+        let token = CancellationToken::new();
+        let (hub, stop) = (Arc::clone(self), token.clone());
+        runtime::spawn(async move {
+            while !stop.is_cancelled() {
+                runtime::sleep(interval).await;
+                if stop.is_cancelled() { break; }
+                // a failed pass is retried on the next tick
+                let _ = hub.anti_entropy(prefix_len).await;
+            }
+        });
+        token
+    }
+
+    /// Store a batch of entries in every back-end they are routed to.
+    /// Each backend receives its share of the batch through its native batch path.
+    /// Returns one result per item: `Ok` only if every target backend stored it.
+    /// Items the policy refuses or that would exceed a quota fail on their
+    /// own and are not written; items no usable backend is routed fail with
+    /// [`HubError::Unsupported`].
+    pub async fn write_many(&self, items: &[(String, Vec<u8>)]) -> Vec<HubResult<()>> {
+        self.write_many_as(&Principal::anonymous(), items).await
+    }
+
+    pub(crate) async fn write_many_as(&self, who: &Principal, items: &[(String, Vec<u8>)]) -> Vec<HubResult<()>> {
+        #[cfg(feature = "dev_metrics")] counter!("memory_hub.write.total").increment(items.len() as u64);
+        let mut out: Vec<HubResult<()>> = (0..items.len()).map(|_| Ok(())).collect();
+        let mut charges = Vec::with_capacity(items.len());
+        let mut audits = Vec::with_capacity(items.len());
+        let mut accepted = Vec::with_capacity(items.len());
+        for (i, (key, value)) in items.iter().enumerate() {
+            let audit = match self.authorize(who, Action::Write, Some(key), Some(value.len())) {
+                Ok(audit) => audit,
+                Err(e) => { charges.push(None); audits.push(None); out[i] = Err(e); continue; }
+            };
+            match self.charge(&[(key, Some(value.len()))]) {
+                Ok(charge) => { charges.push(Some(charge)); accepted.push(i); }
+                Err(e) => { charges.push(None); out[i] = Err(e); }
+            }
+            audits.push(Some(audit));
+        }
+        let batch: Cow<[(String, Vec<u8>)]> = if accepted.len() == items.len() {
+            Cow::Borrowed(items)
+        } else {
+            Cow::Owned(accepted.iter().map(|&i| items[i].clone()).collect())
+        };
+        let batch = &batch;
+        let _tickets: Vec<_> = batch.iter().map(|(key, _)| self.sequencer.issue(key)).collect();
+        let meta = WriteMeta { version: Some(self.sequencer.stamp()), ..Default::default() };
+        let meta = &meta;
+        let groups = self.partition(batch.iter().map(|(k, _)| k.as_str()));
+        let mut routed = vec![false; batch.len()];
+        for &j in groups.iter().flat_map(|(_, members)| members) {
+            routed[j] = true;
+        }
+        if !self.table.load().slots.is_empty() {
+            for (j, (key, _)) in batch.iter().enumerate().filter(|&(j, _)| !routed[j]) {
+                out[accepted[j]] = Err(HubError::Unsupported(format!("no usable backend supports writes for {key}")));
+            }
+        }
+        let results = join_all(groups.iter().map(|(slot, members)| async move {
+            let share: Cow<[(String, Vec<u8>)]> = if members.len() == batch.len() {
+                Cow::Borrowed(batch)
+            } else {
+                Cow::Owned(members.iter().map(|&j| batch[j].clone()).collect())
+            };
+            slot.backend.write_many_with_meta(&share, meta.clone()).await
+        })).await;
+        let mut stored = vec![false; items.len()];
+        for ((slot, members), res) in groups.iter().zip(results) {
+            let mut res = res.into_iter();
+            for &j in members {
+                let r = res.next().unwrap_or_else(|| Err(HubError::Corruption("backend returned incomplete batch".into()).in_backend(&slot.name)));
+                let i = accepted[j];
+                if r.is_err() { slot.miss(&batch[j].0); } else { stored[i] = true; }
+                if out[i].is_ok() { out[i] = r; }
+            }
+        }
+        // keys with nowhere to go failed outright and are not replayed
+        for ((key, _), _) in batch.iter().zip(&routed).filter(|(_, routed)| **routed) {
+            self.missed(key, |_| true, true, []);
+        }
+        for (((res, charge), audit), stored) in out.iter().zip(charges).zip(audits).zip(stored) {
+            if let Some(charge) = charge {
+                self.settle(charge, stored);
+            }
+            if let Some(audit) = audit {
+                audit.record(res);
+            }
+        }
+        out
+    }
+
+    /// Read a batch of keys from back-ends concurrently, each from the
+    /// backends it is routed to. Per key, the highest-priority backend holding a value wins.
+    /// Keys none of them holds are looked up in the key's fallbacks.
+    /// Keys the policy refuses fail on their own.
+    pub async fn read_many(&self, keys: &[String]) -> Vec<HubResult<Option<Vec<u8>>>> {
+        self.read_many_as(&Principal::anonymous(), keys).await
+    }
+
+    pub(crate) async fn read_many_as(&self, who: &Principal, keys: &[String]) -> Vec<HubResult<Option<Vec<u8>>>> {
+        #[cfg(feature = "dev_metrics")] counter!("memory_hub.read.total").increment(keys.len() as u64);
+        let mut out: Vec<HubResult<Option<Vec<u8>>>> = (0..keys.len()).map(|_| Ok(None)).collect();
+        let mut allowed = Vec::with_capacity(keys.len());
+        let mut audits = Vec::with_capacity(keys.len());
+        for (i, key) in keys.iter().enumerate() {
+            match self.authorize_redacted(who, Action::Read, Some(key), None) {
+                Ok(audit) => { allowed.push(i); audits.push(audit); }
+                Err(e) => out[i] = Err(e),
+            }
+        }
+        let batch: Cow<[String]> = if allowed.len() == keys.len() {
+            Cow::Borrowed(keys)
+        } else {
+            Cow::Owned(allowed.iter().map(|&i| keys[i].clone()).collect())
+        };
+        let batch = &batch;
+        let groups = self.partition(batch.iter().map(String::as_str));
+        let results = join_all(groups.iter().map(|(slot, members)| async move {
+            let share: Cow<[String]> = if members.len() == batch.len() {
+                Cow::Borrowed(batch)
+            } else {
+                Cow::Owned(members.iter().map(|&j| batch[j].clone()).collect())
+            };
+            slot.backend.read_many(&share).await
+        })).await;
+        for ((slot, members), res) in groups.iter().zip(results) {
+            let mut res = res.into_iter();
+            for &j in members {
+                let r = res.next().unwrap_or_else(|| Err(HubError::Corruption("backend returned incomplete batch".into()).in_backend(&slot.name)));
+                let i = allowed[j];
+                if matches!(out[i], Ok(None)) { out[i] = r; }
+            }
         }
+        for &i in &allowed {
+            if matches!(out[i], Ok(None)) {
+                let fallbacks = self.fallbacks(&keys[i]);
+                if !fallbacks.is_empty() {
+                    out[i] = strategy::read(ReadStrategy::Priority, &named_of(&fallbacks), keys[i].clone()).await;
+                }
+            }
+        }
+        for (&i, (audit, redact)) in allowed.iter().zip(audits) {
+            if let Ok(Some(value)) = &mut out[i] && !redact.is_empty() {
+                out[i] = policy::redact(Action::Read, &keys[i], std::mem::take(value), &redact).map(Some);
+            }
+            audit.record(&out[i]);
+        }
+        out
+    }
+
+    /// Remove the key from every usable backend, whether or not it is routed
+    /// there, so copies left behind by earlier rules go too.
+    /// Returns `true` if any backend held it.
+    pub async fn delete(&self, key: String) -> HubResult<bool> {
+        self.delete_as(&Principal::anonymous(), key).await
+    }
+
+    pub(crate) async fn delete_as(&self, who: &Principal, key: String) -> HubResult<bool> {
+        let audit = self.authorize(who, Action::Delete, Some(&key), None)?;
+        audit.finish(async {
+            let charge = self.charge(&[(&key, None)])?;
+            // supersedes background retries of earlier writes, which would bring the key back
+            let _ticket = self.sequencer.issue(&key);
+            let targets = self.route(None, |_| true);
+            let results = join_all(targets.iter().map(|s| s.backend.delete(key.clone()))).await;
+            let failed = targets.iter().zip(&results).filter(|(_, r)| r.is_err()).map(|(s, _)| s.name.as_str());
+            self.missed(&key, |_| true, false, failed);
+            self.settle(charge, results.iter().any(Result::is_ok));
+            Ok(collect_all(&targets, results)?.contains(&true))
+        }.await)
+    }
 
-        // Drive all futures concurrently.
-        let results = join_all(futures_vec).await;
-        // First Some wins.
-        for res in results {
-            match res? {
-                Some(v) => return Ok(Some(v)),
-                None => continue,
+    /// Check whether any backend the key is routed to, or one of its
+    /// fallbacks, holds it.
+    pub async fn contains(&self, key: String) -> HubResult<bool> {
+        self.contains_as(&Principal::anonymous(), key).await
+    }
+
+    pub(crate) async fn contains_as(&self, who: &Principal, key: String) -> HubResult<bool> {
+        let audit = self.authorize(who, Action::Read, Some(&key), None)?;
+        audit.finish(async {
+            for targets in [self.route(Some(&key), |_| true), self.fallbacks(&key)] {
+                let results = join_all(targets.iter().map(|s| s.backend.contains(key.clone()))).await;
+                if collect_all(&targets, results)?.contains(&true) {
+                    return Ok(true);
+                }
+            }
+            Ok(false)
+        }.await)
+    }
+
+    /// Stream key/value pairs from all scan-capable back-ends merged in key order.
+    /// Keys come only from backends their routing rule names, fallbacks
+    /// included. A key held by several backends is yielded once, with the value
+    /// of the highest-priority backend it is routed to, or else of a fallback.
+    pub fn scan(&self, range: ScanRange) -> EntryStream {
+        self.scan_as(&Principal::anonymous(), range)
+    }
+
+    pub(crate) fn scan_as(&self, who: &Principal, range: ScanRange) -> EntryStream {
+        match self.authorize_scan(who, &range) {
+            Ok((audit, redact)) => {
+                let strip = |(k, v): (String, Vec<u8>), fields: &[String]| Ok((k.clone(), policy::redact(Action::Scan, &k, v, fields)?));
+                audit.stream(self.permitted(who, self.scan_unchecked(range), |(k, _)| k, strip, redact), |(k, _)| k)
             }
+            Err(e) => stream::once(async move { Err(e) }).boxed(),
         }
-        Ok(None)
     }
-} 
\ No newline at end of file
+
+    fn scan_unchecked(&self, range: ScanRange) -> EntryStream {
+        let targets = match self.targets(None, |c| c.scan, "scan") {
+            Ok(targets) => targets,
+            Err(e) => return stream::once(async move { Err(e) }).boxed(),
+        };
+        let streams = targets.iter().map(|s| s.backend.scan(range.clone())).collect();
+        self.merge_routed(&targets, streams, |(k, _)| k)
+    }
+
+    /// Stream the de-duplicated union of keys from all scan-capable back-ends in order.
+    pub fn scan_keys(&self, range: ScanRange) -> KeyStream {
+        self.scan_keys_as(&Principal::anonymous(), range)
+    }
+
+    pub(crate) fn scan_keys_as(&self, who: &Principal, range: ScanRange) -> KeyStream {
+        let audit = match self.authorize_scan(who, &range) {
+            Ok((audit, _)) => audit,
+            Err(e) => return stream::once(async move { Err(e) }).boxed(),
+        };
+        let targets = match self.targets(None, |c| c.scan, "scan") {
+            Ok(targets) => targets,
+            Err(e) => return audit.stream(stream::once(async move { Err(e) }).boxed(), |k| k),
+        };
+        let streams = targets.iter().map(|s| s.backend.scan_keys(range.clone())).collect();
+        audit.stream(self.permitted(who, self.merge_routed(&targets, streams, |k| k), |k| k, |k, _| Ok(k), Vec::new()), |k| k)
+    }
+
+    /// Leave out the keys of a scan by `who` the policy does not allow `who`
+    /// to scan, checked one by one: the range check up front only sees the
+    /// prefix or lower bound. Items kept go through `redact` with the fields
+    /// of their own decision and the range's `redacted`; items that cannot
+    /// be redacted are left out too.
+    fn permitted<T: Send + 'static>(
+        &self,
+        who: &Principal,
+        stream: BoxStream<'static, HubResult<T>>,
+        key_of: fn(&T) -> &String,
+        redact: fn(T, &[String]) -> HubResult<T>,
+        redacted: Vec<String>,
+    ) -> BoxStream<'static, HubResult<T>> {
+        let Some(policy) = self.settings.load().policy.clone() else { return stream };
+        let principal = who.clone();
+        stream.filter_map(move |item| futures::future::ready(match item {
+            Ok(item) => {
+                let key = key_of(&item);
+                let ctx = serde_json::json!({ "key": key, "namespace": namespace::tenant_of(key), "size": null, "principal": principal });
+                let mut decision = policy.evaluate(Action::Scan.as_str(), &ctx);
+                decision.redact.extend(redacted.iter().cloned());
+                decision.allow.then(|| redact(item, &decision.redact).ok()).flatten().map(Ok)
+            }
+            Err(e) => Some(Err(e)),
+        })).boxed()
+    }
+
+    /// Merge the scans of `targets` with [`merge_sorted`], dropping keys a
+    /// backend holds although they are not routed to it and ranking routed
+    /// backends ahead of fallbacks.
+    fn merge_routed<T: Send + 'static>(
+        &self,
+        targets: &[Arc<Slot>],
+        streams: Vec<BoxStream<'static, HubResult<T>>>,
+        key_of: fn(&T) -> &String,
+    ) -> BoxStream<'static, HubResult<T>> {
+        let router = self.routes.load_full();
+        let names: Arc<Vec<String>> = Arc::new(targets.iter().map(|s| s.name.clone()).collect());
+        let streams = streams.into_iter().enumerate().map(|(idx, s)| {
+            let (router, names) = (Arc::clone(&router), Arc::clone(&names));
+            s.filter(move |res| futures::future::ready(match res {
+                Ok(item) => placement(&router, &names[idx], key_of(item)).is_some(),
+                Err(_) => true,
+            })).boxed()
+        }).collect();
+        merge_sorted(streams, key_of, move |idx, key| placement(&router, &names[idx], key).unwrap_or(u8::MAX))
+    }
+}
+
+/// K-way merge of key-sorted streams, dropping duplicate keys.
+/// On duplicates the item from the stream `rank` puts first wins, the
+/// earliest stream among equals.
+fn merge_sorted<T: Send + 'static>(
+    streams: Vec<BoxStream<'static, HubResult<T>>>,
+    key_of: fn(&T) -> &String,
+    rank: impl Fn(usize, &str) -> u8 + Send + 'static,
+) -> BoxStream<'static, HubResult<T>> {
+    let heads: Vec<_> = streams.into_iter().map(|s| s.peekable()).collect();
+    stream::unfold((heads, rank), move |(mut heads, rank)| async move {
+        let mut min: Option<String> = None;
+        for head in heads.iter_mut() {
+            let is_err = match Pin::new(&mut *head).peek().await {
+                Some(Ok(item)) => {
+                    let k = key_of(item);
+                    if min.as_ref().is_none_or(|m| k < m) { min = Some(k.clone()); }
+                    false
+                }
+                Some(Err(_)) => true,
+                None => false,
+            };
+            // surface backend errors as soon as they reach the head
+            if is_err {
+                let err = head.next().await?;
+                return Some((err, (heads, rank)));
+            }
+        }
+        let min = min?;
+        let mut winner: Option<(u8, HubResult<T>)> = None;
+        for (idx, head) in heads.iter_mut().enumerate() {
+            let same = matches!(Pin::new(&mut *head).peek().await, Some(Ok(item)) if *key_of(item) == min);
+            if same {
+                let item = head.next().await;
+                let r = rank(idx, &min);
+                if let Some(item) = item.filter(|_| winner.as_ref().is_none_or(|(best, _)| r < *best)) {
+                    winner = Some((r, item));
+                }
+            }
+        }
+        winner.map(|(_, w)| (w, (heads, rank)))
+    }).boxed()
+}
@@ -0,0 +1,165 @@
+//! Encryption keys identified by number, and the header recording which
+//! key and format produced a ciphertext.
+use crate::backend::HubResult;
+use crate::error::HubError;
+use crate::keys::{KeyProvider, SecretKey};
+use crate::namespace;
+use aes_gcm_siv::{aead::KeyInit, Aes256GcmSiv};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Leading bytes of a ciphertext: magic `CV`, format version and the
+/// big-endian id of the key that encrypted it. Values written before headers
+/// existed have none and count as encrypted with key id `0`.
+pub(crate) const HEADER_LEN: usize = 7;
+const MAGIC: &[u8; 2] = b"CV";
+/// AES-256-GCM-SIV, 12-byte random nonce ahead of the ciphertext.
+#[cfg(feature = "longmem_sled")]
+pub(crate) const FORMAT_V1: u8 = 1;
+/// As [`FORMAT_V1`], with the value's key name and namespace bound in as
+/// associated data.
+#[cfg(feature = "longmem_sled")]
+pub(crate) const FORMAT_V2: u8 = 2;
+/// STREAM over AES-256-GCM-SIV in 64 KiB chunks after a 7-byte random
+/// nonce prefix, each chunk bound like [`FORMAT_V2`].
+#[cfg(feature = "detailmem_encrypt")]
+pub(crate) const FORMAT_STREAM: u8 = 3;
+/// HKDF salt separating tenant keys from other keys derived from a database key.
+const TENANT_KEY_SALT: &[u8] = b"cognivault/tenant-key/v2";
+/// Id headerless values are taken to be encrypted with.
+#[cfg(feature = "longmem_sled")]
+pub(crate) const LEGACY_KEY_ID: u32 = 0;
+
+pub(crate) fn header(version: u8, key_id: u32) -> [u8; HEADER_LEN] {
+    let mut out = [0; HEADER_LEN];
+    out[..2].copy_from_slice(MAGIC);
+    out[2] = version;
+    out[3..].copy_from_slice(&key_id.to_be_bytes());
+    out
+}
+
+/// Format version, key id and the bytes after the header, if `data` starts
+/// with one. A headerless value may start with the magic by chance, so
+/// callers fall back to the legacy layout when a `FORMAT_V1` value does
+/// not decrypt; `FORMAT_V2` values are never read unbound.
+pub(crate) fn split_header(data: &[u8]) -> Option<(u8, u32, &[u8])> {
+    if data.len() < HEADER_LEN || &data[..2] != MAGIC {
+        return None;
+    }
+    let key_id = u32::from_be_bytes(data[3..HEADER_LEN].try_into().ok()?);
+    Some((data[2], key_id, &data[HEADER_LEN..]))
+}
+
+/// Associated data binding a ciphertext of `domain` to its key: the
+/// namespace, empty outside namespaces, and the full key name.
+pub(crate) fn associated_data(domain: &str, key: &str) -> Vec<u8> {
+    let tenant = namespace::tenant_of(key).unwrap_or_default();
+    [domain.as_bytes(), b"\0", tenant.as_bytes(), b"\0", key.as_bytes()].concat()
+}
+
+/// 256-bit keys by id, one of them active. Encryption uses the active key;
+/// decryption uses whichever key the ciphertext header names. Key material
+/// is wiped on drop.
+#[derive(Clone)]
+pub struct Keyring {
+    keys: BTreeMap<u32, (SecretKey, Aes256GcmSiv)>,
+    active: u32,
+}
+
+impl Keyring {
+    /// Keyring holding `key` under `id`, active.
+    pub fn new(id: u32, key: impl Into<SecretKey>) -> Self {
+        let key = key.into();
+        let cipher = Aes256GcmSiv::new(key.expose().into());
+        Self { keys: BTreeMap::from([(id, (key, cipher))]), active: id }
+    }
+
+    /// Every key `provider` holds for `scope`, its active key active.
+    pub fn from_provider(provider: &dyn KeyProvider, scope: &str) -> HubResult<Self> {
+        let active = provider.active_id(scope)?;
+        let mut ring = Self::new(active, provider.data_key(scope, active)?);
+        for id in provider.key_ids(scope)? {
+            if id != active {
+                ring.add(id, provider.data_key(scope, id)?)?;
+            }
+        }
+        Ok(ring)
+    }
+
+    /// Add `key` under `id` without activating it. Fails with
+    /// [`HubError::Conflict`] if `id` already holds a different key.
+    pub fn add(&mut self, id: u32, key: impl Into<SecretKey>) -> HubResult<()> {
+        let key = key.into();
+        match self.keys.get(&id) {
+            Some((known, _)) if *known != key => Err(HubError::Conflict(format!("key id {id} already holds a different key"))),
+            Some(_) => Ok(()),
+            None => {
+                let cipher = Aes256GcmSiv::new(key.expose().into());
+                self.keys.insert(id, (key, cipher));
+                Ok(())
+            }
+        }
+    }
+
+    pub fn with_key(mut self, id: u32, key: impl Into<SecretKey>) -> HubResult<Self> {
+        self.add(id, key)?;
+        Ok(self)
+    }
+
+    /// Encrypt new values with key `id`, which must have been added.
+    pub fn set_active(&mut self, id: u32) -> HubResult<()> {
+        if !self.keys.contains_key(&id) {
+            return Err(HubError::NotFound(format!("key id {id}")));
+        }
+        self.active = id;
+        Ok(())
+    }
+
+    pub fn active(&self) -> u32 {
+        self.active
+    }
+
+    pub fn ids(&self) -> impl Iterator<Item = u32> + '_ {
+        self.keys.keys().copied()
+    }
+
+    pub fn contains(&self, id: u32) -> bool {
+        self.keys.contains_key(&id)
+    }
+
+    /// Cipher of namespace `tenant` under key `id`, derived from that key
+    /// with HKDF-SHA256, the tenant name as info.
+    pub(crate) fn tenant_cipher(&self, id: u32, tenant: &str) -> Option<Aes256GcmSiv> {
+        use hkdf::Hkdf;
+        use sha2::Sha256;
+        use zeroize::Zeroize;
+        let (master, _) = self.keys.get(&id)?;
+        let mut derived = [0u8; 32];
+        Hkdf::<Sha256>::new(Some(TENANT_KEY_SALT), master.expose())
+            .expand(tenant.as_bytes(), &mut derived)
+            .expect("32 bytes is a valid HKDF-SHA256 output length");
+        let cipher = Aes256GcmSiv::new(&derived.into());
+        derived.zeroize();
+        Some(cipher)
+    }
+
+    /// Cipher protecting `key` under key `id`: the tenant's for namespaced
+    /// keys, the keyring's otherwise.
+    pub(crate) fn cipher_for(&self, id: u32, key: &str) -> HubResult<Aes256GcmSiv> {
+        let unknown = || HubError::Crypto(format!("{key} is encrypted with unknown key id {id}"));
+        match namespace::tenant_of(key) {
+            Some(tenant) => self.tenant_cipher(id, tenant).ok_or_else(unknown),
+            None => self.cipher(id).cloned().ok_or_else(unknown),
+        }
+    }
+
+    pub(crate) fn cipher(&self, id: u32) -> Option<&Aes256GcmSiv> {
+        self.keys.get(&id).map(|(_, cipher)| cipher)
+    }
+}
+
+impl fmt::Debug for Keyring {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Keyring").field("ids", &self.keys.keys().collect::<Vec<_>>()).field("active", &self.active).finish()
+    }
+}
@@ -0,0 +1,377 @@
+//! Where encryption keys come from: [`KeyProvider`] and its implementations,
+//! so raw keys need not sit in configuration.
+use crate::backend::HubResult;
+use crate::error::HubError;
+use aes_gcm_siv::aead::{rand_core::RngCore, Aead, KeyInit, OsRng, Payload};
+use aes_gcm_siv::Aes256GcmSiv;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+/// 256-bit key material, wiped from memory on drop.
+#[derive(Clone, PartialEq, Eq, Zeroize, ZeroizeOnDrop)]
+pub struct SecretKey([u8; 32]);
+
+impl SecretKey {
+    pub fn new(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+
+    /// Fresh random key.
+    pub fn generate() -> Self {
+        let mut key = Self([0; 32]);
+        OsRng.fill_bytes(&mut key.0);
+        key
+    }
+
+    /// Parse 64 hex digits; surrounding whitespace is ignored.
+    pub fn from_hex(text: &str) -> HubResult<Self> {
+        let mut key = Self([0; 32]);
+        hex::decode_to_slice(text.trim(), &mut key.0)
+            .map_err(|e| HubError::InvalidInput(format!("key is not 64 hex digits: {e}")))?;
+        Ok(key)
+    }
+
+    pub fn expose(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl From<[u8; 32]> for SecretKey {
+    fn from(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+}
+
+impl fmt::Debug for SecretKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SecretKey(..)")
+    }
+}
+
+/// Source of the data keys protecting stored values.
+///
+/// Keys are numbered per scope, e.g. a database path or tree name, as in a
+/// [`Keyring`](crate::Keyring). Providers with a single key serve it as id
+/// `0` for every scope.
+pub trait KeyProvider: Send + Sync {
+    /// Key `id` of `scope`.
+    fn data_key(&self, scope: &str, id: u32) -> HubResult<SecretKey>;
+
+    /// Id of the key new data of `scope` is encrypted with.
+    fn active_id(&self, _scope: &str) -> HubResult<u32> {
+        Ok(0)
+    }
+
+    /// Ids of every key of `scope` data may be encrypted with, the active one included.
+    fn key_ids(&self, scope: &str) -> HubResult<Vec<u32>> {
+        Ok(vec![self.active_id(scope)?])
+    }
+}
+
+fn single(key: &SecretKey, id: u32) -> HubResult<SecretKey> {
+    match id {
+        0 => Ok(key.clone()),
+        _ => Err(HubError::NotFound(format!("key id {id}"))),
+    }
+}
+
+/// One fixed key, given directly or read from an environment variable or file.
+#[derive(Debug, Clone)]
+pub struct StaticKey(SecretKey);
+
+impl StaticKey {
+    pub fn new(key: SecretKey) -> Self {
+        Self(key)
+    }
+
+    /// Key held as 64 hex digits in the environment variable `var`.
+    pub fn from_env(var: &str) -> HubResult<Self> {
+        let text = Zeroizing::new(std::env::var(var).map_err(|e| HubError::NotFound(format!("key variable {var}: {e}")))?);
+        Ok(Self(SecretKey::from_hex(&text)?))
+    }
+
+    /// Key held in `path` as 32 raw bytes or 64 hex digits.
+    pub fn from_file(path: impl AsRef<Path>) -> HubResult<Self> {
+        let bytes = Zeroizing::new(std::fs::read(path.as_ref())?);
+        if let Ok(raw) = <[u8; 32]>::try_from(bytes.as_slice()) {
+            return Ok(Self(SecretKey(raw)));
+        }
+        let text = std::str::from_utf8(&bytes)
+            .map_err(|_| HubError::InvalidInput(format!("key file {} holds neither 32 bytes nor hex", path.as_ref().display())))?;
+        Ok(Self(SecretKey::from_hex(text)?))
+    }
+}
+
+impl KeyProvider for StaticKey {
+    fn data_key(&self, _scope: &str, id: u32) -> HubResult<SecretKey> {
+        single(&self.0, id)
+    }
+}
+
+/// Key derivation function of a [`PassphraseKey`].
+#[cfg(feature = "key_derivation")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kdf {
+    Argon2id { memory_kib: u32, iterations: u32, parallelism: u32 },
+    /// Cost `2^log_n`.
+    Scrypt { log_n: u8, r: u32, p: u32 },
+}
+
+#[cfg(feature = "key_derivation")]
+impl Default for Kdf {
+    /// Argon2id with the parameters recommended by OWASP.
+    fn default() -> Self {
+        Kdf::Argon2id { memory_kib: 19 * 1024, iterations: 2, parallelism: 1 }
+    }
+}
+
+/// Key derived from a passphrase and salt. The salt, at least 8 bytes, is
+/// not secret but must stay the same for the data to stay readable.
+#[cfg(feature = "key_derivation")]
+#[derive(Debug, Clone)]
+pub struct PassphraseKey(SecretKey);
+
+#[cfg(feature = "key_derivation")]
+impl PassphraseKey {
+    pub fn derive(passphrase: &[u8], salt: &[u8], kdf: Kdf) -> HubResult<Self> {
+        let invalid = |e: &dyn fmt::Display| HubError::InvalidInput(format!("key derivation: {e}"));
+        let mut key = SecretKey([0; 32]);
+        match kdf {
+            Kdf::Argon2id { memory_kib, iterations, parallelism } => {
+                let params = argon2::Params::new(memory_kib, iterations, parallelism, Some(32)).map_err(|e| invalid(&e))?;
+                argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
+                    .hash_password_into(passphrase, salt, &mut key.0)
+                    .map_err(|e| invalid(&e))?;
+            }
+            Kdf::Scrypt { log_n, r, p } => {
+                if salt.len() < 8 {
+                    return Err(invalid(&"salt shorter than 8 bytes"));
+                }
+                let params = scrypt::Params::new(log_n, r, p, 32).map_err(|e| invalid(&e))?;
+                scrypt::scrypt(passphrase, salt, &params, &mut key.0).map_err(|e| invalid(&e))?;
+            }
+        }
+        Ok(Self(key))
+    }
+}
+
+#[cfg(feature = "key_derivation")]
+impl KeyProvider for PassphraseKey {
+    fn data_key(&self, _scope: &str, id: u32) -> HubResult<SecretKey> {
+        single(&self.0, id)
+    }
+}
+
+/// Key management service holding a master key that never leaves it.
+pub trait Kms: Send + Sync {
+    /// Encrypt `key` under the master key. The same `context` must be passed
+    /// to [`unwrap`](Self::unwrap).
+    fn wrap(&self, key: &SecretKey, context: &str) -> HubResult<Vec<u8>>;
+
+    /// Decrypt a key wrapped with `context`. Fails with
+    /// [`HubError::Integrity`] if `wrapped` or `context` do not match.
+    fn unwrap(&self, wrapped: &[u8], context: &str) -> HubResult<SecretKey>;
+}
+
+/// In-process stand-in for an external [`Kms`], wrapping with
+/// AES-256-GCM-SIV under a local master key. For tests and development.
+pub struct MockKms {
+    master: Aes256GcmSiv,
+    calls: AtomicUsize,
+}
+
+impl MockKms {
+    pub fn new(master: SecretKey) -> Self {
+        Self { master: Aes256GcmSiv::new(master.expose().into()), calls: AtomicUsize::new(0) }
+    }
+
+    /// Number of wrap and unwrap requests served so far.
+    pub fn calls(&self) -> usize {
+        self.calls.load(Ordering::Relaxed)
+    }
+}
+
+impl Kms for MockKms {
+    fn wrap(&self, key: &SecretKey, context: &str) -> HubResult<Vec<u8>> {
+        self.calls.fetch_add(1, Ordering::Relaxed);
+        let mut nonce = [0u8; 12];
+        OsRng.fill_bytes(&mut nonce);
+        let ct = self.master.encrypt(&nonce.into(), Payload { msg: key.expose(), aad: context.as_bytes() })
+            .map_err(|_| HubError::Crypto("key wrapping failed".into()))?;
+        Ok([&nonce[..], &ct].concat())
+    }
+
+    fn unwrap(&self, wrapped: &[u8], context: &str) -> HubResult<SecretKey> {
+        self.calls.fetch_add(1, Ordering::Relaxed);
+        if wrapped.len() < 12 {
+            return Err(HubError::Corruption(format!("wrapped key for {context} too short")));
+        }
+        let (nonce, ct) = wrapped.split_at(12);
+        let plain = Zeroizing::new(self.master.decrypt(nonce.into(), Payload { msg: ct, aad: context.as_bytes() })
+            .map_err(|_| HubError::Integrity(format!("wrapped key for {context} failed authentication")))?);
+        let raw = <[u8; 32]>::try_from(plain.as_slice())
+            .map_err(|_| HubError::Corruption(format!("wrapped key for {context} has the wrong length")))?;
+        Ok(SecretKey(raw))
+    }
+}
+
+/// Envelope encryption: every scope gets random data keys, stored in a JSON
+/// file wrapped by a [`Kms`]. [`create_scope`](Self::create_scope) creates
+/// a scope's key `0` and [`rotate`](Self::rotate) adds the next one; keys of
+/// a scope never created are [`HubError::NotFound`]. Unwrapped keys are
+/// cached, so the KMS is asked once per key.
+pub struct EnvelopeKeys {
+    path: PathBuf,
+    state: Mutex<Envelope>,
+}
+
+struct Envelope {
+    kms: Arc<dyn Kms>,
+    scopes: BTreeMap<String, ScopeKeys>,
+    cache: HashMap<(String, u32), SecretKey>,
+}
+
+/// Wrapped data keys of one scope, as stored.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ScopeKeys {
+    active: u32,
+    /// Hex of the wrapped keys by id.
+    keys: BTreeMap<u32, String>,
+}
+
+fn context(scope: &str, id: u32) -> String {
+    format!("cognivault/data-key/{scope}/{id}")
+}
+
+impl EnvelopeKeys {
+    /// Use the wrapped keys in `path`, created on the first new key.
+    pub fn open(kms: Arc<dyn Kms>, path: impl AsRef<Path>) -> HubResult<Self> {
+        let path = path.as_ref().to_path_buf();
+        let scopes = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self { path, state: Mutex::new(Envelope { kms, scopes, cache: HashMap::new() }) })
+    }
+
+    /// Create `scope` with its data key `0`, active. Fails with
+    /// [`HubError::Conflict`] if the scope exists.
+    pub fn create_scope(&self, scope: &str) -> HubResult<()> {
+        let mut state = self.state.lock().expect("envelope lock");
+        if state.scopes.contains_key(scope) {
+            return Err(HubError::Conflict(format!("key scope {scope} exists")));
+        }
+        self.create(&mut state, scope, 0)?;
+        Ok(())
+    }
+
+    /// Create a new data key for `scope` and make it active. Returns its id.
+    pub fn rotate(&self, scope: &str) -> HubResult<u32> {
+        let mut state = self.state.lock().expect("envelope lock");
+        let last = state.scopes.get(scope).and_then(|s| s.keys.keys().next_back()).ok_or_else(|| unknown(scope))?;
+        let id = last + 1;
+        self.create(&mut state, scope, id)?;
+        Ok(id)
+    }
+
+    /// Wrap every data key anew with `kms`, e.g. to move to a new master key,
+    /// and use it from now on.
+    pub fn rewrap(&self, kms: Arc<dyn Kms>) -> HubResult<()> {
+        let mut state = self.state.lock().expect("envelope lock");
+        let mut scopes = BTreeMap::new();
+        for (scope, keys) in &state.scopes {
+            let mut rewrapped = ScopeKeys { active: keys.active, keys: BTreeMap::new() };
+            for (&id, wrapped) in &keys.keys {
+                let ctx = context(scope, id);
+                let key = state.kms.unwrap(&decode_wrapped(wrapped, &ctx)?, &ctx)?;
+                rewrapped.keys.insert(id, hex::encode(kms.wrap(&key, &ctx)?));
+            }
+            scopes.insert(scope.clone(), rewrapped);
+        }
+        self.persist(&scopes)?;
+        state.scopes = scopes;
+        state.kms = kms;
+        Ok(())
+    }
+
+    fn create(&self, state: &mut Envelope, scope: &str, id: u32) -> HubResult<SecretKey> {
+        let key = SecretKey::generate();
+        let wrapped = hex::encode(state.kms.wrap(&key, &context(scope, id))?);
+        let previous = state.scopes.get(scope).map(|s| s.active);
+        let entry = state.scopes.entry(scope.to_string()).or_default();
+        entry.keys.insert(id, wrapped);
+        entry.active = id;
+        if let Err(e) = self.persist(&state.scopes) {
+            // keep memory in line with the file
+            match previous {
+                Some(active) => {
+                    let entry = state.scopes.entry(scope.to_string()).or_default();
+                    entry.keys.remove(&id);
+                    entry.active = active;
+                }
+                None => { state.scopes.remove(scope); }
+            }
+            return Err(e);
+        }
+        state.cache.insert((scope.to_string(), id), key.clone());
+        Ok(key)
+    }
+
+    /// Write atomically and durably: write and sync a tmp file, rename it
+    /// over the old one, then sync the directory so the rename survives a crash.
+    fn persist(&self, scopes: &BTreeMap<String, ScopeKeys>) -> HubResult<()> {
+        let mut tmp = self.path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let mut file = std::fs::File::create(&tmp)?;
+        file.write_all(&serde_json::to_vec_pretty(scopes)?)?;
+        file.sync_all()?;
+        drop(file);
+        std::fs::rename(&tmp, &self.path)?;
+        let dir = match self.path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        std::fs::File::open(dir)?.sync_all()?;
+        Ok(())
+    }
+}
+
+fn unknown(scope: &str) -> HubError {
+    HubError::NotFound(format!("key scope {scope}"))
+}
+
+fn decode_wrapped(text: &str, context: &str) -> HubResult<Vec<u8>> {
+    hex::decode(text).map_err(|e| HubError::Corruption(format!("wrapped key for {context}: {e}")))
+}
+
+impl KeyProvider for EnvelopeKeys {
+    fn data_key(&self, scope: &str, id: u32) -> HubResult<SecretKey> {
+        let mut state = self.state.lock().expect("envelope lock");
+        if let Some(key) = state.cache.get(&(scope.to_string(), id)) {
+            return Ok(key.clone());
+        }
+        let keys = state.scopes.get(scope).ok_or_else(|| unknown(scope))?;
+        let wrapped = keys.keys.get(&id).ok_or_else(|| HubError::NotFound(format!("key id {id} of {scope}")))?;
+        let ctx = context(scope, id);
+        let key = state.kms.unwrap(&decode_wrapped(wrapped, &ctx)?, &ctx)?;
+        state.cache.insert((scope.to_string(), id), key.clone());
+        Ok(key)
+    }
+
+    fn active_id(&self, scope: &str) -> HubResult<u32> {
+        let state = self.state.lock().expect("envelope lock");
+        state.scopes.get(scope).map(|s| s.active).ok_or_else(|| unknown(scope))
+    }
+
+    fn key_ids(&self, scope: &str) -> HubResult<Vec<u32>> {
+        let state = self.state.lock().expect("envelope lock");
+        state.scopes.get(scope).map(|s| s.keys.keys().copied().collect()).ok_or_else(|| unknown(scope))
+    }
+}
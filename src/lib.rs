@@ -5,27 +5,60 @@
 //!  • Clients/API call [`MemoryHub`] methods.
 //!  • Hub in parallel fan-outs the request to every registered backend that
 //!    implements [`MemoryBackend`] trait.
-//!  • On reads hub merges results using simple first-win policy for now
-//!    (placeholder for loser-tree merge).
+//!  • On reads hub merges results according to a configurable
+//!    [`ReadStrategy`] (priority, first-responder, quorum, newest-wins).
 //!
 //! By default the crate is runtime-agnostic: choose either `runtime_async_std`
 //! (default) or `runtime_tokio` feature at compile time.
 
 mod backend;
+mod error;
+mod record;
+mod txn;
+mod health;
+mod routing;
+mod namespace;
+pub use namespace::Namespace;
+mod quota;
+pub use quota::{Quota, Usage, QuotaStatus, QuotaExceeded, QuotaResource};
+pub use routing::{RoutingConfig, RouteRule, RouteExplanation};
 mod hub;
+mod strategy;
+pub use strategy::{ReadStrategy, WriteConcern, WriteReport, RetryPolicy};
+mod runtime;
+mod tiered;
+mod repair;
+pub use repair::RepairReport;
+pub use tiered::{TieredHub, Tier, TierPolicy, TierStats, TierUsage};
 mod shortmem;
+mod eviction;
+mod ttl;
+pub use eviction::{EvictionPolicy, EvictionListener, CacheStats};
 mod cancellation;
 pub use cancellation::CancellationToken;
 mod limit_guard;
 pub use limit_guard::LimitGuard;
 mod policy;
-pub use policy::{PolicyEngine, AllowAllPolicy};
+pub use policy::{PolicyEngine, AllowAllPolicy, Action, Decision, Principal};
+#[cfg(feature = "opa_policy")] pub use policy::RegoPolicy;
+mod rbac;
+pub use rbac::{RulePolicy, RuleSet, RoleDef, PrincipalDef, Grant, DenyRule, Condition, Weekday, Verdict};
+mod audit;
+pub use audit::{AuditEvent, AuditOutcome, AuditSink, AuditQuery, JsonlAuditSink};
+#[cfg(feature = "merkle_log")] pub use audit::MerkleAuditSink;
+mod session;
+pub use session::Session;
 mod plugin;
 pub use plugin::{PluginLoader, PluginKind};
 #[cfg(feature = "longmem_sled")] mod longmem;
+#[cfg(any(all(feature = "longmem_sled", feature = "longmem_encrypt"), feature = "detailmem_encrypt"))] mod keyring;
+#[cfg(any(all(feature = "longmem_sled", feature = "longmem_encrypt"), feature = "detailmem_encrypt"))] pub use keyring::Keyring;
+#[cfg(feature = "longmem_encrypt")] mod keys;
+#[cfg(feature = "longmem_encrypt")] pub use keys::{KeyProvider, SecretKey, StaticKey, Kms, MockKms, EnvelopeKeys};
+#[cfg(feature = "key_derivation")] pub use keys::{PassphraseKey, Kdf};
 #[cfg(feature = "detailmem_fs")] mod detailmem;
 #[cfg(feature = "detailmem_fs")] pub use detailmem::DetailMem;
-#[cfg(feature = "dev_metrics")] mod observability;
+#[cfg(feature = "dev_metrics")] pub mod observability;
 #[cfg(feature = "dev_metrics")] pub use observability as obs;
 #[cfg(any(feature = "ann_hnsw", feature = "ann_scalar"))] mod ann;
 #[cfg(any(feature = "ann_hnsw", feature = "ann_scalar"))] pub use ann::{AnnEngine, AnnDefault};
@@ -34,23 +67,990 @@ pub use plugin::{PluginLoader, PluginKind};
 
 pub mod sloguard; pub use sloguard::SloGuard;
 
-pub use backend::MemoryBackend;
+pub use backend::{MemoryBackend, HubResult, ScanRange, EntryStream, KeyStream};
+pub use error::HubError;
+pub use record::{Record, RecordMeta, WriteMeta, Expected, CasOutcome};
+pub use txn::{Transaction, TxOp};
+pub use health::{Capabilities, Health, BackendStatus, Readiness};
+pub use shortmem::ShortMem;
 #[cfg(feature = "longmem_sled")] pub use longmem::LongMem;
+#[cfg(all(feature = "longmem_sled", feature = "longmem_encrypt"))] pub use longmem::ReencryptProgress;
 pub use hub::MemoryHub;
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use async_trait::async_trait;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    /// Backend whose first `fail_writes` writes fail.
+    #[derive(Default, Clone)]
+    struct Flaky {
+        inner: Arc<ShortMem>,
+        fail_writes: Arc<AtomicUsize>,
+        down: Arc<std::sync::atomic::AtomicBool>,
+    }
+
+    impl Flaky {
+        fn take_failure(&self) -> HubResult<()> {
+            let left = self.fail_writes.load(Ordering::SeqCst);
+            if left > 0 {
+                self.fail_writes.store(left - 1, Ordering::SeqCst);
+                return Err(HubError::Unavailable("flaky write".into()));
+            }
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl MemoryBackend for Flaky {
+        async fn health(&self) -> Health {
+            if self.down.load(Ordering::SeqCst) { Health::Unhealthy("down".into()) } else { Health::Healthy }
+        }
+        async fn write(&self, key: String, value: Vec<u8>) -> HubResult<()> {
+            self.take_failure()?;
+            self.inner.write(key, value).await
+        }
+        async fn write_with_meta(&self, key: String, value: Vec<u8>, meta: WriteMeta) -> HubResult<()> {
+            self.take_failure()?;
+            self.inner.write_with_meta(key, value, meta).await
+        }
+        async fn read(&self, key: String) -> HubResult<Option<Vec<u8>>> { self.inner.read(key).await }
+        async fn read_record(&self, key: String) -> HubResult<Option<Record>> { self.inner.read_record(key).await }
+        async fn delete(&self, key: String) -> HubResult<bool> { self.inner.delete(key).await }
+    }
 
     #[async_std::test]
     async fn smoke_write_read() {
-        let mut hub = MemoryHub::new();
+        let hub = MemoryHub::new();
         hub.register_backend(Box::new(crate::shortmem::ShortMem::default()));
 
         hub.write("foo".into(), b"bar".to_vec()).await.unwrap();
         let res = hub.read("foo".into()).await.unwrap();
         assert_eq!(res.unwrap(), b"bar".to_vec());
     }
+
+    #[async_std::test]
+    async fn delete_and_merged_scan() {
+        use futures::TryStreamExt;
+        let a = ShortMem::default();
+        let b = ShortMem::default();
+        a.write("k/1".into(), b"a1".to_vec()).await.unwrap();
+        a.write("k/2".into(), b"a2".to_vec()).await.unwrap();
+        b.write("k/2".into(), b"b2".to_vec()).await.unwrap();
+        b.write("k/3".into(), b"b3".to_vec()).await.unwrap();
+        b.write("other".into(), b"x".to_vec()).await.unwrap();
+        let hub = MemoryHub::new();
+        hub.register_backend(Box::new(a));
+        hub.register_backend(Box::new(b));
+
+        let entries: Vec<_> = hub.scan(ScanRange::Prefix("k/".into())).try_collect().await.unwrap();
+        assert_eq!(entries, vec![
+            ("k/1".to_string(), b"a1".to_vec()),
+            ("k/2".to_string(), b"a2".to_vec()),
+            ("k/3".to_string(), b"b3".to_vec()),
+        ]);
+
+        assert!(hub.delete("k/2".into()).await.unwrap());
+        assert!(!hub.contains("k/2".into()).await.unwrap());
+        let keys: Vec<_> = hub.scan_keys(ScanRange::All).try_collect().await.unwrap();
+        assert_eq!(keys, vec!["k/1", "k/3", "other"]);
+    }
+
+    #[async_std::test]
+    async fn batch_write_read() {
+        let hub = MemoryHub::new();
+        hub.register_backend(Box::new(ShortMem::default()));
+        hub.register_backend(Box::new(ShortMem::default()));
+
+        let items: Vec<_> = (0..4).map(|i| (format!("b/{i}"), vec![i as u8])).collect();
+        assert!(hub.write_many(&items).await.iter().all(|r| r.is_ok()));
+        let keys = vec!["b/1".to_string(), "missing".to_string(), "b/3".to_string()];
+        let got: Vec<_> = hub.read_many(&keys).await.into_iter().map(|r| r.unwrap()).collect();
+        assert_eq!(got, vec![Some(vec![1]), None, Some(vec![3])]);
+    }
+
+    #[async_std::test]
+    async fn read_strategies() {
+        let slow_tier = ShortMem::default();
+        let fast_tier = ShortMem::default();
+        slow_tier.write("k".into(), b"old".to_vec()).await.unwrap();
+        fast_tier.write("k".into(), b"new".to_vec()).await.unwrap();
+        let hub = MemoryHub::new();
+        hub.register_backend_with_tier(Box::new(slow_tier), 1);
+        hub.register_backend_with_tier(Box::new(fast_tier), 0);
+
+        assert_eq!(hub.read("k".into()).await.unwrap(), Some(b"new".to_vec()));
+        hub.set_read_strategy(ReadStrategy::Quorum(2));
+        assert!(hub.read("k".into()).await.is_err());
+        assert_eq!(hub.read("absent".into()).await.unwrap(), None);
+        hub.set_read_strategy(ReadStrategy::FirstResponder);
+        assert!(hub.read("k".into()).await.unwrap().is_some());
+    }
+
+    #[async_std::test]
+    async fn write_concern_and_background_retry() {
+        let flaky = Flaky::default();
+        flaky.fail_writes.store(1, Ordering::SeqCst);
+        let hub = MemoryHub::new();
+        let steady = hub.register_backend(Box::new(ShortMem::default()));
+        hub.register_backend(Box::new(flaky.clone()));
+        hub.set_write_retry(RetryPolicy { max_attempts: 3, base_delay: std::time::Duration::from_millis(1) });
+
+        let report = hub.write_with("k".into(), b"v".to_vec(), WriteConcern::Any).await.unwrap();
+        assert!(report.satisfied);
+        assert_eq!(report.succeeded, vec![steady]);
+        // the flaky backend failed once and is retried in the background
+        for _ in 0..100 {
+            if flaky.read("k".into()).await.unwrap().is_some() { break; }
+            async_std::task::sleep(std::time::Duration::from_millis(5)).await;
+        }
+        assert_eq!(flaky.read("k".into()).await.unwrap(), Some(b"v".to_vec()));
+
+        flaky.fail_writes.store(1, Ordering::SeqCst);
+        assert!(hub.write("k2".into(), b"v".to_vec()).await.is_err());
+
+        // a retry overtaken by a newer write of the key is dropped, not replayed over it
+        hub.set_write_retry(RetryPolicy { max_attempts: 3, base_delay: std::time::Duration::from_millis(20) });
+        flaky.fail_writes.store(1, Ordering::SeqCst);
+        hub.write_with("k3".into(), b"v1".to_vec(), WriteConcern::Any).await.unwrap();
+        hub.write_with("k3".into(), b"v2".to_vec(), WriteConcern::All).await.unwrap();
+        async_std::task::sleep(std::time::Duration::from_millis(100)).await;
+        assert_eq!(flaky.read("k3".into()).await.unwrap(), Some(b"v2".to_vec()));
+    }
+
+    #[async_std::test]
+    async fn tiered_placement_promotion_demotion() {
+        let policy = TierPolicy { hot_max_bytes: 4, warm_max_bytes: 16, demote_after: std::time::Duration::ZERO, ..Default::default() };
+        let hub = TieredHub::new(Box::new(ShortMem::default()), Box::new(ShortMem::default()), Box::new(ShortMem::default()), policy);
+        hub.write("small".into(), vec![0; 2]).await.unwrap();
+        hub.write("medium".into(), vec![0; 8]).await.unwrap();
+        hub.write("large".into(), vec![0; 32]).await.unwrap();
+        let stats = hub.stats();
+        assert_eq!((stats.hot.entries, stats.warm.entries, stats.cold.entries), (1, 1, 1));
+        assert_eq!(stats.cold.bytes, 32);
+
+        // a lower-tier hit is promoted to the hot tier
+        assert_eq!(hub.read("medium".into()).await.unwrap(), Some(vec![0; 8]));
+        assert_eq!(hub.stats().hot.entries, 2);
+
+        // idle hot entries move back down
+        assert_eq!(hub.demote_idle().await.unwrap(), 2);
+        let stats = hub.stats();
+        assert_eq!((stats.hot.entries, stats.warm.entries, stats.cold.entries), (0, 2, 1));
+
+        // after a restart the index is empty; a rewrite still drops the old copy
+        let cold = Arc::new(ShortMem::default());
+        cold.write("k".into(), vec![0; 32]).await.unwrap();
+        let hub = TieredHub::new(Box::new(ShortMem::default()), Box::new(ShortMem::default()), Box::new(Flaky { inner: Arc::clone(&cold), ..Default::default() }), TierPolicy::default());
+        hub.write("k".into(), vec![1; 2]).await.unwrap();
+        assert_eq!(cold.read("k".into()).await.unwrap(), None);
+        assert_eq!(hub.read("k".into()).await.unwrap(), Some(vec![1; 2]));
+    }
+
+    #[async_std::test]
+    async fn tier_moves_do_not_overtake_writes() {
+        // a backend that gives other tasks a chance to run mid-operation
+        #[derive(Clone, Default)]
+        struct Slow(Arc<ShortMem>);
+        #[async_trait]
+        impl MemoryBackend for Slow {
+            async fn write(&self, key: String, value: Vec<u8>) -> HubResult<()> {
+                async_std::task::sleep(Duration::from_millis(1)).await;
+                self.0.write(key, value).await
+            }
+            async fn read(&self, key: String) -> HubResult<Option<Vec<u8>>> {
+                let value = self.0.read(key).await;
+                async_std::task::sleep(Duration::from_millis(1)).await;
+                value
+            }
+            async fn delete(&self, key: String) -> HubResult<bool> {
+                async_std::task::sleep(Duration::from_millis(1)).await;
+                self.0.delete(key).await
+            }
+        }
+        let tiers: [Slow; 3] = Default::default();
+        let policy = TierPolicy { hot_max_bytes: 0, demote_after: std::time::Duration::ZERO, ..Default::default() };
+        let hub = Arc::new(TieredHub::new(Box::new(tiers[0].clone()), Box::new(tiers[1].clone()), Box::new(tiers[2].clone()), policy));
+        for round in 0..50u8 {
+            // reads promote the key, demotion pushes it back, writes race both
+            let tasks: Vec<_> = (0..3).map(|task| {
+                let hub = Arc::clone(&hub);
+                async_std::task::spawn(async move {
+                    match task {
+                        0 => hub.write("k".into(), vec![round; 2]).await.map(drop),
+                        1 => hub.read("k".into()).await.map(drop),
+                        _ => hub.demote_idle().await.map(drop),
+                    }
+                })
+            }).collect();
+            for task in tasks {
+                task.await.unwrap();
+            }
+            assert_eq!(hub.read("k".into()).await.unwrap(), Some(vec![round; 2]));
+            let mut copies = 0;
+            for tier in &tiers {
+                copies += tier.0.read("k".into()).await.unwrap().is_some() as usize;
+            }
+            assert_eq!(copies, 1, "round {round}");
+        }
+    }
+
+    #[async_std::test]
+    async fn read_repair_fills_missing_copy() {
+        let stale = ShortMem::default();
+        let newer = ShortMem::default();
+        stale.write("k".into(), b"v1".to_vec()).await.unwrap();
+        newer.write("k".into(), b"v1".to_vec()).await.unwrap();
+        newer.write_with_ttl("k".into(), b"v2".to_vec(), Duration::from_secs(60)).await.unwrap();
+        let hub = MemoryHub::new();
+        let stale = hub.register_backend(Box::new(stale));
+        hub.register_backend(Box::new(newer));
+        hub.set_read_repair(true);
+        let stale = hub.backend(&stale).unwrap();
+
+        // the priority winner may be the stale copy, so it is never pushed out
+        assert_eq!(hub.read("k".into()).await.unwrap(), Some(b"v1".to_vec()));
+        async_std::task::sleep(Duration::from_millis(20)).await;
+        assert_eq!(stale.read("k".into()).await.unwrap(), Some(b"v1".to_vec()));
+
+        hub.set_read_strategy(ReadStrategy::NewestWins);
+        assert_eq!(hub.read("k".into()).await.unwrap(), Some(b"v2".to_vec()));
+        for _ in 0..100 {
+            if stale.read("k".into()).await.unwrap() == Some(b"v2".to_vec()) { break; }
+            async_std::task::sleep(Duration::from_millis(5)).await;
+        }
+        let repaired = stale.read_record("k".into()).await.unwrap().unwrap();
+        let winner = hub.read_with_meta("k".into()).await.unwrap().unwrap();
+        assert_eq!((repaired.value, repaired.meta.version), (b"v2".to_vec(), winner.meta.version));
+        assert!(repaired.meta.expires_at.is_some());
+    }
+
+    #[async_std::test]
+    async fn bounded_shortmem_evicts_and_notifies() {
+        let evicted = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = Arc::clone(&evicted);
+        let lru = ShortMem::with_capacity(usize::MAX, 2, EvictionPolicy::Lru)
+            .with_eviction_listener(Arc::new(move |k, _| sink.lock().unwrap().push(k)));
+        lru.write("a".into(), vec![1]).await.unwrap();
+        lru.write("b".into(), vec![2]).await.unwrap();
+        lru.read("a".into()).await.unwrap();
+        lru.write("c".into(), vec![3]).await.unwrap();
+        assert_eq!(*evicted.lock().unwrap(), vec!["b".to_string()]);
+        assert_eq!(lru.read("b".into()).await.unwrap(), None);
+        let stats = lru.stats();
+        assert_eq!((stats.hits, stats.misses, stats.evictions, stats.entries), (1, 1, 1, 2));
+
+        // byte budget: 10 bytes fit two 1-byte-key/4-byte-value entries
+        let lfu = ShortMem::with_capacity(10, usize::MAX, EvictionPolicy::Lfu);
+        lfu.write("x".into(), vec![0; 4]).await.unwrap();
+        lfu.write("y".into(), vec![0; 4]).await.unwrap();
+        lfu.read("x".into()).await.unwrap();
+        lfu.read("x".into()).await.unwrap();
+        lfu.write("z".into(), vec![0; 4]).await.unwrap();
+        assert!(lfu.contains("x".into()).await.unwrap());
+        assert!(!lfu.contains("y".into()).await.unwrap());
+
+        // TinyLFU refuses a one-off key in favour of a popular one
+        let tiny = ShortMem::with_capacity(usize::MAX, 2, EvictionPolicy::TinyLfu);
+        tiny.write("hot".into(), vec![0]).await.unwrap();
+        tiny.write("warm".into(), vec![0]).await.unwrap();
+        for _ in 0..5 { tiny.read("hot".into()).await.unwrap(); }
+        tiny.write("once".into(), vec![0]).await.unwrap();
+        tiny.write("twice".into(), vec![0]).await.unwrap();
+        assert!(tiny.contains("hot".into()).await.unwrap());
+        assert_eq!(tiny.stats().entries, 2);
+
+        // a write the cache drops at once is reported, not acknowledged
+        let small = ShortMem::with_capacity(8, usize::MAX, EvictionPolicy::Lru);
+        small.write("a".into(), vec![0; 4]).await.unwrap();
+        assert!(matches!(small.write("big".into(), vec![0; 16]).await, Err(HubError::Rejected(_))));
+        assert_eq!(small.read("big".into()).await.unwrap(), None);
+        let stats = small.stats();
+        assert_eq!((stats.rejected, stats.evictions, stats.entries), (1, 1, 0));
+    }
+
+    #[async_std::test]
+    async fn ttl_expires_and_sweeps() {
+        let hub = MemoryHub::new();
+        hub.register_backend(Box::new(ShortMem::default()));
+        hub.write_with_ttl("session".into(), b"tok".to_vec(), Duration::from_millis(30)).await.unwrap();
+        hub.write("pinned".into(), b"keep".to_vec()).await.unwrap();
+        assert_eq!(hub.read("session".into()).await.unwrap(), Some(b"tok".to_vec()));
+
+        async_std::task::sleep(Duration::from_millis(60)).await;
+        assert!(!hub.contains("session".into()).await.unwrap());
+        assert_eq!(hub.sweep_expired().await.unwrap(), 1);
+        assert_eq!(hub.read("session".into()).await.unwrap(), None);
+        assert_eq!(hub.read("pinned".into()).await.unwrap(), Some(b"keep".to_vec()));
+
+        // a plain write clears an earlier ttl
+        hub.write_with_ttl("k".into(), vec![1], Duration::from_millis(30)).await.unwrap();
+        hub.write("k".into(), vec![2]).await.unwrap();
+        async_std::task::sleep(Duration::from_millis(60)).await;
+        assert_eq!(hub.read("k".into()).await.unwrap(), Some(vec![2]));
+    }
+
+    #[async_std::test]
+    async fn record_envelope_versions_and_meta() {
+        let primary = Flaky::default();
+        let hub = MemoryHub::new();
+        hub.register_backend(Box::new(primary.clone()));
+        let second = hub.register_backend(Box::new(ShortMem::default()));
+        let second = hub.backend(&second).unwrap();
+        hub.set_write_retry(RetryPolicy { max_attempts: 1, ..Default::default() });
+
+        let meta = WriteMeta {
+            content_type: Some("application/json".into()),
+            tags: [("kind".to_string(), "doc".to_string())].into(),
+            ..Default::default()
+        };
+        hub.write_with_meta("doc".into(), b"{}".to_vec(), meta.clone()).await.unwrap();
+        let first = hub.read_with_meta("doc".into()).await.unwrap().unwrap();
+        // the hub assigns the version, so both copies carry the same one
+        let copy = second.read_record("doc".into()).await.unwrap().unwrap();
+        assert!(first.meta.version > 0);
+        assert_eq!(copy.meta.version, first.meta.version);
+        assert_eq!(first.meta.content_type.as_deref(), Some("application/json"));
+        assert_eq!(first.meta.tags.get("kind").map(String::as_str), Some("doc"));
+        assert!(first.verify());
+
+        // the priority backend misses the second write and falls behind
+        primary.fail_writes.store(1, Ordering::SeqCst);
+        hub.set_write_concern(WriteConcern::Any);
+        hub.write_with_meta("doc".into(), b"{\"a\":1}".to_vec(), meta).await.unwrap();
+        assert_eq!(hub.read_with_meta("doc".into()).await.unwrap().unwrap().meta.version, first.meta.version);
+
+        hub.set_read_strategy(ReadStrategy::NewestWins);
+        let newest = hub.read_with_meta("doc".into()).await.unwrap().unwrap();
+        assert!(newest.meta.version > first.meta.version);
+        assert_eq!(newest.meta.created_at, copy.meta.created_at);
+        assert_eq!(newest.value, b"{\"a\":1}".to_vec());
+    }
+
+    #[async_std::test]
+    async fn compare_and_swap_serializes_writers() {
+        let mirror = Arc::new(ShortMem::default());
+        let hub = MemoryHub::new();
+        hub.register_backend(Box::new(ShortMem::default()));
+        hub.register_backend(Box::new(Flaky { inner: Arc::clone(&mirror), ..Default::default() }));
+        let hub = Arc::new(hub);
+
+        assert!(hub.put_if_absent("counter".into(), 0u32.to_be_bytes().to_vec()).await.unwrap());
+        assert!(!hub.put_if_absent("counter".into(), vec![9]).await.unwrap());
+
+        let workers = (0..4).map(|_| {
+            let hub = Arc::clone(&hub);
+            async_std::task::spawn(async move {
+                for _ in 0..10 {
+                    loop {
+                        let current = hub.read_with_meta("counter".into()).await.unwrap().unwrap();
+                        let n = u32::from_be_bytes(current.value[..].try_into().unwrap());
+                        let outcome = hub.compare_and_swap("counter".into(), Expected::Version(current.meta.version), (n + 1).to_be_bytes().to_vec()).await.unwrap();
+                        if outcome.is_swapped() { break; }
+                    }
+                }
+            })
+        });
+        futures::future::join_all(workers).await;
+
+        assert_eq!(hub.read("counter".into()).await.unwrap(), Some(40u32.to_be_bytes().to_vec()));
+
+        // the default concern waits for every backend, so the mirror is current
+        let done = hub.compare_and_swap("counter".into(), Expected::Bytes(40u32.to_be_bytes().to_vec()), b"done".to_vec()).await.unwrap();
+        let CasOutcome::Swapped { version } = done else { panic!("swap refused: {done:?}") };
+        let copy = mirror.read_record("counter".into()).await.unwrap().unwrap();
+        assert_eq!((copy.value, copy.meta.version), (b"done".to_vec(), version));
+
+        let stale = hub.compare_and_swap("counter".into(), Expected::Bytes(vec![0]), vec![1]).await.unwrap();
+        assert!(matches!(stale, CasOutcome::Conflict { current: Some(r) } if r.value == b"done"));
+    }
+
+    #[async_std::test]
+    async fn transaction_commits_all_ops() {
+        let hub = MemoryHub::new();
+        hub.register_backend(Box::new(ShortMem::default()));
+        hub.register_backend(Box::new(ShortMem::default()));
+        assert!(hub.supports_transactions());
+        hub.write("idx/old".into(), b"1".to_vec()).await.unwrap();
+
+        let mut tx = hub.begin();
+        tx.put("emb/1".into(), vec![0, 1, 2])
+            .put("meta/1".into(), b"{}".to_vec())
+            .put("idx/1".into(), b"emb/1".to_vec())
+            .delete("idx/old".into());
+        hub.commit(tx).await.unwrap();
+        for key in ["emb/1", "meta/1", "idx/1"] {
+            assert!(hub.contains(key.into()).await.unwrap());
+        }
+        assert!(!hub.contains("idx/old".into()).await.unwrap());
+
+        // a backend without transaction support makes the hub refuse up front
+        hub.register_backend(Box::new(Flaky::default()));
+        assert!(!hub.supports_transactions());
+        let mut tx = Transaction::new();
+        tx.delete("emb/1".into());
+        assert!(hub.commit(tx).await.is_err());
+        assert!(hub.contains("emb/1".into()).await.unwrap());
+    }
+
+    #[async_std::test]
+    async fn health_routing_and_readiness() {
+        use futures::TryStreamExt;
+        let flaky = Flaky::default();
+        let hub = MemoryHub::new();
+        let steady = hub.register_backend(Box::new(ShortMem::default()));
+        hub.register_backend(Box::new(flaky.clone()));
+        hub.set_write_concern(WriteConcern::Quorum(2));
+        assert!(hub.check_health().await.ready);
+
+        // scans only reach the backend declaring the capability
+        hub.write("k".into(), vec![1]).await.unwrap();
+        let keys: Vec<String> = hub.scan_keys(ScanRange::All).try_collect().await.unwrap();
+        assert_eq!(keys, vec!["k".to_string()]);
+        // nor is a plugin without compare-and-swap picked as its primary
+        let plugin_first = MemoryHub::new();
+        plugin_first.register_backend(Box::new(Flaky::default()));
+        plugin_first.register_backend(Box::new(ShortMem::default()));
+        let swapped = plugin_first.compare_and_swap("c".into(), Expected::Absent, vec![1]).await.unwrap();
+        assert!(matches!(swapped, CasOutcome::Swapped { .. }));
+
+        flaky.down.store(true, Ordering::SeqCst);
+        let readiness = hub.check_health().await;
+        assert!(!readiness.ready);
+        assert_eq!(readiness.backends[1].health, Health::Unhealthy("down".into()));
+        assert!(readiness.backends[0].capabilities.scan && !readiness.backends[0].capabilities.persistent);
+
+        // the unhealthy backend is skipped: a quorum of two can no longer be met
+        assert!(hub.write("j".into(), vec![2]).await.is_err());
+        assert_eq!(flaky.inner.read("j".into()).await.unwrap(), None);
+        hub.set_write_concern(WriteConcern::Any);
+        assert!(hub.readiness().ready);
+        let report = hub.write_with("j".into(), vec![2], WriteConcern::All).await.unwrap();
+        assert_eq!(report.succeeded, vec![steady]);
+        assert_eq!(flaky.inner.read("j".into()).await.unwrap(), None);
+    }
+
+    #[async_std::test]
+    async fn batch_writes_fail_without_a_usable_backend() {
+        let flaky = Flaky::default();
+        let hub = MemoryHub::new();
+        hub.register_backend(Box::new(flaky.clone()));
+        flaky.down.store(true, Ordering::SeqCst);
+        hub.check_health().await;
+        let results = hub.write_many(&[("a".into(), vec![1]), ("b".into(), vec![2])]).await;
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|r| matches!(r, Err(HubError::Unsupported(_)))));
+
+        // nothing is replayed once the backend is back
+        flaky.down.store(false, Ordering::SeqCst);
+        hub.check_health().await;
+        assert_eq!(flaky.inner.read("a".into()).await.unwrap(), None);
+        assert_eq!(hub.write_many(&[("a".into(), vec![1])]).await.pop().unwrap().ok(), Some(()));
+
+        flaky.down.store(true, Ordering::SeqCst);
+        hub.check_health().await;
+        assert!(matches!(hub.write_with("c".into(), vec![3], WriteConcern::Any).await, Err(HubError::Unsupported(_))));
+    }
+
+    #[async_std::test]
+    async fn missed_mutations_replay_on_recovery() {
+        let flaky = Flaky::default();
+        let hub = MemoryHub::new();
+        let steady = hub.register_backend(Box::new(ShortMem::default()));
+        hub.register_backend(Box::new(flaky.clone()));
+        hub.set_write_concern(WriteConcern::All);
+        hub.write("k1".into(), b"v1".to_vec()).await.unwrap();
+        hub.write("k2".into(), b"v1".to_vec()).await.unwrap();
+
+        flaky.down.store(true, Ordering::SeqCst);
+        hub.check_health().await;
+        hub.set_write_concern(WriteConcern::Any);
+        hub.write("k1".into(), b"v2".to_vec()).await.unwrap();
+        assert!(hub.delete("k2".into()).await.unwrap());
+        hub.write("k3".into(), b"v1".to_vec()).await.unwrap();
+        assert_eq!(flaky.inner.read("k2".into()).await.unwrap(), Some(b"v1".to_vec()));
+
+        // the recovered backend catches up before it serves again
+        flaky.down.store(false, Ordering::SeqCst);
+        let readiness = hub.check_health().await;
+        assert_eq!(readiness.backends[1].health, Health::Healthy);
+        let (ours, theirs) = (flaky.inner.read_record("k1".into()).await.unwrap().unwrap(), hub.backend(&steady).unwrap().read_record("k1".into()).await.unwrap().unwrap());
+        assert_eq!((ours.value, ours.meta.version), (b"v2".to_vec(), theirs.meta.version));
+        assert_eq!(flaky.inner.read("k2".into()).await.unwrap(), None);
+        assert_eq!(flaky.inner.read("k3".into()).await.unwrap(), Some(b"v1".to_vec()));
+
+        // a failed write is replayed as well
+        flaky.fail_writes.store(1, Ordering::SeqCst);
+        hub.set_write_retry(RetryPolicy { max_attempts: 1, ..Default::default() });
+        hub.write("k4".into(), b"v1".to_vec()).await.unwrap();
+        // replay waits for the write's background work to give up
+        let mut tries = 0;
+        while hub.check_health().await.backends[1].health != Health::Healthy {
+            tries += 1;
+            assert!(tries < 100, "missed write never replayed");
+            async_std::task::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(flaky.inner.read("k4".into()).await.unwrap(), Some(b"v1".to_vec()));
+    }
+
+    #[async_std::test]
+    async fn named_backends_swap_at_runtime() {
+        let hub = Arc::new(MemoryHub::new());
+        hub.register_named("hot", Box::new(ShortMem::default()), 0).unwrap();
+        hub.register_named("cold", Box::new(ShortMem::default()), 1).unwrap();
+        assert!(hub.register_named("hot", Box::new(ShortMem::default()), 0).is_err());
+        hub.write("k".into(), vec![1]).await.unwrap();
+
+        // swapping through a shared handle keeps name and tier
+        let fresh = Arc::new(ShortMem::default());
+        let old = hub.replace("hot", Box::new(Flaky { inner: Arc::clone(&fresh), ..Default::default() })).unwrap();
+        assert_eq!(old.read("k".into()).await.unwrap(), Some(vec![1]));
+        let report = hub.write_with("j".into(), vec![2], WriteConcern::All).await.unwrap();
+        assert_eq!(report.succeeded.len(), 2);
+        assert_eq!(fresh.read("j".into()).await.unwrap(), Some(vec![2]));
+        let names: Vec<(String, u32)> = hub.inspect().into_iter().map(|b| (b.name, b.tier)).collect();
+        assert_eq!(names, vec![("hot".to_string(), 0), ("cold".to_string(), 1)]);
+
+        let cold = hub.deregister("cold").unwrap();
+        assert!(hub.deregister("cold").is_none() && hub.backend("cold").is_none());
+        hub.write("i".into(), vec![3]).await.unwrap();
+        assert_eq!(cold.read("i".into()).await.unwrap(), None);
+        assert!(hub.replace("cold", Box::new(ShortMem::default())).is_err());
+    }
+
+    #[async_std::test]
+    async fn prefix_routing_with_fallback() {
+        use futures::TryStreamExt;
+        let hub = MemoryHub::new();
+        let (sess, emb) = (Arc::new(ShortMem::default()), Flaky::default());
+        hub.register_named("sess", Box::new(Flaky { inner: Arc::clone(&sess), ..Default::default() }), 0).unwrap();
+        hub.register_named("emb", Box::new(emb.clone()), 0).unwrap();
+        hub.register_named("spare", Box::new(ShortMem::default()), 1).unwrap();
+        let config: RoutingConfig = serde_json::from_str(r#"{ "rules": [
+            { "pattern": "sess/*", "backends": ["sess"] },
+            { "pattern": "emb/*", "backends": ["emb"], "fallback": ["missing", "spare"] },
+            { "pattern": "emb/tmp/", "backends": ["sess"] }
+        ] }"#).unwrap();
+        hub.set_routing(config.clone()).unwrap();
+        assert_eq!(hub.routing(), config);
+
+        hub.write("sess/1".into(), vec![1]).await.unwrap();
+        hub.write("emb/tmp/1".into(), vec![2]).await.unwrap();
+        assert_eq!(sess.read("sess/1".into()).await.unwrap(), Some(vec![1]));
+        assert_eq!(sess.read("emb/tmp/1".into()).await.unwrap(), Some(vec![2]));
+        assert_eq!(emb.read("emb/tmp/1".into()).await.unwrap(), None);
+        let explained = hub.explain("other");
+        assert_eq!((explained.rule, explained.backends.len()), (None, 3));
+
+        // the fallback chain takes over while the routed backend is down
+        emb.down.store(true, Ordering::SeqCst);
+        hub.check_health().await;
+        let explained = hub.explain("emb/7");
+        assert_eq!(explained, RouteExplanation { rule: Some("emb/*".into()), backends: vec!["spare".into()], via_fallback: true });
+        hub.write("emb/7".into(), vec![7]).await.unwrap();
+        assert_eq!(hub.backend("spare").unwrap().read("emb/7".into()).await.unwrap(), Some(vec![7]));
+
+        // the recovered backend takes over what the fallback stored meanwhile
+        emb.down.store(false, Ordering::SeqCst);
+        hub.check_health().await;
+        assert_eq!(emb.read("emb/7".into()).await.unwrap(), Some(vec![7]));
+        assert_eq!(hub.read_with_meta("emb/7".into()).await.unwrap().unwrap().value, vec![7]);
+
+        // a fallback copy not moved back yet is still found, and scans only
+        // take keys from the backends they are routed to
+        let spare = hub.backend("spare").unwrap();
+        spare.write("emb/9".into(), vec![9]).await.unwrap();
+        spare.write("sess/stray".into(), vec![0]).await.unwrap();
+        assert_eq!(hub.read("emb/9".into()).await.unwrap(), Some(vec![9]));
+        assert!(hub.contains("emb/9".into()).await.unwrap());
+        assert_eq!(hub.read_many(&["emb/9".into()]).await.into_iter().next().unwrap().unwrap(), Some(vec![9]));
+        let keys: Vec<String> = hub.scan_keys(ScanRange::All).try_collect().await.unwrap();
+        assert_eq!(keys, vec!["emb/7", "emb/9"]);
+
+        let bad = RoutingConfig { rules: vec![
+            RouteRule { pattern: "a/*".into(), backends: vec!["sess".into()], fallback: vec![] },
+            RouteRule { pattern: "a/".into(), backends: vec!["emb".into()], fallback: vec![] },
+        ] };
+        assert!(hub.set_routing(bad).is_err());
+    }
+
+    #[async_std::test]
+    async fn namespaces_isolate_tenants() {
+        use futures::TryStreamExt;
+        use std::ops::Bound;
+        let hub = Arc::new(MemoryHub::new());
+        hub.register_backend(Box::new(ShortMem::default()));
+        let (acme, globex) = (hub.namespace("acme").unwrap(), hub.namespace("globex").unwrap());
+        assert!(hub.namespace("../acme").is_err());
+
+        acme.write("a".into(), vec![1; 4]).await.unwrap();
+        acme.write("b".into(), vec![2; 4]).await.unwrap();
+        globex.write("a".into(), vec![9]).await.unwrap();
+        assert_eq!(acme.read("a".into()).await.unwrap(), Some(vec![1; 4]));
+        assert_eq!(globex.read("b".into()).await.unwrap(), None);
+        assert!(hub.contains("__ns/acme/a".into()).await.unwrap());
+
+        // scans stay inside the namespace, however the range is written
+        let keys: Vec<String> = acme.scan_keys(ScanRange::All).try_collect().await.unwrap();
+        assert_eq!(keys, vec!["a".to_string(), "b".to_string()]);
+        let keys: Vec<String> = globex.scan_keys(ScanRange::Range(Bound::Unbounded, Bound::Unbounded)).try_collect().await.unwrap();
+        assert_eq!(keys, vec!["a".to_string()]);
+        let entries: Vec<(String, Vec<u8>)> = acme.scan(ScanRange::Prefix("b".into())).try_collect().await.unwrap();
+        assert_eq!(entries, vec![("b".to_string(), vec![2; 4])]);
+
+        // quotas refuse growth but not overwrites that shrink
+        assert_eq!(acme.usage(), Usage { keys: 2, bytes: 8 });
+        hub.namespace("acme").unwrap().set_quota(Quota { max_keys: Some(2), max_bytes: Some(10) });
+        assert!(acme.write("c".into(), vec![3]).await.is_err());
+        assert!(acme.write("a".into(), vec![1; 7]).await.is_err());
+        acme.write("a".into(), vec![1; 6]).await.unwrap();
+        assert!(acme.delete("b".into()).await.unwrap());
+        acme.write("c".into(), vec![3]).await.unwrap();
+        assert_eq!(acme.usage(), Usage { keys: 2, bytes: 7 });
+        assert_eq!(globex.usage(), Usage { keys: 1, bytes: 1 });
+    }
+
+    #[async_std::test]
+    async fn prefix_quotas_reject_and_rebuild() {
+        let hub = MemoryHub::new();
+        let name = hub.register_backend(Box::new(ShortMem::default()));
+        let store = hub.backend(&name).unwrap();
+        store.write("blob/old".into(), vec![0; 5]).await.unwrap();
+        hub.set_quota("blob/", Quota { max_keys: Some(3), max_bytes: Some(20) });
+        hub.set_quota("blob/big/", Quota { max_keys: None, max_bytes: Some(4) });
+        hub.rebuild_usage().await.unwrap();
+        assert_eq!(hub.usage("blob/"), Some(Usage { keys: 1, bytes: 5 }));
+
+        hub.write("blob/a".into(), vec![1; 8]).await.unwrap();
+        hub.write("other".into(), vec![0; 64]).await.unwrap();
+        let err = hub.write("blob/big/x".into(), vec![2; 5]).await.unwrap_err();
+        let HubError::QuotaExceeded(exceeded) = err else { panic!("expected quota error, got {err}") };
+        assert_eq!((exceeded.scope.as_str(), exceeded.resource, exceeded.limit), ("blob/big/", QuotaResource::Bytes, 4));
+
+        // a batch fails per item; a delete frees room again
+        let results = hub.write_many(&[("blob/c".into(), vec![4; 3]), ("blob/d".into(), vec![5; 1])]).await;
+        assert!(results[0].is_ok());
+        assert!(matches!(&results[1], Err(HubError::QuotaExceeded(e)) if e.resource == QuotaResource::Keys));
+        assert!(hub.delete("blob/old".into()).await.unwrap());
+        hub.write("blob/d".into(), vec![5; 1]).await.unwrap();
+        assert_eq!(hub.usage("blob/"), Some(Usage { keys: 3, bytes: 12 }));
+        assert_eq!(hub.quotas().len(), 2);
+
+        // counters drift when data changes behind the hub's back; a rebuild fixes them
+        store.delete("blob/a".into()).await.unwrap();
+        hub.rebuild_usage().await.unwrap();
+        assert_eq!(hub.usage("blob/"), Some(Usage { keys: 2, bytes: 4 }));
+    }
+
+    #[async_std::test]
+    async fn quotas_keep_charges_of_writes_that_reached_a_backend() {
+        let hub = MemoryHub::new();
+        let (a, b) = (Flaky::default(), Flaky::default());
+        hub.register_backend(Box::new(a.clone()));
+        hub.register_backend(Box::new(b.clone()));
+        hub.set_write_concern(WriteConcern::All);
+        hub.set_write_retry(RetryPolicy { max_attempts: 1, ..Default::default() });
+        hub.set_quota("q/", Quota::default());
+
+        // stored on one backend: the concern fails but the size is booked
+        a.fail_writes.store(1, Ordering::SeqCst);
+        assert!(hub.write_with_meta("q/a".into(), vec![1; 4], WriteMeta::default()).await.is_err());
+        assert_eq!(hub.usage("q/"), Some(Usage { keys: 1, bytes: 4 }));
+        // stored nowhere: given back
+        a.fail_writes.store(1, Ordering::SeqCst);
+        b.fail_writes.store(1, Ordering::SeqCst);
+        let report = hub.write_with("q/b".into(), vec![2; 8], WriteConcern::Any).await.unwrap();
+        assert_eq!((report.satisfied, report.failed.len()), (false, 2));
+        assert_eq!(hub.usage("q/"), Some(Usage { keys: 1, bytes: 4 }));
+        // overwrites are booked against the size the quotas remember
+        hub.write("q/a".into(), vec![1; 2]).await.unwrap();
+        assert_eq!(hub.usage("q/"), Some(Usage { keys: 1, bytes: 2 }));
+        assert!(hub.delete("q/a".into()).await.unwrap());
+        assert_eq!(hub.usage("q/"), Some(Usage::default()));
+    }
+
+    #[async_std::test]
+    async fn fan_out_errors_are_typed_and_aggregated() {
+        let hub = MemoryHub::new();
+        let (a, b) = (Flaky::default(), Flaky::default());
+        a.fail_writes.store(1, Ordering::SeqCst);
+        b.fail_writes.store(1, Ordering::SeqCst);
+        hub.register_named("a", Box::new(a), 0).unwrap();
+        hub.register_named("b", Box::new(b), 0).unwrap();
+        hub.set_write_retry(RetryPolicy { max_attempts: 1, ..Default::default() });
+
+        // `Any` waits for both failures before giving up
+        let report = hub.write_with("k".into(), vec![1], WriteConcern::Any).await.unwrap();
+        let err = report.into_result().unwrap_err();
+        let HubError::Multiple(errors) = &err else { panic!("expected aggregated error, got {err}") };
+        let mut names: Vec<&str> = errors.iter().map(|e| match e {
+            HubError::Backend { name, source } if matches!(**source, HubError::Unavailable(_)) => name.as_str(),
+            other => panic!("unexpected {other}"),
+        }).collect();
+        names.sort();
+        assert_eq!(names, ["a", "b"]);
+        assert!(err.is_retryable());
+
+        // capability and input errors are final
+        let mut tx = hub.begin();
+        tx.put("k".into(), vec![2]);
+        let err = hub.commit(tx).await.unwrap_err();
+        assert!(matches!(err.root(), HubError::Unsupported(_)) && !err.is_retryable());
+        assert!(matches!(hub.replace("c", Box::new(ShortMem::default())), Err(HubError::NotFound(_))));
+    }
+
+    #[derive(Default)]
+    struct Recorder(std::sync::Mutex<Vec<AuditEvent>>);
+
+    impl AuditSink for Recorder {
+        fn record(&self, event: &AuditEvent) {
+            self.0.lock().unwrap().push(event.clone());
+        }
+    }
+
+    /// Everyone may read; only writers may change data.
+    struct WritersOnly;
+
+    impl PolicyEngine for WritersOnly {
+        fn allow(&self, action: &str, ctx: &serde_json::Value) -> bool {
+            action == "read" || ctx["principal"]["roles"].as_array().is_some_and(|r| r.iter().any(|r| r == "writer"))
+        }
+    }
+
+    #[async_std::test]
+    async fn policy_checks_every_operation_and_audits() {
+        use futures::StreamExt;
+        let audit = Arc::new(Recorder::default());
+        let hub = MemoryHub::new();
+        hub.register_backend(Box::new(ShortMem::default()));
+        hub.set_audit_sink(audit.clone());
+        let hub = Arc::new(hub);
+
+        let alice = hub.session(Principal::new("alice").with_role("writer"));
+        alice.namespace("acme").unwrap().write("doc".into(), vec![1, 2]).await.unwrap();
+        // a shared hub can still be reconfigured
+        hub.set_policy(Arc::new(WritersOnly));
+        let bob = hub.session(Principal::new("bob"));
+        let acme = bob.namespace("acme").unwrap();
+        assert_eq!(acme.read("doc".into()).await.unwrap(), Some(vec![1, 2]));
+        let err = acme.write("doc".into(), vec![3]).await.unwrap_err();
+        assert!(matches!(&err, HubError::PolicyDenied { action, .. } if action == "write"));
+        assert!(matches!(hub.delete("__ns/acme/doc".into()).await, Err(HubError::PolicyDenied { .. })));
+        assert!(matches!(acme.scan(ScanRange::All).next().await, Some(Err(HubError::PolicyDenied { .. }))));
+        assert_eq!(hub.read("__ns/acme/doc".into()).await.unwrap(), Some(vec![1, 2]));
+
+        let events = audit.0.lock().unwrap();
+        let summary: Vec<(&str, Action, bool)> = events.iter().map(|e| (e.principal.as_str(), e.action, e.allowed)).collect();
+        assert_eq!(summary, vec![
+            ("alice", Action::Write, true),
+            ("bob", Action::Read, true),
+            ("bob", Action::Write, false),
+            ("anonymous", Action::Delete, false),
+            ("bob", Action::Scan, false),
+            ("anonymous", Action::Read, true),
+        ]);
+        assert_eq!((events[0].namespace.as_deref(), events[0].size), (Some("acme"), Some(2)));
+        assert_eq!(events[4].key.as_deref(), Some("__ns/acme/"));
+    }
+
+    /// Principals only reach keys of their own tenant.
+    struct OwnTenant;
+
+    impl PolicyEngine for OwnTenant {
+        fn allow(&self, _action: &str, ctx: &serde_json::Value) -> bool {
+            !ctx["namespace"].is_null() && ctx["namespace"] == ctx["principal"]["attributes"]["tenant"]
+        }
+    }
+
+    #[async_std::test]
+    async fn range_scans_leave_out_keys_of_other_tenants() {
+        use futures::TryStreamExt;
+        use std::ops::Bound;
+        let hub = MemoryHub::new();
+        let name = hub.register_backend(Box::new(ShortMem::default()));
+        hub.set_policy(Arc::new(OwnTenant));
+        let store = hub.backend(&name).unwrap();
+        for key in ["__ns/acme/a", "__ns/acme/b", "__ns/globex/a", "plain"] {
+            store.write(key.into(), vec![1]).await.unwrap();
+        }
+        let hub = Arc::new(hub);
+        let alice = hub.session(Principal::new("alice").with_attribute("tenant", "acme"));
+
+        // the lower bound is acme's, the rest of the range is not
+        let range = ScanRange::Range(Bound::Included("__ns/acme/".into()), Bound::Unbounded);
+        let keys: Vec<String> = alice.scan_keys(range.clone()).try_collect().await.unwrap();
+        assert_eq!(keys, vec!["__ns/acme/a", "__ns/acme/b"]);
+        let entries: Vec<(String, Vec<u8>)> = alice.scan(range).try_collect().await.unwrap();
+        assert_eq!(entries.len(), 2);
+        let range = ScanRange::Range(Bound::Included("__ns/acme/b".into()), Bound::Included("__ns/globex/z".into()));
+        let keys: Vec<String> = alice.scan_keys(range).try_collect().await.unwrap();
+        assert_eq!(keys, vec!["__ns/acme/b"]);
+    }
+
+    /// Allows everything, hiding personal data and asking for a notice.
+    struct MaskPersonal;
+
+    impl PolicyEngine for MaskPersonal {
+        fn allow(&self, _action: &str, _ctx: &serde_json::Value) -> bool {
+            true
+        }
+
+        fn evaluate(&self, action: &str, _ctx: &serde_json::Value) -> Decision {
+            let redact = if action == "write" { vec![] } else { vec!["ssn".into(), "/owner/email".into()] };
+            Decision { allow: true, redact, obligations: vec![serde_json::json!({ "notify": "dpo" })] }
+        }
+    }
+
+    #[async_std::test]
+    async fn policy_redactions_and_obligations_apply() {
+        use futures::TryStreamExt;
+        let audit = Arc::new(Recorder::default());
+        let hub = MemoryHub::new();
+        hub.register_backend(Box::new(ShortMem::default()));
+        hub.set_policy(Arc::new(MaskPersonal));
+        hub.set_audit_sink(audit.clone());
+        let doc = serde_json::json!({ "name": "a", "ssn": "123", "owner": { "id": 7, "email": "a@b" } });
+        hub.write("p/1".into(), serde_json::to_vec(&doc).unwrap()).await.unwrap();
+        hub.write("p/2".into(), b"not json".to_vec()).await.unwrap();
+
+        let expected = serde_json::json!({ "name": "a", "owner": { "id": 7 } });
+        let value = hub.read("p/1".into()).await.unwrap().unwrap();
+        assert_eq!(serde_json::from_slice::<serde_json::Value>(&value).unwrap(), expected);
+        let record = hub.read_with_meta("p/1".into()).await.unwrap().unwrap();
+        assert!(record.verify() && record.value == value);
+        let many = hub.read_many(&["p/1".into(), "p/2".into()]).await;
+        assert_eq!(many[0].as_ref().unwrap().as_deref(), Some(&value[..]));
+        // nothing could be stripped from a value that is not JSON
+        assert!(matches!(many[1], Err(HubError::PolicyDenied { .. })));
+        assert!(matches!(hub.read("p/2".into()).await, Err(HubError::PolicyDenied { .. })));
+        let entries: Vec<(String, Vec<u8>)> = hub.scan(ScanRange::Prefix("p/".into())).try_collect().await.unwrap();
+        assert_eq!(entries, vec![("p/1".to_string(), value)]);
+
+        let events = audit.0.lock().unwrap();
+        assert!(events.iter().all(|e| e.obligations == [serde_json::json!({ "notify": "dpo" })]));
+    }
+
+    #[async_std::test]
+    async fn jsonl_audit_log_records_outcomes_and_answers_queries() {
+        use futures::StreamExt;
+        let path = std::env::temp_dir().join(format!("cognivault-audit-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let sink = Arc::new(JsonlAuditSink::open(&path).unwrap());
+        let flaky = Flaky::default();
+        flaky.fail_writes.store(1, Ordering::SeqCst);
+        let hub = MemoryHub::new();
+        hub.register_backend(Box::new(flaky));
+        hub.set_write_retry(RetryPolicy { max_attempts: 1, ..Default::default() });
+        hub.set_audit_sink(sink.clone());
+        let hub = Arc::new(hub);
+
+        assert!(hub.write("a".into(), vec![1]).await.is_err());
+        hub.write("a".into(), vec![2]).await.unwrap();
+        hub.session(Principal::new("bob")).read("a".into()).await.unwrap();
+        hub.write("b".into(), vec![3]).await.unwrap();
+        // the backend cannot scan
+        assert!(matches!(hub.scan(ScanRange::All).next().await, Some(Err(HubError::Unsupported(_)))));
+        let after = ttl::now_millis() + 1;
+
+        let on_a = sink.query(&AuditQuery::new().key("a")).unwrap();
+        let outcomes: Vec<(Action, &str, bool)> = on_a.iter().map(|e| (e.action, e.principal.as_str(), e.outcome == AuditOutcome::Ok)).collect();
+        assert_eq!(outcomes, vec![(Action::Write, "anonymous", false), (Action::Write, "anonymous", true), (Action::Read, "bob", true)]);
+        assert!(matches!(&on_a[0].outcome, AuditOutcome::Failed { error } if error.contains("write concern not met")));
+        assert_eq!(sink.query(&AuditQuery::new().principal("bob")).unwrap().len(), 1);
+        let all = sink.query(&AuditQuery::new().between(0, after)).unwrap();
+        assert_eq!(all.len(), 5);
+        assert_eq!((all[4].action, all[4].key.as_deref()), (Action::Scan, None));
+        assert!(matches!(all[4].outcome, AuditOutcome::Failed { .. }));
+        assert!(sink.query(&AuditQuery::new().between(after, u64::MAX)).unwrap().is_empty());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rule_policy_grants_by_role_and_attributes_with_deny_overrides() {
+        let rules = r#"{
+            "roles": {
+                "reader": { "grants": [{ "actions": ["read", "scan"] }] },
+                "editor": { "inherits": ["reader"], "grants": [{
+                    "actions": ["write", "delete"], "keys": ["__ns/*"],
+                    "when": [
+                        { "op": "same_as", "field": "namespace", "other": "principal.attributes.tenant" },
+                        { "op": "at_most", "field": "size", "max": 16 },
+                        { "op": "time_window", "from": "22:00", "to": "06:00", "days": ["fri"] }
+                    ]
+                }] }
+            },
+            "principals": { "carol": { "roles": ["editor"] } },
+            "deny": [{ "name": "frozen", "actions": ["delete"], "keys": ["__ns/acme/frozen/*"] }]
+        }"#;
+        let mut policy = RulePolicy::from_json(rules).unwrap();
+        let ctx = |id: &str, key: &str, size: u64| serde_json::json!({
+            "key": key, "namespace": "acme", "size": size,
+            "principal": { "id": id, "roles": [], "attributes": { "tenant": "acme" } },
+        });
+        // Friday 1970-01-02 23:00 UTC, inside the window
+        let friday_night = 86_400 + 23 * 3600;
+        let at = |action: &str, ctx: &serde_json::Value, t| policy.verdict_at(action, ctx, t);
+        assert_eq!(at("write", &ctx("carol", "__ns/acme/a", 4), friday_night), Verdict::Granted { role: "editor".into() });
+        assert_eq!(at("read", &ctx("carol", "__ns/acme/a", 0), 0), Verdict::Granted { role: "reader".into() });
+        assert_eq!(at("write", &ctx("carol", "__ns/acme/a", 4), friday_night + 8 * 3600), Verdict::NoGrant);
+        // Saturday 02:00 is still Friday's window, Friday 02:00 is Thursday's
+        assert!(at("write", &ctx("carol", "__ns/acme/a", 4), friday_night + 3 * 3600).is_allowed());
+        assert_eq!(at("write", &ctx("carol", "__ns/acme/a", 4), 86_400 + 2 * 3600), Verdict::NoGrant);
+        assert_eq!(at("write", &ctx("carol", "__ns/acme/a", 64), friday_night), Verdict::NoGrant);
+        assert_eq!(at("write", &ctx("dave", "__ns/acme/a", 4), friday_night), Verdict::NoGrant);
+        let mut other_tenant = ctx("carol", "__ns/acme/a", 4);
+        other_tenant["principal"]["attributes"]["tenant"] = "globex".into();
+        assert_eq!(at("write", &other_tenant, friday_night), Verdict::NoGrant);
+        assert_eq!(at("delete", &ctx("carol", "__ns/acme/frozen/x", 0), friday_night),
+            Verdict::Denied { rule: "frozen".into(), overrode: Some("editor".into()) });
+        assert_eq!(at("delete", &ctx("dave", "__ns/acme/frozen/x", 0), 0).to_string(), "denied by rule frozen");
+
+        assert!(!policy.allow("write", &ctx("dave", "__ns/acme/a", 4)));
+        policy.set_dry_run(true);
+        assert!(policy.allow("write", &ctx("dave", "__ns/acme/a", 4)));
+        assert!(!policy.explain("write", &ctx("dave", "__ns/acme/a", 4)).is_allowed());
+
+        let err = RulePolicy::from_json(r#"{ "principals": { "eve": { "roles": ["root"] } } }"#).unwrap_err();
+        assert!(matches!(err, HubError::InvalidInput(_)), "{err}");
+        assert!(RulePolicy::from_json(r#"{ "deny": [{ "when": [{ "op": "time_window", "from": "25:00", "to": "01:00" }] }] }"#).is_err());
+    }
+
+    #[async_std::test]
+    async fn rule_policy_checks_whole_scan_ranges() {
+        use futures::TryStreamExt;
+        use std::ops::Bound;
+        let policy = RulePolicy::from_json(r#"{
+            "roles": { "reader": { "grants": [{ "actions": ["scan"], "keys": ["__ns/acme/*"] }] } },
+            "principals": { "bob": { "roles": ["reader"] } },
+            "deny": [{ "name": "drafts", "actions": ["scan"], "keys": ["__ns/acme/drafts/*"] }]
+        }"#).unwrap();
+        let hub = MemoryHub::new();
+        let name = hub.register_backend(Box::new(ShortMem::default()));
+        hub.set_policy(Arc::new(policy));
+        let store = hub.backend(&name).unwrap();
+        for key in ["__ns/acme/a", "__ns/acme/drafts/x", "__ns/acme/z", "__ns/globex/a"] {
+            store.write(key.into(), vec![1]).await.unwrap();
+        }
+        let hub = Arc::new(hub);
+        let bob = hub.session(Principal::new("bob"));
+        let scan = |range| bob.scan_keys(range).try_collect::<Vec<String>>();
+        let range = |start: &str, end: Bound<String>| ScanRange::Range(Bound::Included(start.into()), end);
+
+        let keys = scan(range("__ns/acme/a", Bound::Excluded("__ns/acme/d".into()))).await.unwrap();
+        assert_eq!(keys, vec!["__ns/acme/a"]);
+        // the grant must cover the whole range, not only its lower bound
+        let err = scan(range("__ns/acme/a", Bound::Unbounded)).await.unwrap_err();
+        assert!(matches!(err, HubError::PolicyDenied { .. }), "{err}");
+        assert!(scan(ScanRange::All).await.is_err());
+        // a deny refuses every range reaching into it
+        assert!(scan(range("__ns/acme/b", Bound::Included("__ns/acme/e".into()))).await.is_err());
+        assert!(scan(ScanRange::Prefix("__ns/acme/".into())).await.is_err());
+        assert!(scan(ScanRange::Prefix("__ns/acme/drafts/old/".into())).await.is_err());
+        assert_eq!(scan(range("__ns/acme/e", Bound::Excluded("__ns/acme/zz".into()))).await.unwrap(), vec!["__ns/acme/z"]);
+    }
 }
 
 #[cfg(all(feature="ann_scalar", test))]
@@ -66,3 +1066,670 @@ mod ann_tests {
         assert_eq!(res[0], 0);
     }
 }
+
+#[cfg(all(feature="opa_policy", test))]
+mod opa_tests {
+    use super::*;
+    use std::time::Duration;
+    use wasm_encoder::{
+        CodeSection, ConstExpr, DataSection, EntityType, ExportKind, ExportSection, Function, FunctionSection,
+        GlobalSection, GlobalType, ImportSection, Instruction as I, MemArg, MemoryType, Module, TypeSection, ValType,
+    };
+
+    /// Rule of a [`policy_module`]: answers `result` when the input holds
+    /// `input` and the data document holds `data`, for the first `evals`
+    /// evaluations of the instance if set.
+    struct Rule {
+        input: &'static str,
+        data: &'static str,
+        evals: Option<i32>,
+        result: &'static str,
+    }
+
+    const fn rule(input: &'static str, result: &'static str) -> Rule {
+        Rule { input, data: "", evals: None, result }
+    }
+
+    /// OPA ABI 1.0 module with one entrypoint, `cognivault/allow`, deciding
+    /// by substring matches on the JSON text of `input` and `data`, else
+    /// denying. Values are kept as NUL-terminated JSON text.
+    fn policy_module(rules: &[Rule]) -> Vec<u8> {
+        let mut strings = Vec::new();
+        let mut intern = |text: &str| {
+            let at = 1024 + strings.len() as i32;
+            strings.extend_from_slice(text.as_bytes());
+            strings.push(0);
+            at
+        };
+        let (builtins, entrypoints) = (intern("{}"), intern(r#"{"cognivault/allow":0}"#));
+        let deny = intern(r#"[{"result":false}]"#);
+        let rules: Vec<(i32, i32, Option<i32>, i32)> = rules.iter()
+            .map(|r| (intern(r.input), intern(r.data), r.evals, intern(&format!(r#"[{{"result":{}}}]"#, r.result))))
+            .collect();
+
+        let mut types = TypeSection::new();
+        types.function([ValType::I32], [ValType::I32]);
+        types.function([ValType::I32], []);
+        types.function([ValType::I32, ValType::I32], [ValType::I32]);
+        types.function([], [ValType::I32]);
+        types.function([ValType::I32, ValType::I32], []);
+        let mut imports = ImportSection::new();
+        imports.import("env", "memory", EntityType::Memory(MemoryType { minimum: 2, maximum: None, memory64: false, shared: false }));
+        let mut globals = GlobalSection::new();
+        for (mutable, init) in [(true, 65536), (true, 0), (false, 1), (false, 0)] {
+            globals.global(GlobalType { val_type: ValType::I32, mutable }, &ConstExpr::i32_const(init));
+        }
+        let (heap, evals) = (0, 1);
+        let mem = |offset| MemArg { offset, align: 2, memory_index: 0 };
+        let byte = MemArg { offset: 0, align: 0, memory_index: 0 };
+
+        // has(haystack, needle): whether needle occurs in haystack
+        let mut has = Function::new([(2, ValType::I32)]);
+        for ins in [
+            I::Block(wasm_encoder::BlockType::Empty), I::Loop(wasm_encoder::BlockType::Empty),
+            I::I32Const(0), I::LocalSet(3),
+            I::Block(wasm_encoder::BlockType::Empty), I::Loop(wasm_encoder::BlockType::Empty),
+            I::LocalGet(1), I::LocalGet(3), I::I32Add, I::I32Load8U(byte), I::I32Eqz,
+            I::If(wasm_encoder::BlockType::Empty), I::I32Const(1), I::Return, I::End,
+            I::LocalGet(0), I::LocalGet(2), I::I32Add, I::LocalGet(3), I::I32Add, I::I32Load8U(byte),
+            I::LocalGet(1), I::LocalGet(3), I::I32Add, I::I32Load8U(byte), I::I32Ne, I::BrIf(1),
+            I::LocalGet(3), I::I32Const(1), I::I32Add, I::LocalSet(3), I::Br(0),
+            I::End, I::End,
+            I::LocalGet(0), I::LocalGet(2), I::I32Add, I::I32Load8U(byte), I::I32Eqz, I::BrIf(1),
+            I::LocalGet(2), I::I32Const(1), I::I32Add, I::LocalSet(2), I::Br(0),
+            I::End, I::End,
+            I::I32Const(0), I::End,
+        ] {
+            has.instruction(&ins);
+        }
+        // eval(ctx): the first matching rule's result set, stored at ctx + 8
+        let mut eval = Function::new([]);
+        let answer = |f: &mut Function, result: i32| {
+            for ins in [
+                I::LocalGet(0), I::I32Const(result), I::I32Store(mem(8)),
+                I::GlobalGet(evals), I::I32Const(1), I::I32Add, I::GlobalSet(evals),
+                I::I32Const(0), I::Return,
+            ] {
+                f.instruction(&ins);
+            }
+        };
+        for &(input, data, limit, result) in &rules {
+            for ins in [
+                I::LocalGet(0), I::I32Load(mem(0)), I::I32Const(input), I::Call(0),
+                I::LocalGet(0), I::I32Load(mem(4)), I::I32Const(data), I::Call(0), I::I32And,
+            ] {
+                eval.instruction(&ins);
+            }
+            if let Some(limit) = limit {
+                for ins in [I::GlobalGet(evals), I::I32Const(limit), I::I32LtU, I::I32And] {
+                    eval.instruction(&ins);
+                }
+            }
+            eval.instruction(&I::If(wasm_encoder::BlockType::Empty));
+            answer(&mut eval, result);
+            eval.instruction(&I::End);
+        }
+        answer(&mut eval, deny);
+        eval.instruction(&I::End);
+
+        let body = |ins: &[I]| {
+            let mut f = Function::new([]);
+            for i in ins {
+                f.instruction(i);
+            }
+            f.instruction(&I::End);
+            f
+        };
+        let exports: Vec<(&str, u32, Function)> = vec![
+            ("has", 2, has),
+            ("opa_malloc", 0, body(&[I::GlobalGet(heap), I::GlobalGet(heap), I::LocalGet(0), I::I32Add, I::GlobalSet(heap)])),
+            ("opa_free", 1, body(&[])),
+            ("opa_json_parse", 2, body(&[I::LocalGet(0)])),
+            ("opa_json_dump", 0, body(&[I::LocalGet(0)])),
+            ("builtins", 3, body(&[I::I32Const(builtins)])),
+            ("entrypoints", 3, body(&[I::I32Const(entrypoints)])),
+            ("opa_heap_ptr_get", 3, body(&[I::GlobalGet(heap)])),
+            ("opa_heap_ptr_set", 1, body(&[I::LocalGet(0), I::GlobalSet(heap)])),
+            ("opa_eval_ctx_new", 3, body(&[I::I32Const(512)])),
+            ("opa_eval_ctx_set_input", 4, body(&[I::LocalGet(0), I::LocalGet(1), I::I32Store(mem(0))])),
+            ("opa_eval_ctx_set_data", 4, body(&[I::LocalGet(0), I::LocalGet(1), I::I32Store(mem(4))])),
+            ("opa_eval_ctx_set_entrypoint", 4, body(&[])),
+            ("eval", 0, eval),
+            ("opa_eval_ctx_get_result", 0, body(&[I::LocalGet(0), I::I32Load(mem(8))])),
+        ];
+        let (mut functions, mut export, mut code) = (FunctionSection::new(), ExportSection::new(), CodeSection::new());
+        for (idx, (name, ty, f)) in exports.iter().enumerate() {
+            functions.function(*ty);
+            export.export(name, ExportKind::Func, idx as u32);
+            code.function(f);
+        }
+        export.export("opa_wasm_abi_version", ExportKind::Global, 2);
+        export.export("opa_wasm_abi_minor_version", ExportKind::Global, 3);
+        let mut data = DataSection::new();
+        data.active(0, &ConstExpr::i32_const(1024), strings);
+
+        let mut module = Module::new();
+        module.section(&types).section(&imports).section(&functions).section(&globals)
+            .section(&export).section(&code).section(&data);
+        module.finish()
+    }
+
+    const ENTRYPOINT: &str = "cognivault/allow";
+
+    fn ctx(id: &str) -> serde_json::Value {
+        serde_json::json!({ "key": "doc", "namespace": null, "size": null, "principal": Principal::new(id) })
+    }
+
+    #[test]
+    fn rego_policy_decides_on_input_and_data() {
+        let module = policy_module(&[
+            rule(r#""action":"read""#, "true"),
+            Rule { input: r#""action":"write""#, data: r#""writers":["alice"]"#, evals: None, result: "true" },
+            rule(r#""action":"scan""#, r#"{"allow":true,"redact":["ssn"],"obligations":[{"notify":"dpo"}]}"#),
+        ]);
+        let policy = RegoPolicy::from_bytes(module, ENTRYPOINT).unwrap();
+        assert!(policy.allow("read", &ctx("bob")));
+        assert!(!policy.allow("delete", &ctx("bob")));
+        assert!(!policy.allow("write", &ctx("alice")));
+        assert_eq!(policy.evaluate("scan", &ctx("bob")), Decision {
+            allow: true,
+            redact: vec!["ssn".into()],
+            obligations: vec![serde_json::json!({ "notify": "dpo" })],
+        });
+
+        // the cached denial belongs to the previous data document
+        policy.set_data(serde_json::json!({ "writers": ["alice"] })).unwrap();
+        assert!(policy.allow("write", &ctx("alice")));
+        assert!(RegoPolicy::from_bytes(policy_module(&[]), "cognivault/other").is_err());
+    }
+
+    #[test]
+    fn rego_policy_caches_decisions_per_generation() {
+        // allows the first read of each loaded instance only
+        let once = || policy_module(&[Rule { input: r#""action":"read""#, data: "", evals: Some(1), result: "true" }]);
+        let uncached = RegoPolicy::from_bytes(once(), ENTRYPOINT).unwrap().with_cache_ttl(Duration::ZERO);
+        assert!(uncached.allow("read", &ctx("bob")));
+        assert!(!uncached.allow("read", &ctx("bob")));
+
+        let path = std::env::temp_dir().join(format!("cognivault-policy-{}.wasm", std::process::id()));
+        std::fs::write(&path, once()).unwrap();
+        let policy = RegoPolicy::from_file(&path, ENTRYPOINT).unwrap().with_cache_ttl(Duration::from_secs(60));
+        assert!(policy.allow("read", &ctx("bob")));
+        assert!(policy.allow("read", &ctx("bob")), "second decision comes from the cache");
+        assert!(!policy.allow("read", &ctx("carol")), "other contexts are evaluated");
+
+        std::fs::write(&path, policy_module(&[])).unwrap();
+        policy.reload().unwrap();
+        assert!(!policy.allow("read", &ctx("bob")), "decisions of the previous bundle are not served");
+        assert!(!policy.reload_if_changed().unwrap());
+        // a broken bundle keeps the policy in force
+        std::fs::write(&path, b"broken").unwrap();
+        assert!(policy.reload().is_err());
+        assert!(!policy.allow("read", &ctx("bob")));
+        std::fs::write(&path, once()).unwrap();
+        policy.reload().unwrap();
+        assert!(policy.allow("read", &ctx("bob")));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn rego_policy_rejects_broken_bundles() {
+        let err = RegoPolicy::from_bytes(b"not a policy".to_vec(), "cognivault/allow").err().expect("garbage must not load");
+        assert!(matches!(err, HubError::InvalidInput(_)), "{err}");
+        // a valid but empty module lacks the OPA ABI exports
+        let empty = b"\0asm\x01\0\0\0".to_vec();
+        assert!(RegoPolicy::from_bytes(empty, "cognivault/allow").is_err());
+        assert!(RegoPolicy::from_file("/nonexistent/bundle.tar.gz", "cognivault/allow").is_err());
+    }
+}
+
+#[cfg(all(feature="policy_toml", test))]
+mod policy_toml_tests {
+    use super::*;
+
+    #[test]
+    fn rule_policy_loads_toml() {
+        let policy = RulePolicy::from_toml(r#"
+            [roles.reader]
+            grants = [{ actions = ["read"], keys = ["public/*"] }]
+
+            [principals.anonymous]
+            roles = ["reader"]
+        "#).unwrap();
+        let ctx = |key: &str| serde_json::json!({ "key": key, "principal": { "id": "anonymous" } });
+        assert!(policy.allow("read", &ctx("public/a")));
+        assert!(!policy.allow("read", &ctx("private/a")));
+        assert!(!policy.allow("scan", &serde_json::json!({ "principal": { "id": "anonymous" } })));
+    }
+}
+
+#[cfg(all(feature="longmem_sled", feature="longmem_encrypt", test))]
+mod longmem_tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[async_std::test]
+    async fn key_rotation_reencrypts_resumably() {
+        let dir = std::env::temp_dir().join(format!("cognivault-rotation-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let (old, new) = ([1u8; 32], [2u8; 32]);
+        let db = LongMem::open(&dir, Some(old)).unwrap();
+        for k in ["a", "__ns/acme/b", "c"] {
+            db.write(k.into(), k.as_bytes().to_vec()).await.unwrap();
+        }
+        db.rotate_key(1, new).unwrap();
+        db.write("d".into(), b"d".to_vec()).await.unwrap();
+        assert_eq!(db.read("a".into()).await.unwrap(), Some(b"a".to_vec()));
+        let first = db.reencrypt_batch(2).unwrap();
+        assert_eq!((first.scanned, first.done), (2, false));
+        drop(db);
+
+        // the pass resumes after reopening
+        let db = reopen(|| LongMem::open_with_keyring(&dir, Keyring::new(1, new).with_key(0, old).unwrap()));
+        let rest = db.reencrypt_batch(10).unwrap();
+        assert_eq!((rest.scanned, rest.skipped, rest.done), (2, 0, true));
+        assert_eq!(first.rewritten + rest.rewritten, 3);
+        drop(db);
+
+        let db = reopen(|| LongMem::open_with_keyring(&dir, Keyring::new(1, new)));
+        for k in ["a", "__ns/acme/b", "c", "d"] {
+            assert_eq!(db.read(k.into()).await.unwrap(), Some(k.as_bytes().to_vec()));
+        }
+        drop(db);
+        let db = reopen(|| LongMem::open(&dir, Some(old)));
+        assert!(matches!(db.read("a".into()).await, Err(HubError::Crypto(_))));
+        drop(db);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[async_std::test]
+    async fn namespace_keys_apply_after_reopen() {
+        let dir = std::env::temp_dir().join(format!("cognivault-tenant-keys-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let db = LongMem::open(&dir, Some([1u8; 32])).unwrap();
+        db.set_namespace_key("acme", [5u8; 32]).unwrap();
+        db.write("__ns/acme/a".into(), b"a".to_vec()).await.unwrap();
+        db.write("__ns/globex/a".into(), b"g".to_vec()).await.unwrap();
+        drop(db);
+
+        let db = reopen(|| LongMem::open(&dir, Some([1u8; 32])));
+        assert_eq!(db.read("__ns/acme/a".into()).await.unwrap(), Some(b"a".to_vec()));
+        assert_eq!(db.read("__ns/globex/a".into()).await.unwrap(), Some(b"g".to_vec()));
+
+        // the namespace key outlives the database key it was wrapped with
+        db.rotate_key(1, [2u8; 32]).unwrap();
+        db.write("__ns/acme/b".into(), b"b".to_vec()).await.unwrap();
+        assert!(matches!(db.reencrypt_batch(0), Err(HubError::InvalidInput(_))));
+        while !db.reencrypt_batch(1).unwrap().done {}
+        db.set_keyring(Keyring::new(1, [2u8; 32]));
+        db.set_namespace_key("acme", [5u8; 32]).unwrap();
+        assert_eq!(db.read("__ns/acme/a".into()).await.unwrap(), Some(b"a".to_vec()));
+        assert_eq!(db.read("__ns/acme/b".into()).await.unwrap(), Some(b"b".to_vec()));
+        // existing values would no longer decrypt under a new key
+        assert!(matches!(db.set_namespace_key("acme", [7u8; 32]), Err(HubError::Conflict(_))));
+        assert!(matches!(db.set_namespace_key("globex", [6u8; 32]), Err(HubError::Conflict(_))));
+        assert_eq!(db.read("__ns/acme/a".into()).await.unwrap(), Some(b"a".to_vec()));
+        assert_eq!(db.read("__ns/globex/a".into()).await.unwrap(), Some(b"g".to_vec()));
+        drop(db);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[async_std::test]
+    async fn envelope_keys_open_longmem_through_a_kms() {
+        let dir = std::env::temp_dir().join(format!("cognivault-envelope-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let (store, wrapped) = (dir.join("db"), dir.join("keys.json"));
+        let kms = Arc::new(MockKms::new(SecretKey::generate()));
+        let keys = EnvelopeKeys::open(kms.clone(), &wrapped).unwrap();
+        // scopes are created explicitly, never by asking for their keys
+        assert!(matches!(keys.data_key("db", 0), Err(HubError::NotFound(_))));
+        assert!(matches!(LongMem::open_with_provider(&store, &keys, "db"), Err(HubError::NotFound(_))));
+        assert!(matches!(keys.rotate("db"), Err(HubError::NotFound(_))));
+        keys.create_scope("db").unwrap();
+        assert!(matches!(keys.create_scope("db"), Err(HubError::Conflict(_))));
+        assert!(!dir.join("keys.json.tmp").exists());
+        let db = LongMem::open_with_provider(&store, &keys, "db").unwrap();
+        db.write("a".into(), b"a".to_vec()).await.unwrap();
+        let id = keys.rotate("db").unwrap();
+        db.rotate_key(id, keys.data_key("db", id).unwrap()).unwrap();
+        db.write("b".into(), b"b".to_vec()).await.unwrap();
+        drop(db);
+        // two wraps, the later unwrap served from the cache
+        assert_eq!(kms.calls(), 2);
+
+        // a new master key: rewrap, then reopen from the file alone
+        let kms2 = Arc::new(MockKms::new(SecretKey::generate()));
+        keys.rewrap(kms2.clone()).unwrap();
+        let keys = EnvelopeKeys::open(kms2, &wrapped).unwrap();
+        assert_eq!(keys.key_ids("db").unwrap(), vec![0, 1]);
+        let db = reopen(|| LongMem::open_with_provider(&store, &keys, "db"));
+        assert_eq!(db.keyring().active(), 1);
+        assert_eq!(db.read("a".into()).await.unwrap(), Some(b"a".to_vec()));
+        assert_eq!(db.read("b".into()).await.unwrap(), Some(b"b".to_vec()));
+        drop(db);
+        let stale = EnvelopeKeys::open(kms, &wrapped).unwrap();
+        assert!(matches!(stale.data_key("db", 0), Err(HubError::Integrity(_))));
+
+        let file = dir.join("key.hex");
+        std::fs::write(&file, format!("{}\n", "ab".repeat(32))).unwrap();
+        assert_eq!(StaticKey::from_file(&file).unwrap().data_key("any", 0).unwrap().expose(), &[0xab; 32]);
+        assert!(matches!(SecretKey::from_hex("xyz"), Err(HubError::InvalidInput(_))));
+        assert!(StaticKey::from_env("COGNIVAULT_TEST_UNSET_KEY").is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[async_std::test]
+    async fn ciphertexts_are_bound_to_their_keys() {
+        use aes_gcm_siv::{aead::{Aead, KeyInit}, Aes256GcmSiv};
+        let dir = std::env::temp_dir().join(format!("cognivault-binding-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let key = [7u8; 32];
+        let legacy = |value: &[u8]| {
+            let plaintext = record::encode(&RecordMeta::next(None, value, &WriteMeta::default()), value).unwrap();
+            let nonce = [9u8; 12];
+            let ct = Aes256GcmSiv::new(&key.into()).encrypt(&nonce.into(), plaintext.as_slice()).unwrap();
+            [&nonce[..], &ct].concat()
+        };
+        let db = LongMem::open(&dir, Some(key)).unwrap();
+        db.write("x".into(), b"secret".to_vec()).await.unwrap();
+        db.write("y".into(), b"public".to_vec()).await.unwrap();
+        drop(db);
+
+        // swap values behind the store's back, and add one in the unbound format
+        let raw = reopen(|| sled::open(&dir));
+        raw.insert("y", raw.get("x").unwrap().unwrap()).unwrap();
+        raw.insert("legacy", legacy(b"old")).unwrap();
+        drop(raw);
+
+        let db = reopen(|| LongMem::open(&dir, Some(key)));
+        assert!(matches!(db.read("y".into()).await, Err(HubError::Integrity(_))));
+        // an overwrite does not silently restart the history of a value it cannot read
+        assert!(matches!(db.write("y".into(), b"new".to_vec()).await, Err(HubError::Integrity(_))));
+        assert_eq!(db.read("legacy".into()).await.unwrap(), Some(b"old".to_vec()));
+        db.set_require_binding(true).unwrap();
+        assert!(matches!(db.read("legacy".into()).await, Err(HubError::Integrity(_))));
+        db.set_require_binding(false).unwrap();
+        let pass = db.reencrypt_batch(10).unwrap();
+        assert_eq!((pass.rewritten, pass.skipped, pass.done), (1, 1, true));
+        assert_eq!(db.read("legacy".into()).await.unwrap(), Some(b"old".to_vec()));
+        assert_eq!(db.read("x".into()).await.unwrap(), Some(b"secret".to_vec()));
+        drop(db);
+
+        // the finished pass keeps unbound values out after a reopen
+        let raw = reopen(|| sled::open(&dir));
+        raw.insert("late", legacy(b"late")).unwrap();
+        drop(raw);
+        let db = reopen(|| LongMem::open(&dir, Some(key)));
+        assert!(matches!(db.read("late".into()).await, Err(HubError::Integrity(_))));
+        drop(db);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Open a database a handle dropped just before used, retrying while
+    /// sled's background threads still hold its lock.
+    fn reopen<T, E: std::fmt::Debug>(open: impl Fn() -> Result<T, E>) -> T {
+        for _ in 0..50 {
+            if let Ok(db) = open() {
+                return db;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        }
+        open().unwrap()
+    }
+}
+
+#[cfg(all(feature="detailmem_fs", test))]
+mod detailmem_tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[async_std::test]
+    async fn detailmem_keeps_expiry_and_data_in_one_file() {
+        let dir = std::env::temp_dir().join(format!("cognivault-detail-header-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let store = DetailMem::open(&dir).unwrap();
+        store.write_with_ttl("short".into(), b"v".to_vec(), Duration::from_millis(20)).await.unwrap();
+        assert!(!dir.join("short.meta").exists());
+        assert!(store.read_record("short".into()).await.unwrap().unwrap().meta.expires_at.is_some());
+        async_std::task::sleep(Duration::from_millis(40)).await;
+        assert_eq!(store.read("short".into()).await.unwrap(), None);
+
+        // layout from before headers: bare blob plus sidecar
+        std::fs::write(dir.join("old.bin"), b"legacy").unwrap();
+        std::fs::write(dir.join("old.meta"), br#"{"expires_at":1}"#).unwrap();
+        assert!(!store.contains("old".into()).await.unwrap());
+        std::fs::write(dir.join("old.meta"), b"{}").unwrap();
+        assert_eq!(store.read("old".into()).await.unwrap(), Some(b"legacy".to_vec()));
+        store.write("old".into(), b"new".to_vec()).await.unwrap();
+        assert!(!dir.join("old.meta").exists());
+        assert_eq!(store.read("old".into()).await.unwrap(), Some(b"new".to_vec()));
+
+        // keys refused since they must be safe paths move to one that is not
+        std::fs::create_dir_all(dir.join("x")).unwrap();
+        std::fs::write(dir.join("x/y.bin"), b"kept").unwrap();
+        assert!(store.read("x/./y".into()).await.is_err());
+        assert!(store.migrate_key("x/./y", "x/z").await.unwrap());
+        assert_eq!(store.read("x/z".into()).await.unwrap(), Some(b"kept".to_vec()));
+        assert!(!dir.join("x/y.bin").exists());
+        assert!(store.migrate_key("../x", "x/w").await.is_err());
+
+        // scans check blobs against their checksums like reads do
+        store.write("x/z".into(), b"kept".to_vec()).await.unwrap();
+        let mut blob = std::fs::read(dir.join("x/z.bin")).unwrap();
+        *blob.last_mut().unwrap() ^= 1;
+        std::fs::write(dir.join("x/z.bin"), blob).unwrap();
+        let mut scan = store.scan(ScanRange::Prefix("x/".into()));
+        assert!(matches!(futures::StreamExt::next(&mut scan).await, Some(Err(HubError::Corruption(_)))));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[async_std::test]
+    async fn detailmem_handles_serialize_on_key_locks() {
+        let dir = std::env::temp_dir().join(format!("cognivault-detail-locks-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let first = DetailMem::open(&dir).unwrap();
+        first.write("n".into(), 0u32.to_be_bytes().to_vec()).await.unwrap();
+        // separate handles share nothing in memory, only the lock files
+        let workers = (0..4).map(|_| {
+            let store = DetailMem::open(&dir).unwrap();
+            async_std::task::spawn(async move {
+                for _ in 0..10 {
+                    loop {
+                        let current = store.read_record("n".into()).await.unwrap().unwrap();
+                        let n = u32::from_be_bytes(current.value[..].try_into().unwrap());
+                        let next = (n + 1).to_be_bytes().to_vec();
+                        if store.compare_and_swap("n".into(), Expected::Version(current.meta.version), next).await.unwrap().is_swapped() { break; }
+                    }
+                }
+            })
+        });
+        futures::future::join_all(workers).await;
+        assert_eq!(first.read("n".into()).await.unwrap(), Some(40u32.to_be_bytes().to_vec()));
+        assert!(matches!(first.write(".locks/0".into(), vec![]).await, Err(HubError::InvalidInput(_))));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[async_std::test]
+    async fn detailmem_open_leaves_running_commits_alone() {
+        let dir = std::env::temp_dir().join(format!("cognivault-detail-recover-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let store = DetailMem::open(&dir).unwrap();
+        // every open runs recovery while commits are staged and applied
+        let opener = {
+            let dir = dir.clone();
+            std::thread::spawn(move || for _ in 0..200 { DetailMem::open(&dir).unwrap(); })
+        };
+        for i in 0..200u8 {
+            let mut tx = Transaction::new();
+            tx.put("a".into(), vec![i]).put("b".into(), vec![i]);
+            store.commit(tx).await.unwrap();
+        }
+        opener.join().unwrap();
+        assert_eq!(store.read("a".into()).await.unwrap(), Some(vec![199]));
+        assert_eq!(store.read("b".into()).await.unwrap(), Some(vec![199]));
+        assert_eq!(std::fs::read_dir(dir.join(".txn")).unwrap().count(), 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
+
+#[cfg(all(feature="detailmem_encrypt", test))]
+mod detailmem_encrypt_tests {
+    use super::*;
+    use futures::TryStreamExt;
+
+    #[async_std::test]
+    async fn detailmem_encrypts_blobs_and_detects_tampering() {
+        let dir = std::env::temp_dir().join(format!("cognivault-detail-encrypt-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        // written before encryption was configured
+        DetailMem::open(&dir).unwrap().write("old".into(), b"plain".to_vec()).await.unwrap();
+
+        let store = DetailMem::open_encrypted(&dir, Keyring::new(0, [7u8; 32])).unwrap();
+        assert!(store.capabilities().encrypted);
+        let large: Vec<u8> = (0..150_000u32).map(|i| i as u8).collect();
+        store.write("big".into(), large.clone()).await.unwrap();
+        store.write("__ns/acme/doc".into(), b"tenant".to_vec()).await.unwrap();
+        store.write("empty".into(), Vec::new()).await.unwrap();
+        assert!(!std::fs::read(dir.join("big.bin")).unwrap().windows(64).any(|w| w == &large[..64]));
+        assert_eq!(store.read("big".into()).await.unwrap(), Some(large.clone()));
+        assert_eq!(store.read("__ns/acme/doc".into()).await.unwrap(), Some(b"tenant".to_vec()));
+        assert_eq!(store.read("empty".into()).await.unwrap(), Some(Vec::new()));
+        // plaintext is refused unless allowed for a migration
+        assert!(matches!(store.read("old".into()).await, Err(HubError::Integrity(_))));
+        store.set_require_encryption(false);
+        assert_eq!(store.read("old".into()).await.unwrap(), Some(b"plain".to_vec()));
+        let all: Vec<_> = store.scan(ScanRange::All).try_collect().await.unwrap();
+        assert_eq!(all.len(), 4);
+
+        // old blobs stay readable after rotation
+        store.rotate_key(1, [8u8; 32]).unwrap();
+        store.write("new".into(), b"rotated".to_vec()).await.unwrap();
+        assert_eq!(store.read("big".into()).await.unwrap(), Some(large));
+
+        let integrity = |res: HubResult<Option<Vec<u8>>>| matches!(res, Err(HubError::Integrity(_)));
+        let mut blob = std::fs::read(dir.join("big.bin")).unwrap();
+        blob[100_000] ^= 1;
+        std::fs::write(dir.join("big.bin"), &blob).unwrap();
+        assert!(integrity(store.read("big".into()).await));
+        std::fs::copy(dir.join("new.bin"), dir.join("empty.bin")).unwrap();
+        assert!(integrity(store.read("empty".into()).await));
+        store.set_require_encryption(true);
+        assert!(integrity(store.read("old".into()).await));
+
+        // the metadata's flag is not trusted: clearing it does not make
+        // ciphertext plaintext, setting it does not make plaintext pass
+        let reflag = |name: &str, from: &str, to: &str| {
+            let path = dir.join(name);
+            let file = std::fs::read(&path).unwrap();
+            let len = u32::from_be_bytes(file[4..8].try_into().unwrap()) as usize;
+            let header = String::from_utf8(file[8..8 + len].to_vec()).unwrap();
+            assert!(header.contains(from));
+            let header = header.replacen(from, to, 1);
+            let rewritten = [&file[..4], &(header.len() as u32).to_be_bytes(), header.as_bytes(), &file[8 + len..]].concat();
+            std::fs::write(&path, rewritten).unwrap();
+        };
+        reflag("new.bin", ",\"encrypted\":true", "");
+        assert_eq!(store.read("new".into()).await.unwrap(), Some(b"rotated".to_vec()));
+        reflag("old.bin", "}}", "},\"encrypted\":true}");
+        assert!(integrity(store.read("old".into()).await));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
+
+#[cfg(all(feature="key_derivation", test))]
+mod kdf_tests {
+    use super::*;
+
+    #[test]
+    fn passphrase_keys_are_deterministic_per_salt() {
+        let argon = Kdf::Argon2id { memory_kib: 64, iterations: 1, parallelism: 1 };
+        let scrypt = Kdf::Scrypt { log_n: 4, r: 8, p: 1 };
+        let key = |kdf, salt: &[u8]| PassphraseKey::derive(b"correct horse", salt, kdf).unwrap().data_key("db", 0).unwrap();
+        assert_eq!(key(argon, b"salt-one"), key(argon, b"salt-one"));
+        assert_ne!(key(argon, b"salt-one"), key(argon, b"salt-two"));
+        assert_ne!(key(argon, b"salt-one"), key(scrypt, b"salt-one"));
+        assert_eq!(key(scrypt, b"salt-one"), key(scrypt, b"salt-one"));
+        assert!(PassphraseKey::derive(b"pw", b"short", scrypt).is_err());
+        assert!(PassphraseKey::derive(b"pw", b"short", argon).is_err());
+    }
+}
+
+#[cfg(all(feature="merkle_log", test))]
+mod audit_tests {
+    use super::*;
+    use futures::TryStreamExt;
+    use std::sync::Arc;
+
+    #[async_std::test]
+    async fn merkle_audit_log_detects_tampering() {
+        let dir = std::env::temp_dir().join(format!("cognivault-merkle-audit-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let sink = Arc::new(MerkleAuditSink::open(&dir).unwrap());
+        let hub = MemoryHub::new();
+        hub.register_backend(Box::new(ShortMem::default()));
+        hub.set_audit_sink(sink.clone());
+        for k in ["x", "y", "z"] {
+            hub.write(k.into(), k.as_bytes().to_vec()).await.unwrap();
+        }
+        let root = sink.verify().unwrap();
+        assert_eq!(root, sink.root().unwrap());
+        drop(hub);
+        drop(sink);
+
+        // a line torn by a crash is dropped on open
+        let events = dir.join("audit.jsonl");
+        let mut file = std::fs::OpenOptions::new().append(true).open(&events).unwrap();
+        std::io::Write::write_all(&mut file, b"{\"at_millis\":1,").unwrap();
+        drop(file);
+
+        // reopening continues the chain
+        let sink = Arc::new(MerkleAuditSink::open(&dir).unwrap());
+        assert_eq!(sink.verify().unwrap(), root);
+        assert_eq!(sink.query(&AuditQuery::new().key_prefix("y")).unwrap().len(), 1);
+        let hub = MemoryHub::new();
+        hub.register_backend(Box::new(ShortMem::default()));
+        hub.set_audit_sink(sink.clone());
+        for k in ["p/1", "p/2", "q/1"] {
+            hub.write(k.into(), vec![1]).await.unwrap();
+        }
+        let keys: Vec<String> = hub.scan_keys(ScanRange::Prefix("p/".into())).try_collect().await.unwrap();
+        drop(hub);
+        assert_eq!(sink.verify().unwrap(), sink.root().unwrap());
+        let scans = sink.query(&AuditQuery::new().key("p/")).unwrap();
+        assert_eq!((scans.len(), &scans[0].returned), (1, &keys));
+
+        let text = std::fs::read_to_string(&events).unwrap();
+        std::fs::write(&events, text.replacen("\"y\"", "\"w\"", 1)).unwrap();
+        let err = sink.verify().unwrap_err();
+        assert!(matches!(&err, HubError::Corruption(msg) if msg.contains("event 2")), "{err}");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
+
+#[cfg(all(feature="merkle_log", test))]
+mod repair_tests {
+    use super::*;
+
+    #[async_std::test]
+    async fn anti_entropy_reconciles_differing_buckets() {
+        let hub = MemoryHub::new();
+        let a = ShortMem::default();
+        let b = ShortMem::default();
+        for k in ["aa/1", "aa/2", "bb/1"] {
+            a.write(k.into(), b"same".to_vec()).await.unwrap();
+            b.write(k.into(), b"same".to_vec()).await.unwrap();
+        }
+        a.write("cc/1".into(), b"only-a".to_vec()).await.unwrap();
+        b.write("aa/2".into(), b"drift".to_vec()).await.unwrap();
+        hub.register_backend(Box::new(a));
+        hub.register_backend(Box::new(b));
+
+        let report = hub.anti_entropy(2).await.unwrap();
+        assert_eq!(report.buckets_compared, 3);
+        assert_eq!(report.buckets_mismatched, 2);
+        assert_eq!(report.keys_repaired, 2);
+        let again = hub.anti_entropy(2).await.unwrap();
+        assert_eq!(again.buckets_mismatched, 0);
+    }
+}
@@ -1,73 +1,695 @@
-use crate::backend::{HubResult, MemoryBackend};
+use crate::backend::{EntryStream, HubResult, MemoryBackend, ScanRange};
+use crate::error::HubError;
+use crate::health::{Capabilities, Health};
+use crate::record::{self, CasOutcome, Expected, Record, RecordMeta, WriteMeta};
+use crate::ttl::{deadline_millis, now_millis};
+use crate::txn::{Transaction, TxOp};
 use async_trait::async_trait;
-#[cfg(feature = "longmem_encrypt")] use aes_gcm_siv::{aead::{Aead, KeyInit, OsRng, generic_array::GenericArray}, Aes256GcmSiv};
+use futures::stream::{self, StreamExt};
+use sled::Transactional;
+use sled::transaction::{ConflictableTransactionError, TransactionError, TransactionalTree};
+use std::time::Duration;
+#[cfg(feature = "longmem_encrypt")] use crate::cancellation::CancellationToken;
+#[cfg(feature = "longmem_encrypt")] use crate::keyring::{self, Keyring, FORMAT_V1, FORMAT_V2, LEGACY_KEY_ID};
+#[cfg(feature = "longmem_encrypt")] use crate::keys::{KeyProvider, SecretKey};
+#[cfg(feature = "longmem_encrypt")] use crate::{namespace, runtime};
+#[cfg(feature = "longmem_encrypt")] use arc_swap::ArcSwap;
+#[cfg(feature = "longmem_encrypt")] use dashmap::DashMap;
+#[cfg(feature = "longmem_encrypt")] use std::ops::Bound;
+#[cfg(feature = "longmem_encrypt")] use std::sync::Arc;
+#[cfg(feature = "longmem_encrypt")] use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(feature = "longmem_encrypt")] use aes_gcm_siv::{aead::{Aead, KeyInit, OsRng, Payload, generic_array::GenericArray}, Aes256GcmSiv};
+
+/// Domain of the associated data binding values to their keys.
+#[cfg(feature = "longmem_encrypt")]
+const AAD_DOMAIN: &str = "cognivault/longmem/v2";
+/// Domain of the associated data binding a wrapped namespace key to its tenant.
+#[cfg(feature = "longmem_encrypt")]
+const TENANT_KEY_DOMAIN: &str = "cognivault/longmem/tenant-key";
+/// Key of the re-encryption cursor in the `__reencrypt` tree.
+#[cfg(feature = "longmem_encrypt")]
+const CURSOR: &[u8] = b"cursor";
+/// Present in the `__reencrypt` tree while unbound values are refused.
+#[cfg(feature = "longmem_encrypt")]
+const REQUIRE_BINDING: &[u8] = b"require_binding";
 
 /// Persistent storage backend backed by sled key-value database.
+/// Values are stored inside a [`Record`] envelope; entries written before
+/// envelopes existed read back as legacy records.
 /// If feature `longmem_encrypt` is enabled, values are encrypted with random nonce
-/// using AES-256-GCM-SIV.
+/// using AES-256-GCM-SIV. Keys of a tenant namespace (`__ns/<tenant>/…`) are
+/// encrypted with a per-tenant key, derived from the database key unless one
+/// is set with [`set_namespace_key`](Self::set_namespace_key). Such keys are
+/// kept in the `__tenant_keys` tree, encrypted with the database key.
+///
+/// Database keys live in a [`Keyring`]. Each ciphertext starts with a header
+/// naming the format and the id of the key that encrypted it, so values are
+/// read with any known key and written with the active one. After
+/// [`rotate_key`](Self::rotate_key), [`reencrypt_batch`](Self::reencrypt_batch)
+/// moves old values to the active key; its cursor is kept in the
+/// `__reencrypt` tree, so a pass resumes where it stopped after a crash.
+///
+/// The key name and namespace of a value are bound in as associated data, so
+/// a ciphertext copied to another key fails to decrypt with
+/// [`HubError::Integrity`]. Values from before the binding still read until
+/// a re-encryption pass has migrated them; from then on unbound values are
+/// refused, see [`set_require_binding`](Self::set_require_binding).
+///
+/// Expiring keys are tracked in two extra trees: `__ttl` maps a key to its
+/// deadline and `__ttl_idx` orders `(deadline, key)` so the sweeper can stop
+/// at the first entry that is not yet due. Transactions run as one sled
+/// transaction over the data and both expiry trees.
+#[derive(Clone)]
 pub struct LongMem {
     db: sled::Db,
+    ttl: sled::Tree,
+    ttl_idx: sled::Tree,
+    /// Database keys; the per-tenant keys are derived from them.
+    #[cfg(feature = "longmem_encrypt")]
+    keyring: Arc<ArcSwap<Keyring>>,
+    /// Ciphers of the tenants seen so far, by database key id; the same
+    /// for every id when the tenant has a namespace key.
+    #[cfg(feature = "longmem_encrypt")]
+    tenant_ciphers: Arc<DashMap<(u32, String), Aes256GcmSiv>>,
+    /// Namespace keys set with `set_namespace_key`, wrapped, by tenant.
+    #[cfg(feature = "longmem_encrypt")]
+    tenant_keys: sled::Tree,
+    #[cfg(feature = "longmem_encrypt")]
+    reencrypt: sled::Tree,
     #[cfg(feature = "longmem_encrypt")]
-    cipher: Aes256GcmSiv,
+    require_binding: Arc<AtomicBool>,
+}
+
+/// Result of one [`LongMem::reencrypt_batch`] call.
+#[cfg(feature = "longmem_encrypt")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReencryptProgress {
+    pub scanned: usize,
+    /// Values moved to the active key or the current format.
+    pub rewritten: usize,
+    /// Values that could not be decrypted and were left as they are.
+    pub skipped: usize,
+    /// The pass reached the end of the database.
+    pub done: bool,
 }
 
 impl LongMem {
-    /// Open/create database at `path`. When encryption is enabled, `key` must be Some(32 bytes).
+    /// Open/create database at `path`. When encryption is enabled, `key` must be Some(32 bytes);
+    /// it becomes key id `0` of the keyring, the id values from before key ids were recorded have.
     pub fn open(path: &std::path::Path, key: Option<[u8;32]>) -> HubResult<Self> {
-        let db = sled::open(path)?;
         #[cfg(feature = "longmem_encrypt")]
         {
-            let key_bytes = key.ok_or_else(|| anyhow::anyhow!("encryption key required"))?;
-            let cipher = Aes256GcmSiv::new(GenericArray::from_slice(&key_bytes));
-            Ok(Self { db, cipher })
+            let key_bytes = key.ok_or_else(|| HubError::Crypto("encryption key required".into()))?;
+            Self::open_with_keyring(path, Keyring::new(LEGACY_KEY_ID, key_bytes))
         }
         #[cfg(not(feature = "longmem_encrypt"))]
         {
             let _ = key;
-            Ok(Self { db })
+            let db = sled::open(path)?;
+            let ttl = db.open_tree("__ttl")?;
+            let ttl_idx = db.open_tree("__ttl_idx")?;
+            Ok(Self { db, ttl, ttl_idx })
+        }
+    }
+
+    /// Open/create the database at `path`, encrypting with the active key of `keyring`.
+    #[cfg(feature = "longmem_encrypt")]
+    pub fn open_with_keyring(path: &std::path::Path, keyring: Keyring) -> HubResult<Self> {
+        let db = sled::open(path)?;
+        let ttl = db.open_tree("__ttl")?;
+        let ttl_idx = db.open_tree("__ttl_idx")?;
+        let reencrypt = db.open_tree("__reencrypt")?;
+        let tenant_keys = db.open_tree("__tenant_keys")?;
+        let keyring = Arc::new(ArcSwap::from_pointee(keyring));
+        let require_binding = Arc::new(AtomicBool::new(reencrypt.contains_key(REQUIRE_BINDING)?));
+        Ok(Self { db, ttl, ttl_idx, keyring, tenant_ciphers: Arc::default(), tenant_keys, reencrypt, require_binding })
+    }
+
+    /// Refuse values whose ciphertext is not bound to its key with
+    /// [`HubError::Integrity`], so that unbound copies of old values cannot
+    /// be swapped in. A finished re-encryption pass turns this on. The
+    /// setting is kept in the `__reencrypt` tree and holds after a reopen.
+    #[cfg(feature = "longmem_encrypt")]
+    pub fn set_require_binding(&self, required: bool) -> HubResult<()> {
+        if required {
+            self.reencrypt.insert(REQUIRE_BINDING, &[])?;
+        } else {
+            self.reencrypt.remove(REQUIRE_BINDING)?;
         }
+        self.require_binding.store(required, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Open/create the database at `path` with the keys `provider` holds for
+    /// `scope`, e.g. the database name.
+    #[cfg(feature = "longmem_encrypt")]
+    pub fn open_with_provider(path: &std::path::Path, provider: &dyn KeyProvider, scope: &str) -> HubResult<Self> {
+        Self::open_with_keyring(path, Keyring::from_provider(provider, scope)?)
     }
 
+    /// Snapshot of the current keyring.
     #[cfg(feature = "longmem_encrypt")]
-    fn encrypt(&self, plaintext: &[u8]) -> HubResult<Vec<u8>> {
+    pub fn keyring(&self) -> Arc<Keyring> {
+        self.keyring.load_full()
+    }
+
+    /// Replace the keyring, e.g. to drop a key no value uses any more.
+    #[cfg(feature = "longmem_encrypt")]
+    pub fn set_keyring(&self, keyring: Keyring) {
+        self.keyring.store(Arc::new(keyring));
+    }
+
+    /// Add `key` under `id` and encrypt new values with it. Existing values
+    /// stay readable with their old key until re-encrypted.
+    #[cfg(feature = "longmem_encrypt")]
+    pub fn rotate_key(&self, id: u32, key: impl Into<SecretKey>) -> HubResult<()> {
+        let mut ring = Keyring::clone(&self.keyring.load());
+        ring.add(id, key)?;
+        ring.set_active(id)?;
+        self.set_keyring(ring);
+        Ok(())
+    }
+
+    /// Use `key` for the values of namespace `tenant` instead of the derived
+    /// key, whichever database key id they carry. Must be set before the
+    /// tenant's first write: fails with [`HubError::Conflict`] if the tenant
+    /// has values under another key. Setting the current key again does
+    /// nothing. The key is stored wrapped with the active database key, so
+    /// it applies again after a reopen; after a rotation
+    /// [`reencrypt_batch`](Self::reencrypt_batch) wraps it with the new key.
+    #[cfg(feature = "longmem_encrypt")]
+    pub fn set_namespace_key(&self, tenant: &str, key: [u8; 32]) -> HubResult<()> {
+        if let Some(wrapped) = self.tenant_keys.get(tenant.as_bytes())?
+            && unwrap_tenant_key(&self.keyring.load(), tenant, &wrapped)?.as_slice() == key
+        {
+            return Ok(());
+        }
+        if self.db.scan_prefix(namespace::prefix_of(tenant)).next().transpose()?.is_some() {
+            return Err(HubError::Conflict(format!("namespace {tenant} holds values encrypted with another key")));
+        }
+        self.tenant_keys.insert(tenant.as_bytes(), wrap_tenant_key(&self.keyring.load(), tenant, &key)?)?;
+        self.tenant_ciphers.retain(|(_, cached), _| cached != tenant);
+        Ok(())
+    }
+
+    /// Key set for namespace `tenant` with [`set_namespace_key`](Self::set_namespace_key),
+    /// if any.
+    #[cfg(feature = "longmem_encrypt")]
+    fn namespace_key(&self, tenant: &str) -> HubResult<Option<Aes256GcmSiv>> {
+        let Some(wrapped) = self.tenant_keys.get(tenant.as_bytes())? else { return Ok(None) };
+        let key = unwrap_tenant_key(&self.keyring.load(), tenant, &wrapped)?;
+        Ok(Some(Aes256GcmSiv::new(GenericArray::from_slice(&key))))
+    }
+
+    /// Wrap the namespace keys still wrapped with an older database key
+    /// with the active one.
+    #[cfg(feature = "longmem_encrypt")]
+    fn rewrap_tenant_keys(&self) -> HubResult<()> {
+        let ring = self.keyring.load();
+        for entry in self.tenant_keys.iter() {
+            let (name, wrapped) = entry?;
+            if matches!(keyring::split_header(&wrapped), Some((FORMAT_V2, id, _)) if id == ring.active()) {
+                continue;
+            }
+            let tenant = String::from_utf8_lossy(&name);
+            let key = unwrap_tenant_key(&ring, &tenant, &wrapped)?;
+            let rewrapped = wrap_tenant_key(&ring, &tenant, key.as_slice().try_into().expect("unwrapped keys are 32 bytes"))?;
+            // a concurrent set_namespace_key wins
+            let _ = self.tenant_keys.compare_and_swap(&name, Some(wrapped), Some(rewrapped))?;
+        }
+        Ok(())
+    }
+
+    /// Cipher protecting `key` under database key `id`: the tenant's for
+    /// namespaced keys, the database's otherwise.
+    #[cfg(feature = "longmem_encrypt")]
+    fn cipher_for(&self, id: u32, key: &str) -> HubResult<Aes256GcmSiv> {
+        let ring = self.keyring.load();
+        let Some(tenant) = namespace::tenant_of(key) else { return ring.cipher_for(id, key) };
+        if let Some(cipher) = self.tenant_ciphers.get(&(id, tenant.to_string())) {
+            return Ok(cipher.clone());
+        }
+        let cipher = match self.namespace_key(tenant)? {
+            Some(cipher) => cipher,
+            None => ring.cipher_for(id, key)?,
+        };
+        Ok(self.tenant_ciphers.entry((id, tenant.to_string())).or_insert(cipher).clone())
+    }
+
+    #[cfg(feature = "longmem_encrypt")]
+    fn encrypt(&self, key: &str, plaintext: &[u8]) -> HubResult<Vec<u8>> {
         use aes_gcm_siv::aead::rand_core::RngCore;
+        let id = self.keyring.load().active();
         let mut nonce = [0u8; 12];
         OsRng.fill_bytes(&mut nonce);
         let nonce_ga = GenericArray::from_slice(&nonce);
-        let mut ciphertext = self.cipher.encrypt(nonce_ga, plaintext)?;
-        // prepend nonce
-        let mut combined = nonce.to_vec();
+        let payload = Payload { msg: plaintext, aad: &keyring::associated_data(AAD_DOMAIN, key) };
+        let mut ciphertext = self.cipher_for(id, key)?.encrypt(nonce_ga, payload)
+            .map_err(|_| HubError::Crypto("encryption failed".into()))?;
+        // header, then nonce
+        let mut combined = keyring::header(FORMAT_V2, id).to_vec();
+        combined.extend_from_slice(&nonce);
         combined.append(&mut ciphertext);
         Ok(combined)
     }
 
     #[cfg(feature = "longmem_encrypt")]
-    fn decrypt(&self, data: &[u8]) -> HubResult<Vec<u8>> {
-        if data.len() < 12 { return Err(anyhow::anyhow!("ciphertext too short")); }
+    fn decrypt(&self, key: &str, data: &[u8]) -> HubResult<Vec<u8>> {
+        self.unseal(key, data).map(|(plaintext, ..)| plaintext)
+    }
+
+    /// Plaintext of `data` with the id of the key that decrypted it and the
+    /// format it was stored in.
+    #[cfg(feature = "longmem_encrypt")]
+    fn unseal(&self, key: &str, data: &[u8]) -> HubResult<(Vec<u8>, u32, u8)> {
+        let legacy = || self.open_sealed(LEGACY_KEY_ID, FORMAT_V1, key, data).map(|p| (p, LEGACY_KEY_ID, FORMAT_V1));
+        match keyring::split_header(data) {
+            // bound values never fall back to the unbound layout
+            Some((FORMAT_V2, id, rest)) => self.open_sealed(id, FORMAT_V2, key, rest).map(|p| (p, id, FORMAT_V2)),
+            Some((FORMAT_V1, id, rest)) => match self.open_sealed(id, FORMAT_V1, key, rest) {
+                Ok(plaintext) => Ok((plaintext, id, FORMAT_V1)),
+                // a headerless value whose nonce happens to look like a header
+                Err(e) => legacy().map_err(|_| e),
+            },
+            _ => legacy(),
+        }
+    }
+
+    /// Decrypt nonce and ciphertext `data` stored in format `version`.
+    #[cfg(feature = "longmem_encrypt")]
+    fn open_sealed(&self, id: u32, version: u8, key: &str, data: &[u8]) -> HubResult<Vec<u8>> {
+        if version < FORMAT_V2 && self.require_binding.load(Ordering::Relaxed) {
+            return Err(HubError::Integrity(format!("{key} is not bound to its key")));
+        }
+        if data.len() < 12 { return Err(HubError::Corruption(format!("ciphertext of {key} too short"))); }
         let (nonce, ct) = data.split_at(12);
         let nonce_ga = GenericArray::from_slice(nonce);
-        Ok(self.cipher.decrypt(nonce_ga, ct)? )
+        let aad = if version >= FORMAT_V2 { keyring::associated_data(AAD_DOMAIN, key) } else { Vec::new() };
+        self.cipher_for(id, key)?.decrypt(nonce_ga, Payload { msg: ct, aad: &aad })
+            .map_err(|_| HubError::Integrity(format!("{key} failed authentication")))
+    }
+
+    /// Move up to `limit` stored values, from where the previous call
+    /// stopped, to the active key and the current format, binding them to
+    /// their key. Values written meanwhile already are and are left alone. The cursor restarts from the beginning when the
+    /// active key changes. Namespace keys are wrapped with the active key
+    /// first. A `limit` of zero is refused with [`HubError::InvalidInput`].
+    /// Once a pass is done, unbound values are refused as with
+    /// [`set_require_binding`](Self::set_require_binding).
+    #[cfg(feature = "longmem_encrypt")]
+    pub fn reencrypt_batch(&self, limit: usize) -> HubResult<ReencryptProgress> {
+        if limit == 0 {
+            return Err(HubError::InvalidInput("re-encryption batch limit must be positive".into()));
+        }
+        self.rewrap_tenant_keys()?;
+        let active = self.keyring.load().active();
+        let start = match self.reencrypt.get(CURSOR)? {
+            Some(c) if c.len() >= 4 && c[..4] == active.to_be_bytes() => Bound::Excluded(c[4..].to_vec()),
+            _ => Bound::Unbounded,
+        };
+        let mut progress = ReencryptProgress::default();
+        let mut last = None;
+        for entry in self.db.range::<Vec<u8>, _>((start, Bound::Unbounded)).take(limit) {
+            let (k, v) = entry?;
+            progress.scanned += 1;
+            let key = String::from_utf8_lossy(&k).into_owned();
+            match self.unseal(&key, &v) {
+                Ok((_, id, FORMAT_V2)) if id == active => {}
+                Ok((plaintext, ..)) => {
+                    let stored = self.encrypt(&key, &plaintext)?;
+                    if self.db.compare_and_swap(&k, Some(v), Some(stored))?.is_ok() {
+                        progress.rewritten += 1;
+                    }
+                }
+                Err(e) => {
+                    log::warn!("re-encryption skipped {key}: {e}");
+                    progress.skipped += 1;
+                }
+            }
+            last = Some(k);
+        }
+        match last {
+            Some(k) if progress.scanned == limit => {
+                self.reencrypt.insert(CURSOR, [&active.to_be_bytes()[..], &k].concat())?;
+            }
+            _ => {
+                self.reencrypt.remove(CURSOR)?;
+                self.set_require_binding(true)?;
+                progress.done = true;
+            }
+        }
+        Ok(progress)
+    }
+
+    /// Run [`reencrypt_batch`](Self::reencrypt_batch) with batches of `batch`
+    /// values, pausing `pause` in between, until the pass is done or the
+    /// returned token is cancelled.
+    #[cfg(feature = "longmem_encrypt")]
+    pub fn spawn_reencryption(&self, batch: usize, pause: Duration) -> CancellationToken {
+        let token = CancellationToken::new();
+        let (this, stop) = (self.clone(), token.clone());
+        runtime::spawn(async move {
+            while !stop.is_cancelled() {
+                // a failed batch is retried after the pause
+                if this.reencrypt_batch(batch).is_ok_and(|p| p.done) { break; }
+                runtime::sleep(pause).await;
+            }
+        });
+        token
+    }
+
+    /// Whether `key` carries a deadline that has passed.
+    fn is_expired(&self, key: &[u8]) -> HubResult<bool> {
+        if self.ttl.is_empty() { return Ok(false); }
+        Ok(self.ttl.get(key)?.is_some_and(|d| decode_deadline(&d) <= now_millis()))
+    }
+
+    /// Store a new version of the value and set (or clear) its deadline,
+    /// `meta.expires_at`, in one transaction.
+    fn put(&self, key: &str, value: &[u8], meta: &WriteMeta) -> HubResult<()> {
+        let deadline = meta.expires_at;
+        if deadline.is_none() && !self.ttl.contains_key(key)? {
+            // compare-and-swap so concurrent writers never reuse a version
+            loop {
+                let old = self.db.get(key)?;
+                let stored = self.next_version(key, old.as_deref(), value, meta)?;
+                if self.db.compare_and_swap(key, old, Some(stored))?.is_ok() {
+                    return Ok(());
+                }
+            }
+        }
+        (&*self.db, &self.ttl, &self.ttl_idx).transaction(|(data, ttl, idx)| {
+            let stored = self.next_version(key, data.get(key)?.as_deref(), value, meta)
+                .map_err(ConflictableTransactionError::Abort)?;
+            set_deadline(ttl, idx, key.as_bytes(), deadline)?;
+            data.insert(key.as_bytes(), stored)?;
+            Ok(())
+        }).map_err(tx_error)
+    }
+
+    /// Encoded envelope for `value`, versioned after the currently stored bytes.
+    /// A stored value that fails to decode or verify fails the write, rather
+    /// than restarting the key's history at version 1.
+    fn next_version(&self, key: &str, old: Option<&[u8]>, value: &[u8], meta: &WriteMeta) -> HubResult<Vec<u8>> {
+        let prev = old.map(|o| self.decode(key, o)).transpose()?;
+        self.encode(key, &RecordMeta::next(prev.as_ref().map(|r| &r.meta), value, meta), value)
+    }
+
+    /// Remove a value together with its deadline. Returns the previous stored bytes.
+    fn remove(&self, key: &[u8]) -> HubResult<Option<sled::IVec>> {
+        (&*self.db, &self.ttl, &self.ttl_idx).transaction(|(data, ttl, idx)| {
+            clear_deadline::<sled::Error>(ttl, idx, key)?;
+            Ok(data.remove(key)?)
+        }).map_err(tx_error)
+    }
+
+    /// Turn a value and its metadata into the bytes stored in sled.
+    fn encode(&self, key: &str, meta: &RecordMeta, value: &[u8]) -> HubResult<Vec<u8>> {
+        let plaintext = record::encode(meta, value)?;
+        #[cfg(feature = "longmem_encrypt")]
+        { self.encrypt(key, &plaintext) }
+        #[cfg(not(feature = "longmem_encrypt"))]
+        { let _ = key; Ok(plaintext) }
+    }
+
+    /// Turn a stored sled value back into a checksum-verified record.
+    fn decode(&self, key: &str, stored: &[u8]) -> HubResult<Record> {
+        #[cfg(feature = "longmem_encrypt")]
+        let plaintext = self.decrypt(key, stored)?;
+        #[cfg(not(feature = "longmem_encrypt"))]
+        let plaintext = stored.to_vec();
+        record::decode(plaintext).checked(key)
     }
 }
 
 #[async_trait]
 impl MemoryBackend for LongMem {
-    async fn write(&self, key: String, mut value: Vec<u8>) -> HubResult<()> {
-        #[cfg(feature = "longmem_encrypt")]
-        { value = self.encrypt(&value)?; }
-        self.db.insert(key, value)?;
-        Ok(())
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            persistent: true,
+            encrypted: cfg!(feature = "longmem_encrypt"),
+            scan: true,
+            ttl: true,
+            compare_and_swap: true,
+            transactions: true,
+            metadata: true,
+        }
+    }
+
+    async fn health(&self) -> Health {
+        // a point read exercises the page cache and, on a miss, the log
+        match self.db.get("__health") {
+            Ok(_) => Health::Healthy,
+            Err(e) => Health::Unhealthy(e.to_string()),
+        }
+    }
+
+    async fn write(&self, key: String, value: Vec<u8>) -> HubResult<()> {
+        self.put(&key, &value, &WriteMeta::default())
+    }
+
+    async fn write_with_ttl(&self, key: String, value: Vec<u8>, ttl: Duration) -> HubResult<()> {
+        self.put(&key, &value, &WriteMeta { expires_at: Some(deadline_millis(ttl)), ..Default::default() })
+    }
+
+    async fn write_with_meta(&self, key: String, value: Vec<u8>, meta: WriteMeta) -> HubResult<()> {
+        self.put(&key, &value, &meta)
+    }
+
+    async fn compare_and_swap(&self, key: String, expected: Expected, new: Vec<u8>) -> HubResult<CasOutcome> {
+        self.compare_and_swap_with_meta(key, expected, new, WriteMeta::default()).await
+    }
+
+    async fn compare_and_swap_with_meta(&self, key: String, expected: Expected, new: Vec<u8>, meta: WriteMeta) -> HubResult<CasOutcome> {
+        loop {
+            let old = self.db.get(&key)?;
+            let current = match &old {
+                Some(v) if !self.is_expired(key.as_bytes())? => Some(self.decode(&key, v)?),
+                _ => None,
+            };
+            if !expected.matches(current.as_ref()) {
+                return Ok(CasOutcome::Conflict { current });
+            }
+            let next = RecordMeta::next(current.as_ref().map(|r| &r.meta), &new, &meta);
+            let stored = self.encode(&key, &next, &new)?;
+            let swapped = if meta.expires_at.is_some() || self.ttl.contains_key(&key)? {
+                // the deadline has to go in the same step as the swap
+                (&*self.db, &self.ttl, &self.ttl_idx).transaction(|(data, ttl, idx)| {
+                    if data.get(&key)? != old { return Ok(false); }
+                    set_deadline::<sled::Error>(ttl, idx, key.as_bytes(), meta.expires_at)?;
+                    data.insert(key.as_bytes(), stored.as_slice())?;
+                    Ok(true)
+                }).map_err(tx_error)?
+            } else {
+                self.db.compare_and_swap(&key, old, Some(stored))?.is_ok()
+            };
+            if swapped {
+                return Ok(CasOutcome::Swapped { version: next.version });
+            }
+            // lost the race: re-evaluate against the new value
+        }
+    }
+
+    async fn commit(&self, tx: Transaction) -> HubResult<()> {
+        let meta = WriteMeta { version: tx.version(), ..Default::default() };
+        let ops = tx.into_ops();
+        (&*self.db, &self.ttl, &self.ttl_idx).transaction(|(data, ttl, idx)| {
+            for op in &ops {
+                let key = op.key().as_bytes();
+                clear_deadline(ttl, idx, key)?;
+                match op {
+                    TxOp::Put { key, value } => {
+                        // reads inside the transaction see its earlier ops
+                        let stored = self.next_version(key, data.get(key)?.as_deref(), value, &meta)
+                            .map_err(ConflictableTransactionError::Abort)?;
+                        data.insert(key.as_bytes(), stored)?;
+                    }
+                    TxOp::Delete { .. } => { data.remove(key)?; }
+                }
+            }
+            Ok(())
+        }).map_err(tx_error)
+    }
+
+    async fn sweep_expired(&self) -> HubResult<usize> {
+        let now = now_millis();
+        let mut removed = 0;
+        // the index is ordered by deadline: stop at the first entry not yet due
+        for entry in self.ttl_idx.range(..index_key(now.saturating_add(1), &[])) {
+            let (idx_key, _) = entry?;
+            let key = &idx_key[8..];
+            if self.is_expired(key)? && self.remove(key)?.is_some() {
+                removed += 1;
+            } else {
+                // stale index entry whose key was rewritten or removed
+                self.ttl_idx.remove(&idx_key)?;
+            }
+        }
+        Ok(removed)
+    }
+
+    async fn write_many(&self, items: &[(String, Vec<u8>)]) -> Vec<HubResult<()>> {
+        self.write_many_with_meta(items, WriteMeta::default()).await
+    }
+
+    async fn write_many_with_meta(&self, items: &[(String, Vec<u8>)], meta: WriteMeta) -> Vec<HubResult<()>> {
+        if meta.expires_at.is_some() {
+            // deadlines are set per key, outside what a sled batch covers
+            return items.iter().map(|(k, v)| self.put(k, v, &meta)).collect();
+        }
+        // Encode every item first; items that fail stay out of the sled batch.
+        // Versions not set by `meta` follow the stored values read here, so a
+        // concurrent single write may be overtaken by the batch with the same version.
+        let mut batch = sled::Batch::default();
+        let mut results: Vec<HubResult<()>> = Vec::with_capacity(items.len());
+        for (k, v) in items {
+            let enc = self.db.get(k).map_err(Into::into)
+                .and_then(|old| self.next_version(k, old.as_deref(), v, &meta));
+            results.push(enc.map(|enc| batch.insert(k.as_bytes(), enc)));
+        }
+        if let Err(e) = self.db.apply_batch(batch) {
+            let msg = e.to_string();
+            for res in results.iter_mut().filter(|r| r.is_ok()) {
+                *res = Err(HubError::Io(std::io::Error::other(format!("batch apply failed: {msg}"))));
+            }
+            return results;
+        }
+        // plain writes clear earlier expiries
+        if !self.ttl.is_empty() {
+            for ((k, _), res) in items.iter().zip(results.iter_mut()) {
+                if res.is_ok() && self.ttl.contains_key(k).unwrap_or(true) {
+                    let cleared = (&self.ttl, &self.ttl_idx)
+                        .transaction(|(ttl, idx)| clear_deadline::<sled::Error>(ttl, idx, k.as_bytes()))
+                        .map_err(tx_error);
+                    *res = cleared;
+                }
+            }
+        }
+        results
     }
 
     async fn read(&self, key: String) -> HubResult<Option<Vec<u8>>> {
-        match self.db.get(key)? {
+        match self.db.get(&key)? {
+            Some(_) if self.is_expired(key.as_bytes())? => Ok(None),
+            Some(v) => Ok(Some(self.decode(&key, &v)?.value)),
+            None => Ok(None)
+        }
+    }
+
+    async fn read_record(&self, key: String) -> HubResult<Option<Record>> {
+        match self.db.get(&key)? {
+            Some(_) if self.is_expired(key.as_bytes())? => Ok(None),
             Some(v) => {
-                let mut bytes = v.to_vec();
-                #[cfg(feature = "longmem_encrypt")]
-                { bytes = self.decrypt(&bytes)?; }
-                Ok(Some(bytes))
+                let mut record = self.decode(&key, &v)?;
+                // envelopes from before expiries were recorded in them
+                if record.meta.expires_at.is_none() && !self.ttl.is_empty() {
+                    record.meta.expires_at = self.ttl.get(&key)?.map(|d| decode_deadline(&d));
+                }
+                Ok(Some(record))
             }
             None => Ok(None)
         }
     }
-} 
\ No newline at end of file
+
+    async fn delete(&self, key: String) -> HubResult<bool> {
+        let expired = self.is_expired(key.as_bytes())?;
+        Ok(self.remove(key.as_bytes())?.is_some() && !expired)
+    }
+
+    async fn contains(&self, key: String) -> HubResult<bool> {
+        Ok(self.db.contains_key(&key)? && !self.is_expired(key.as_bytes())?)
+    }
+
+    fn scan(&self, range: ScanRange) -> EntryStream {
+        // sled iterates in key order, so the stream is already sorted.
+        let iter = match range {
+            ScanRange::All => self.db.iter(),
+            ScanRange::Prefix(p) => self.db.scan_prefix(p),
+            ScanRange::Range(start, end) => self.db.range::<String, _>((start, end)),
+        };
+        let this = self.clone();
+        stream::iter(iter).filter_map(move |res| {
+            let item = (|| {
+                let (k, v) = res?;
+                if this.is_expired(&k)? { return Ok(None); }
+                let key = String::from_utf8(k.to_vec()).map_err(|e| HubError::Corruption(format!("stored key is not UTF-8: {e}")))?;
+                let value = this.decode(&key, &v)?.value;
+                Ok(Some((key, value)))
+            })();
+            futures::future::ready(item.transpose())
+        }).boxed()
+    }
+}
+
+/// `key` of namespace `tenant` encrypted with the active key of `ring`,
+/// behind a header naming that key.
+#[cfg(feature = "longmem_encrypt")]
+fn wrap_tenant_key(ring: &Keyring, tenant: &str, key: &[u8; 32]) -> HubResult<Vec<u8>> {
+    use aes_gcm_siv::aead::rand_core::RngCore;
+    let id = ring.active();
+    let mut nonce = [0u8; 12];
+    OsRng.fill_bytes(&mut nonce);
+    let aad = [TENANT_KEY_DOMAIN.as_bytes(), b"\0", tenant.as_bytes()].concat();
+    let mut wrapped = keyring::header(FORMAT_V2, id).to_vec();
+    wrapped.extend_from_slice(&nonce);
+    wrapped.append(&mut ring.cipher(id).expect("active key is in the keyring")
+        .encrypt(GenericArray::from_slice(&nonce), Payload { msg: key, aad: &aad })
+        .map_err(|_| HubError::Crypto("encryption failed".into()))?);
+    Ok(wrapped)
+}
+
+/// Namespace key of `tenant` from its wrapped form, with any key of `ring`.
+#[cfg(feature = "longmem_encrypt")]
+fn unwrap_tenant_key(ring: &Keyring, tenant: &str, wrapped: &[u8]) -> HubResult<zeroize::Zeroizing<Vec<u8>>> {
+    let Some((FORMAT_V2, id, rest)) = keyring::split_header(wrapped) else {
+        return Err(HubError::Corruption(format!("key of namespace {tenant} has an unknown format")));
+    };
+    if rest.len() < 12 { return Err(HubError::Corruption(format!("key of namespace {tenant} too short"))); }
+    let (nonce, ct) = rest.split_at(12);
+    let aad = [TENANT_KEY_DOMAIN.as_bytes(), b"\0", tenant.as_bytes()].concat();
+    let cipher = ring.cipher(id)
+        .ok_or_else(|| HubError::Crypto(format!("key of namespace {tenant} is encrypted with unknown key id {id}")))?;
+    let key = zeroize::Zeroizing::new(cipher.decrypt(GenericArray::from_slice(nonce), Payload { msg: ct, aad: &aad })
+        .map_err(|_| HubError::Integrity(format!("key of namespace {tenant} failed authentication")))?);
+    if key.len() != 32 {
+        return Err(HubError::Corruption(format!("key of namespace {tenant} has the wrong length")));
+    }
+    Ok(key)
+}
+
+/// `__ttl_idx` key: big-endian deadline followed by the user key.
+fn index_key(deadline: u64, key: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(8 + key.len());
+    out.extend_from_slice(&deadline.to_be_bytes());
+    out.extend_from_slice(key);
+    out
+}
+
+fn decode_deadline(raw: &[u8]) -> u64 {
+    raw.try_into().map(u64::from_be_bytes).unwrap_or(0)
+}
+
+/// Drop the deadline of `key`, if any, inside a transaction.
+fn clear_deadline<E>(ttl: &TransactionalTree, idx: &TransactionalTree, key: &[u8]) -> Result<(), ConflictableTransactionError<E>> {
+    if let Some(old) = ttl.remove(key)? {
+        idx.remove(index_key(decode_deadline(&old), key))?;
+    }
+    Ok(())
+}
+
+/// Replace the deadline of `key` with `deadline`, or drop it, inside a transaction.
+fn set_deadline<E>(ttl: &TransactionalTree, idx: &TransactionalTree, key: &[u8], deadline: Option<u64>) -> Result<(), ConflictableTransactionError<E>> {
+    clear_deadline(ttl, idx, key)?;
+    if let Some(at) = deadline {
+        ttl.insert(key, &at.to_be_bytes())?;
+        idx.insert(index_key(at, key), &[])?;
+    }
+    Ok(())
+}
+
+fn tx_error<E: Into<HubError>>(e: TransactionError<E>) -> HubError {
+    match e {
+        TransactionError::Abort(e) => e.into(),
+        TransactionError::Storage(e) => e.into(),
+    }
+}
@@ -1,5 +1,5 @@
 use sha2::{Sha256, Digest};
-use anyhow::Result;
+use crate::backend::HubResult;
 use std::fs::{OpenOptions, File};
 use std::io::{Read, Write, Seek, SeekFrom};
 use std::path::Path;
@@ -12,38 +12,53 @@ pub struct MerkleLog {
 }
 
 impl MerkleLog {
-    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+    pub fn open<P: AsRef<Path>>(path: P) -> HubResult<Self> {
         let file = OpenOptions::new().create(true).read(true).append(true).open(path)?;
         Ok(Self { file })
     }
 
-    pub fn append(&mut self, leaf_hash: [u8;32]) -> Result<()> {
+    pub fn append(&mut self, leaf_hash: [u8;32]) -> HubResult<()> {
         self.file.write_all(&leaf_hash)?;
         self.file.sync_data()?;
         Ok(())
     }
 
-    /// Вычислить Merkle-root, читая все листья.
-    pub fn root(&mut self) -> Result<[u8;32]> {
+    /// Все листья в порядке добавления.
+    pub fn leaves(&mut self) -> HubResult<Vec<[u8;32]>> {
         self.file.seek(SeekFrom::Start(0))?;
         let mut leaves = Vec::<[u8;32]>::new();
         let mut buf = [0u8;32];
         while self.file.read_exact(&mut buf).is_ok() {
             leaves.push(buf);
         }
-        if leaves.is_empty() { return Ok([0u8;32]); }
-        let mut level = leaves;
-        while level.len() > 1 {
-            let mut next = Vec::with_capacity((level.len()+1)/2);
-            for chunk in level.chunks(2) {
-                let mut hasher = Sha256::new();
-                hasher.update(chunk[0]);
-                if chunk.len()==2 { hasher.update(chunk[1]); }
-                let h = hasher.finalize();
-                next.push(h.into());
-            }
-            level = next;
+        Ok(leaves)
+    }
+
+    /// Вычислить Merkle-root, читая все листья.
+    pub fn root(&mut self) -> HubResult<[u8;32]> {
+        Ok(root_of(self.leaves()?))
+    }
+}
+
+/// Leaf hash of a data block.
+pub fn leaf(data: &[u8]) -> [u8;32] {
+    Sha256::digest(data).into()
+}
+
+/// Свёртка листьев в Merkle-root (pair-wise). Пустой набор даёт нулевой хэш.
+pub fn root_of(leaves: Vec<[u8;32]>) -> [u8;32] {
+    if leaves.is_empty() { return [0u8;32]; }
+    let mut level = leaves;
+    while level.len() > 1 {
+        let mut next = Vec::with_capacity(level.len().div_ceil(2));
+        for chunk in level.chunks(2) {
+            let mut hasher = Sha256::new();
+            hasher.update(chunk[0]);
+            if chunk.len()==2 { hasher.update(chunk[1]); }
+            let h = hasher.finalize();
+            next.push(h.into());
         }
-        Ok(level[0])
+        level = next;
     }
-} 
\ No newline at end of file
+    level[0]
+}
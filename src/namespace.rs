@@ -0,0 +1,205 @@
+//! Tenant namespaces on top of [`MemoryHub`].
+//!
+//! A [`Namespace`] handle stores every key under `__ns/<tenant>/`, strips
+//! that prefix again on the way out and confines scans to it. Backends see
+//! ordinary keys: `DetailMem` therefore keeps each tenant in its own
+//! directory and `LongMem` encrypts each tenant with its own key.
+use crate::backend::{EntryStream, HubResult, KeyStream, ScanRange};
+use crate::error::HubError;
+use crate::hub::MemoryHub;
+use crate::policy::Principal;
+use crate::quota::{Quota, Usage};
+use crate::record::{CasOutcome, Expected, Record, WriteMeta};
+use crate::txn::{Transaction, TxOp};
+use futures::stream::StreamExt;
+use std::ops::Bound;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Prefix of all namespaced keys.
+pub(crate) const NS_ROOT: &str = "__ns/";
+
+/// Tenant owning `key`, if it is a namespaced key.
+pub(crate) fn tenant_of(key: &str) -> Option<&str> {
+    let rest = key.strip_prefix(NS_ROOT)?;
+    rest.split_once('/').map(|(tenant, _)| tenant)
+}
+
+/// Key prefix of the tenant `name`.
+pub(crate) fn prefix_of(name: &str) -> String {
+    format!("{NS_ROOT}{name}/")
+}
+
+/// Tenant names are non-empty and limited to ASCII letters, digits, `-`, `_` and `.`,
+/// so they are safe as a single path component.
+pub(crate) fn validate_name(name: &str) -> HubResult<()> {
+    let valid = !name.is_empty()
+        && name != "."
+        && name != ".."
+        && name.bytes().all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.'));
+    if !valid {
+        return Err(HubError::InvalidInput(format!("namespace name {name:?}")));
+    }
+    Ok(())
+}
+
+/// Handle scoping hub operations to one tenant; see the [module docs](self).
+/// Cheap to clone.
+///
+/// Every namespace is a quota scope of the hub: its usage is counted from
+/// the first handle on (or from [`MemoryHub::rebuild_usage`]) and writes
+/// beyond [`set_quota`](Self::set_quota) fail with
+/// [`QuotaExceeded`](crate::QuotaExceeded).
+///
+/// Operations run as the principal of the [`Session`](crate::Session) the
+/// handle was obtained from, or as [`Principal::anonymous`].
+#[derive(Clone)]
+pub struct Namespace {
+    hub: Arc<MemoryHub>,
+    name: String,
+    prefix: String,
+    principal: Principal,
+}
+
+impl Namespace {
+    pub(crate) fn new(hub: Arc<MemoryHub>, name: String, principal: Principal) -> Self {
+        let prefix = prefix_of(&name);
+        Self { hub, name, prefix, principal }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn quota(&self) -> Quota {
+        self.hub.quota(&self.prefix).unwrap_or_default()
+    }
+
+    /// Set the limits checked by later writes. Stored data above a lowered
+    /// limit is kept; only growth is refused.
+    pub fn set_quota(&self, quota: Quota) {
+        self.hub.set_quota(self.prefix.clone(), quota);
+    }
+
+    pub fn usage(&self) -> Usage {
+        self.hub.usage(&self.prefix).unwrap_or_default()
+    }
+
+    fn full_key(&self, key: &str) -> String {
+        format!("{}{key}", self.prefix)
+    }
+
+    /// See [`MemoryHub::write`].
+    pub async fn write(&self, key: String, value: Vec<u8>) -> HubResult<()> {
+        self.hub.write_as(&self.principal, self.full_key(&key), value).await
+    }
+
+    /// See [`MemoryHub::write_with_ttl`].
+    pub async fn write_with_ttl(&self, key: String, value: Vec<u8>, ttl: Duration) -> HubResult<()> {
+        self.hub.write_with_ttl_as(&self.principal, self.full_key(&key), value, ttl).await
+    }
+
+    /// See [`MemoryHub::write_with_meta`].
+    pub async fn write_with_meta(&self, key: String, value: Vec<u8>, meta: WriteMeta) -> HubResult<()> {
+        self.hub.write_with_meta_as(&self.principal, self.full_key(&key), value, meta).await
+    }
+
+    /// See [`MemoryHub::compare_and_swap`].
+    pub async fn compare_and_swap(&self, key: String, expected: Expected, new: Vec<u8>) -> HubResult<CasOutcome> {
+        self.hub.compare_and_swap_as(&self.principal, self.full_key(&key), expected, new).await
+    }
+
+    /// See [`MemoryHub::put_if_absent`].
+    pub async fn put_if_absent(&self, key: String, value: Vec<u8>) -> HubResult<bool> {
+        Ok(self.compare_and_swap(key, Expected::Absent, value).await?.is_swapped())
+    }
+
+    /// See [`MemoryHub::commit`]. Keys are taken relative to the namespace.
+    pub async fn commit(&self, tx: Transaction) -> HubResult<()> {
+        let mut scoped = Transaction::new();
+        for op in tx.into_ops() {
+            match op {
+                TxOp::Put { key, value } => scoped.put(self.full_key(&key), value),
+                TxOp::Delete { key } => scoped.delete(self.full_key(&key)),
+            };
+        }
+        self.hub.commit_as(&self.principal, scoped).await
+    }
+
+    /// See [`MemoryHub::read`].
+    pub async fn read(&self, key: String) -> HubResult<Option<Vec<u8>>> {
+        self.hub.read_as(&self.principal, self.full_key(&key)).await
+    }
+
+    /// See [`MemoryHub::read_with_meta`].
+    pub async fn read_with_meta(&self, key: String) -> HubResult<Option<Record>> {
+        self.hub.read_with_meta_as(&self.principal, self.full_key(&key)).await
+    }
+
+    /// See [`MemoryHub::read_many`].
+    pub async fn read_many(&self, keys: &[String]) -> Vec<HubResult<Option<Vec<u8>>>> {
+        let full: Vec<String> = keys.iter().map(|k| self.full_key(k)).collect();
+        self.hub.read_many_as(&self.principal, &full).await
+    }
+
+    /// See [`MemoryHub::delete`].
+    pub async fn delete(&self, key: String) -> HubResult<bool> {
+        self.hub.delete_as(&self.principal, self.full_key(&key)).await
+    }
+
+    /// See [`MemoryHub::contains`].
+    pub async fn contains(&self, key: String) -> HubResult<bool> {
+        self.hub.contains_as(&self.principal, self.full_key(&key)).await
+    }
+
+    /// Translate a range over tenant keys into one over hub keys.
+    fn scoped(&self, range: ScanRange) -> ScanRange {
+        let prefixed = |b: Bound<String>| match b {
+            Bound::Included(k) => Bound::Included(self.full_key(&k)),
+            Bound::Excluded(k) => Bound::Excluded(self.full_key(&k)),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        match range {
+            ScanRange::All => ScanRange::Prefix(self.prefix.clone()),
+            ScanRange::Prefix(p) => ScanRange::Prefix(self.full_key(&p)),
+            ScanRange::Range(start, end) => {
+                let start = match prefixed(start) {
+                    Bound::Unbounded => Bound::Included(self.prefix.clone()),
+                    b => b,
+                };
+                let end = match prefixed(end) {
+                    // `0` sorts right after the trailing `/`
+                    Bound::Unbounded => Bound::Excluded(format!("{}0", &self.prefix[..self.prefix.len() - 1])),
+                    b => b,
+                };
+                ScanRange::Range(start, end)
+            }
+        }
+    }
+
+    /// See [`MemoryHub::scan`]. Only this namespace's keys are returned,
+    /// without the namespace prefix.
+    pub fn scan(&self, range: ScanRange) -> EntryStream {
+        let prefix = self.prefix.clone();
+        self.hub.scan_as(&self.principal, self.scoped(range)).filter_map(move |item| {
+            let item = match item {
+                Ok((key, value)) => key.strip_prefix(&prefix).map(|k| Ok((k.to_string(), value))),
+                Err(e) => Some(Err(e)),
+            };
+            async move { item }
+        }).boxed()
+    }
+
+    /// See [`MemoryHub::scan_keys`]. Only this namespace's keys are returned,
+    /// without the namespace prefix.
+    pub fn scan_keys(&self, range: ScanRange) -> KeyStream {
+        let prefix = self.prefix.clone();
+        self.hub.scan_keys_as(&self.principal, self.scoped(range)).filter_map(move |item| {
+            let item = match item {
+                Ok(key) => key.strip_prefix(&prefix).map(|k| Ok(k.to_string())),
+                Err(e) => Some(Err(e)),
+            };
+            async move { item }
+        }).boxed()
+    }
+}
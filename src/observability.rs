@@ -1,17 +1,18 @@
 #[cfg(feature = "dev_metrics")]
 mod prom {
     use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
-    static mut HANDLE: Option<PrometheusHandle> = None;
+    use std::sync::OnceLock;
+    static HANDLE: OnceLock<PrometheusHandle> = OnceLock::new();
 
     pub fn init() {
         let builder = PrometheusBuilder::new();
-        let handle = builder.install().expect("prometheus recorder install");
-        unsafe { HANDLE = Some(handle); }
+        let handle = builder.install_recorder().expect("prometheus recorder install");
+        let _ = HANDLE.set(handle);
     }
 
     /// Get exposition string for scraping.
     pub fn render() -> String {
-        unsafe { HANDLE.as_ref().map(|h| h.render()).unwrap_or_default() }
+        HANDLE.get().map(|h| h.render()).unwrap_or_default()
     }
 }
 
@@ -21,4 +22,4 @@ mod prom {
     pub fn render() -> String { String::new() }
 }
 
-pub use prom::*; 
\ No newline at end of file
+pub use prom::*;
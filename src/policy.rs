@@ -1,8 +1,161 @@
+use crate::backend::HubResult;
+use crate::error::HubError;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+
 /// Trait for Policy Decision Point (PDP).
 /// Determines whether a given action is authorized under some context.
+///
+/// [`MemoryHub`](crate::MemoryHub) calls it before every read, write,
+/// delete and scan with one of the [`Action`] names and a context of the form
+///
+/// ```json
+/// { "key": "__ns/acme/doc/1", "namespace": "acme", "size": 512,
+///   "principal": { "id": "alice", "roles": ["editor"], "attributes": {} } }
+/// ```
+///
+/// `namespace` is `null` for keys outside tenant namespaces, `size` is the
+/// value length for writes and `null` otherwise. For scans `key` is the
+/// prefix or lower bound of the range, `null` for full scans, and `range`
+/// holds the whole range: `{"prefix": "p/"}`, or
+/// `{"start": "a", "start_inclusive": true, "end": null, "end_inclusive": false}`
+/// with `null` for unbounded sides. Each key the scan yields is then checked
+/// as a scan of that key, without `range`, and keys refused are left out of
+/// the results.
+///
+/// The hub asks through [`evaluate`](Self::evaluate), so the redactions and
+/// obligations of a [`Decision`] take effect.
 pub trait PolicyEngine: Send + Sync {
     /// Evaluate action + context. Returns `true` if allowed.
     fn allow(&self, action: &str, context_json: &serde_json::Value) -> bool;
+
+    /// Full decision, for engines that attach redactions or obligations to
+    /// an allow. The default wraps [`allow`](Self::allow).
+    fn evaluate(&self, action: &str, context_json: &serde_json::Value) -> Decision {
+        Decision { allow: self.allow(action, context_json), ..Default::default() }
+    }
+}
+
+/// Outcome of [`PolicyEngine::evaluate`].
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Decision {
+    #[serde(default)]
+    pub allow: bool,
+    /// Fields stripped from values before reads and scans hand them out:
+    /// top-level field names, or JSON pointers such as `/owner/email`.
+    /// Values that are not JSON objects are refused instead.
+    #[serde(default)]
+    pub redact: Vec<String>,
+    /// Duties attached to the decision, e.g. `{"notify": "dpo"}`. The hub
+    /// records them with the operation's [`AuditEvent`](crate::audit::AuditEvent)
+    /// for the audit sink to carry out.
+    #[serde(default)]
+    pub obligations: Vec<serde_json::Value>,
+}
+
+/// `value` with the `fields` of a [`Decision::redact`] removed. Fails if
+/// `value` is not a JSON object, as nothing could be stripped from it.
+pub(crate) fn redact(action: Action, key: &str, value: Vec<u8>, fields: &[String]) -> HubResult<Vec<u8>> {
+    if fields.is_empty() {
+        return Ok(value);
+    }
+    let refused = || HubError::PolicyDenied {
+        action: action.to_string(),
+        reason: format!("policy redacts fields of {key}, which does not hold a JSON object"),
+    };
+    let mut doc: serde_json::Value = serde_json::from_slice(&value).map_err(|_| refused())?;
+    if !doc.is_object() {
+        return Err(refused());
+    }
+    for field in fields {
+        let (parent, name) = match field.strip_prefix('/') {
+            Some(_) => {
+                let (parent, last) = field.rsplit_once('/').expect("pointer has a slash");
+                (parent, last.replace("~1", "/").replace("~0", "~"))
+            }
+            None => ("", field.clone()),
+        };
+        if let Some(serde_json::Value::Object(map)) = doc.pointer_mut(parent) {
+            map.remove(&name);
+        }
+    }
+    Ok(serde_json::to_vec(&doc).expect("JSON values serialize"))
+}
+
+/// Kind of hub operation checked against the policy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Read,
+    Write,
+    Delete,
+    Scan,
+}
+
+impl Action {
+    /// Name passed to [`PolicyEngine::allow`].
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Action::Read => "read",
+            Action::Write => "write",
+            Action::Delete => "delete",
+            Action::Scan => "scan",
+        }
+    }
+}
+
+impl std::str::FromStr for Action {
+    type Err = HubError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read" => Ok(Action::Read),
+            "write" => Ok(Action::Write),
+            "delete" => Ok(Action::Delete),
+            "scan" => Ok(Action::Scan),
+            other => Err(HubError::InvalidInput(format!("unknown action {other:?}"))),
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Caller on whose behalf hub operations run; see
+/// [`MemoryHub::session`](crate::MemoryHub::session).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Principal {
+    pub id: String,
+    #[serde(default)]
+    pub roles: Vec<String>,
+    /// Free-form attributes for attribute-based rules.
+    #[serde(default)]
+    pub attributes: BTreeMap<String, serde_json::Value>,
+}
+
+impl Principal {
+    pub fn new(id: impl Into<String>) -> Self {
+        Self { id: id.into(), roles: Vec::new(), attributes: BTreeMap::new() }
+    }
+
+    /// Principal of calls made directly on the hub, outside any session.
+    pub fn anonymous() -> Self {
+        Self::new("anonymous")
+    }
+
+    pub fn with_role(mut self, role: impl Into<String>) -> Self {
+        self.roles.push(role.into());
+        self
+    }
+
+    pub fn with_attribute(mut self, name: impl Into<String>, value: impl Into<serde_json::Value>) -> Self {
+        self.attributes.insert(name.into(), value.into());
+        self
+    }
 }
 
 /// Allow-all policy (default).
@@ -15,21 +168,251 @@ impl PolicyEngine for AllowAllPolicy {
     }
 }
 
+#[cfg(feature = "opa_policy")]
+pub use opa_policy::RegoPolicy;
+
 #[cfg(feature = "opa_policy")]
 mod opa_policy {
-    use super::PolicyEngine;
-    use opa_wasm::OpaPolicy;
+    use super::{Decision, PolicyEngine};
+    use crate::backend::HubResult;
+    use crate::cancellation::CancellationToken;
+    use crate::error::HubError;
+    use crate::runtime;
+    use dashmap::DashMap;
+    use futures::FutureExt;
+    use opa_wasm::wasmtime::{Config, Engine, Module, Store};
+    use opa_wasm::{DefaultContext, Policy, Runtime};
+    use std::borrow::Cow;
+    use std::future::Future;
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant, SystemTime};
+
+    /// Cached decisions held before expired ones are dropped.
+    const CACHE_LIMIT: usize = 10_000;
 
+    enum Source {
+        File(PathBuf),
+        Bytes(Vec<u8>),
+    }
+
+    struct Loaded {
+        /// Bumped on every reload and data change; cached decisions of
+        /// earlier generations are never served.
+        generation: u64,
+        module: Module,
+        store: Store<()>,
+        policy: Policy<DefaultContext>,
+    }
+
+    /// Policy written in Rego and compiled to WebAssembly with
+    /// `opa build -t wasm`, evaluated in-process.
+    ///
+    /// The entrypoint receives `{"action": ..., "ctx": ...}` as `input`, with
+    /// `ctx` as described on [`PolicyEngine`], and may return a bool or an
+    /// object shaped like [`Decision`]. Anything else, an undefined result or
+    /// an evaluation error denies. External data documents are available to
+    /// the policy as `data`.
+    ///
+    /// Evaluation runs on the calling thread and must finish without
+    /// waiting: a policy whose builtins wait on I/O, such as `http.send`,
+    /// denies. Decisions are cached per policy generation, action and
+    /// context for [`with_cache_ttl`](Self::with_cache_ttl).
     pub struct RegoPolicy {
-        engine: OpaPolicy,
+        engine: Engine,
+        source: Source,
+        entrypoint: String,
+        data: Mutex<serde_json::Value>,
+        loaded: Mutex<Loaded>,
+        /// Modification time of the bundle file when it was last loaded.
+        modified: Mutex<Option<SystemTime>>,
+        generations: AtomicU64,
+        cache: DashMap<(u64, String), (Decision, Instant)>,
+        cache_ttl: Duration,
+    }
+
+    impl RegoPolicy {
+        /// Load a bundle (`bundle.tar.gz`) or a bare `policy.wasm` from `path`.
+        /// [`reload_if_changed`](Self::reload_if_changed) picks up later edits.
+        pub fn from_file(path: impl Into<PathBuf>, entrypoint: impl Into<String>) -> HubResult<Self> {
+            Self::new(Source::File(path.into()), entrypoint.into())
+        }
+
+        /// Load a bundle or a bare `policy.wasm` from memory.
+        pub fn from_bytes(bytes: impl Into<Vec<u8>>, entrypoint: impl Into<String>) -> HubResult<Self> {
+            Self::new(Source::Bytes(bytes.into()), entrypoint.into())
+        }
+
+        fn new(source: Source, entrypoint: String) -> HubResult<Self> {
+            let mut config = Config::new();
+            config.async_support(true);
+            let engine = Engine::new(&config).map_err(HubError::other)?;
+            let data = serde_json::Value::Object(Default::default());
+            let (bytes, modified) = read(&source)?;
+            let loaded = instantiate(&engine, compile(&engine, &bytes)?, &entrypoint, &data, 0)?;
+            Ok(Self {
+                engine,
+                source,
+                entrypoint,
+                data: Mutex::new(data),
+                loaded: Mutex::new(loaded),
+                modified: Mutex::new(modified),
+                generations: AtomicU64::new(0),
+                cache: DashMap::new(),
+                cache_ttl: Duration::from_secs(1),
+            })
+        }
+
+        /// Keep decisions for `ttl`; zero disables caching. Defaults to one second.
+        pub fn with_cache_ttl(mut self, ttl: Duration) -> Self {
+            self.cache_ttl = ttl;
+            self
+        }
+
+        /// Replace the external `data` document and drop cached decisions.
+        pub fn set_data(&self, data: serde_json::Value) -> HubResult<()> {
+            // held throughout so updates go into force in generation order
+            let mut current = self.data.lock().expect("policy lock");
+            let module = self.loaded.lock().expect("policy lock").module.clone();
+            let fresh = instantiate(&self.engine, module, &self.entrypoint, &data, self.next_generation())?;
+            self.install(fresh);
+            *current = data;
+            Ok(())
+        }
+
+        /// Load the bundle again and drop cached decisions. On error the
+        /// previous policy stays in force.
+        pub fn reload(&self) -> HubResult<()> {
+            let (bytes, modified) = read(&self.source)?;
+            let module = compile(&self.engine, &bytes)?;
+            let data = self.data.lock().expect("policy lock");
+            let fresh = instantiate(&self.engine, module, &self.entrypoint, &data, self.next_generation())?;
+            *self.modified.lock().expect("policy lock") = modified;
+            self.install(fresh);
+            Ok(())
+        }
+
+        fn next_generation(&self) -> u64 {
+            self.generations.fetch_add(1, Ordering::Relaxed) + 1
+        }
+
+        /// Put `fresh` in force and drop the decisions cached for earlier
+        /// generations.
+        fn install(&self, fresh: Loaded) {
+            let current = fresh.generation;
+            *self.loaded.lock().expect("policy lock") = fresh;
+            self.cache.retain(|(generation, _), _| *generation >= current);
+        }
+
+        /// Reload if the bundle file was modified since it was last loaded.
+        /// Returns whether it was. Policies loaded from memory never change.
+        pub fn reload_if_changed(&self) -> HubResult<bool> {
+            let Source::File(path) = &self.source else { return Ok(false) };
+            let current = std::fs::metadata(path)?.modified().ok();
+            if current == *self.modified.lock().expect("policy lock") {
+                return Ok(false);
+            }
+            self.reload()?;
+            Ok(true)
+        }
+
+        /// Run [`reload_if_changed`](Self::reload_if_changed) every `interval`
+        /// until the returned token is cancelled.
+        pub fn spawn_reloader(self: &Arc<Self>, interval: Duration) -> CancellationToken {
+            let token = CancellationToken::new();
+            let (policy, stop) = (Arc::clone(self), token.clone());
+            runtime::spawn(async move {
+                while !stop.is_cancelled() {
+                    runtime::sleep(interval).await;
+                    if stop.is_cancelled() { break; }
+                    // a broken bundle keeps the previous policy until it is fixed
+                    let _ = policy.reload_if_changed();
+                }
+            });
+            token
+        }
+
+        /// Decision of the policy in force, with its generation.
+        fn decide(&self, input: &serde_json::Value) -> (Decision, u64) {
+            let mut loaded = self.loaded.lock().expect("policy lock");
+            let Loaded { generation, store, policy, .. } = &mut *loaded;
+            let results: serde_json::Value = match ready(policy.evaluate(&mut *store, &self.entrypoint, input)) {
+                Ok(results) => results,
+                Err(_) => return (Decision::default(), *generation),
+            };
+            // the result set holds one `{"result": ...}` per solution
+            let decision = match results.get(0).and_then(|r| r.get("result")) {
+                Some(serde_json::Value::Bool(allow)) => Decision { allow: *allow, ..Default::default() },
+                Some(obj @ serde_json::Value::Object(_)) => serde_json::from_value(obj.clone()).unwrap_or_default(),
+                _ => Decision::default(),
+            };
+            (decision, *generation)
+        }
     }
-    impl super::PolicyEngine for RegoPolicy {
+
+    impl PolicyEngine for RegoPolicy {
         fn allow(&self, action: &str, ctx: &serde_json::Value) -> bool {
+            self.evaluate(action, ctx).allow
+        }
+
+        fn evaluate(&self, action: &str, ctx: &serde_json::Value) -> Decision {
             let input = serde_json::json!({ "action": action, "ctx": ctx });
-            match self.engine.evaluate(&input) {
-                Ok(v) => v.as_bool().unwrap_or(false),
-                Err(_) => false,
+            if self.cache_ttl.is_zero() {
+                return self.decide(&input).0;
+            }
+            let current = self.loaded.lock().expect("policy lock").generation;
+            let key = (current, input.to_string());
+            if let Some(hit) = self.cache.get(&key).filter(|e| e.1.elapsed() < self.cache_ttl) {
+                return hit.0.clone();
+            }
+            // a reload in between makes this a decision of the newer generation
+            let (decision, generation) = self.decide(&input);
+            if self.cache.len() >= CACHE_LIMIT {
+                self.cache.retain(|_, e| e.1.elapsed() < self.cache_ttl);
             }
+            self.cache.insert((generation, key.1), (decision.clone(), Instant::now()));
+            decision
         }
     }
-} 
\ No newline at end of file
+
+    /// Bundle bytes and, for files, their modification time.
+    fn read(source: &Source) -> HubResult<(Cow<'_, [u8]>, Option<SystemTime>)> {
+        match source {
+            Source::File(path) => {
+                let bytes = std::fs::read(path)?;
+                let modified = std::fs::metadata(path)?.modified().ok();
+                Ok((Cow::Owned(bytes), modified))
+            }
+            Source::Bytes(bytes) => Ok((Cow::Borrowed(bytes), None)),
+        }
+    }
+
+    /// Compile a `.tar.gz` bundle (recognised by its gzip header) or a bare module.
+    fn compile(engine: &Engine, bytes: &[u8]) -> HubResult<Module> {
+        let wasm = if bytes.starts_with(&[0x1f, 0x8b]) {
+            Cow::Owned(ready(opa_wasm::load_bundle(bytes)).map_err(|e| HubError::InvalidInput(format!("OPA bundle: {e:#}")))?)
+        } else {
+            Cow::Borrowed(bytes)
+        };
+        Module::new(engine, &*wasm).map_err(|e| HubError::InvalidInput(format!("OPA policy module: {e:#}")))
+    }
+
+    fn instantiate(engine: &Engine, module: Module, entrypoint: &str, data: &serde_json::Value, generation: u64) -> HubResult<Loaded> {
+        let mut store = Store::new(engine, ());
+        let runtime = ready(Runtime::new(&mut store, &module)).map_err(HubError::other)?;
+        if !runtime.entrypoints().contains(entrypoint) {
+            let mut known: Vec<&str> = runtime.entrypoints().into_iter().collect();
+            known.sort_unstable();
+            return Err(HubError::InvalidInput(format!("OPA policy has no entrypoint {entrypoint}; it has {}", known.join(", "))));
+        }
+        let policy = ready(runtime.with_data(&mut store, data)).map_err(HubError::other)?;
+        Ok(Loaded { generation, module, store, policy })
+    }
+
+    /// Run a policy call that does not wait on anything to completion
+    /// without blocking the thread; one that does fails.
+    fn ready<T>(call: impl Future<Output = anyhow::Result<T>>) -> anyhow::Result<T> {
+        call.now_or_never().unwrap_or_else(|| Err(anyhow::anyhow!("OPA policy waited on I/O")))
+    }
+}
@@ -0,0 +1,248 @@
+//! Usage accounting and quotas per key prefix.
+//!
+//! A quota scope is a key prefix, for example a tenant namespace
+//! (`__ns/acme/`) or any other prefix such as `blob/`. Scopes may nest; a
+//! write has to fit every scope containing its key. Counters are kept in
+//! memory, updated by hub writes and deletes, and recomputed from backend
+//! scans by [`MemoryHub::rebuild_usage`](crate::MemoryHub::rebuild_usage).
+//! The size of every counted key is remembered alongside, so a write is
+//! booked without reading the value it replaces.
+#[cfg(feature = "dev_metrics")] use metrics::{counter, gauge};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::{Mutex, RwLock};
+
+/// Limits of one scope. `None` means unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Quota {
+    pub max_keys: Option<u64>,
+    pub max_bytes: Option<u64>,
+}
+
+/// Keys and value bytes stored under a scope.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub keys: u64,
+    pub bytes: u64,
+}
+
+/// A scope with its limits and current usage, as listed by
+/// [`MemoryHub::quotas`](crate::MemoryHub::quotas).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuotaStatus {
+    pub prefix: String,
+    pub quota: Quota,
+    pub usage: Usage,
+}
+
+/// What a [`QuotaExceeded`] error ran out of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuotaResource {
+    Keys,
+    Bytes,
+}
+
+impl fmt::Display for QuotaResource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            QuotaResource::Keys => "keys",
+            QuotaResource::Bytes => "bytes",
+        })
+    }
+}
+
+/// A write was refused because it would take a scope over its [`Quota`].
+/// Returned as [`HubError::QuotaExceeded`](crate::HubError::QuotaExceeded).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuotaExceeded {
+    /// Prefix of the scope whose limit was hit.
+    pub scope: String,
+    pub resource: QuotaResource,
+    pub limit: u64,
+    /// Usage the write would have led to.
+    pub requested: u64,
+}
+
+impl fmt::Display for QuotaExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "quota exceeded for {}: {} {} requested, limit is {}", self.scope, self.requested, self.resource, self.limit)
+    }
+}
+
+impl std::error::Error for QuotaExceeded {}
+
+/// Signed change of a scope's [`Usage`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Delta {
+    keys: i64,
+    bytes: i64,
+}
+
+impl Usage {
+    fn apply(&mut self, delta: Delta) {
+        self.keys = self.keys.saturating_add_signed(delta.keys);
+        self.bytes = self.bytes.saturating_add_signed(delta.bytes);
+    }
+}
+
+/// New value length of one key, `None` for a removal.
+pub(crate) type Change<'a> = (&'a str, Option<usize>);
+
+/// Usage booked by [`Quotas::reserve`]; handed back to [`Quotas::refund`]
+/// if the write does not happen.
+#[derive(Debug, Default)]
+#[must_use]
+pub(crate) struct Charge {
+    deltas: Vec<(String, Delta)>,
+    /// Keys with their size before and after the booking.
+    sizes: Vec<(String, Option<usize>, Option<usize>)>,
+}
+
+#[derive(Debug, Default)]
+struct Scope {
+    quota: Quota,
+    usage: Usage,
+}
+
+/// All scopes of a hub.
+#[derive(Debug, Default)]
+pub(crate) struct Quotas {
+    scopes: RwLock<BTreeMap<String, Scope>>,
+    /// Value length of each key inside a scope. Locked after `scopes`.
+    sizes: Mutex<HashMap<String, usize>>,
+}
+
+impl Quotas {
+    /// Set the limits of `prefix`, creating the scope with zero usage if needed.
+    pub(crate) fn set(&self, prefix: String, quota: Quota) {
+        self.scopes.write().expect("quota lock").entry(prefix).or_default().quota = quota;
+    }
+
+    /// Start counting `prefix` without limits, unless it is counted already.
+    pub(crate) fn track(&self, prefix: &str) {
+        let mut scopes = self.scopes.write().expect("quota lock");
+        if !scopes.contains_key(prefix) {
+            scopes.insert(prefix.to_string(), Scope::default());
+        }
+    }
+
+    pub(crate) fn remove(&self, prefix: &str) -> bool {
+        let mut scopes = self.scopes.write().expect("quota lock");
+        let removed = scopes.remove(prefix).is_some();
+        self.sizes.lock().expect("sizes lock").retain(|key, _| scopes.keys().any(|p| key.starts_with(p.as_str())));
+        removed
+    }
+
+    pub(crate) fn get(&self, prefix: &str) -> Option<QuotaStatus> {
+        let scopes = self.scopes.read().expect("quota lock");
+        scopes.get(prefix).map(|s| QuotaStatus { prefix: prefix.to_string(), quota: s.quota, usage: s.usage })
+    }
+
+    pub(crate) fn list(&self) -> Vec<QuotaStatus> {
+        let scopes = self.scopes.read().expect("quota lock");
+        scopes.iter().map(|(prefix, s)| QuotaStatus { prefix: prefix.clone(), quota: s.quota, usage: s.usage }).collect()
+    }
+
+    pub(crate) fn prefixes(&self) -> Vec<String> {
+        self.scopes.read().expect("quota lock").keys().cloned().collect()
+    }
+
+    /// Replace the usage of `prefix` and the sizes of its keys with values
+    /// recomputed from a scan.
+    pub(crate) fn reset(&self, prefix: &str, usage: Usage, sizes: Vec<(String, usize)>) {
+        if let Some(scope) = self.scopes.write().expect("quota lock").get_mut(prefix) {
+            scope.usage = usage;
+            let mut known = self.sizes.lock().expect("sizes lock");
+            known.retain(|key, _| !key.starts_with(prefix));
+            known.extend(sizes);
+            export(prefix, usage);
+        }
+    }
+
+    /// Book `changes` if every affected scope stays within its quota; book
+    /// nothing otherwise. Shrinking a scope is never refused. Keys outside
+    /// every scope are not counted.
+    pub(crate) fn reserve(&self, changes: &[Change<'_>]) -> Result<Charge, QuotaExceeded> {
+        let mut scopes = self.scopes.write().expect("quota lock");
+        let mut known = self.sizes.lock().expect("sizes lock");
+        let mut deltas: BTreeMap<&str, Delta> = BTreeMap::new();
+        let mut sizes = Vec::new();
+        for &(key, new_len) in changes {
+            if !scopes.keys().any(|p| key.starts_with(p.as_str())) {
+                continue;
+            }
+            let old_len = known.get(key).copied();
+            sizes.push((key.to_string(), old_len, new_len));
+            let delta = Delta {
+                keys: new_len.is_some() as i64 - old_len.is_some() as i64,
+                bytes: new_len.unwrap_or(0) as i64 - old_len.unwrap_or(0) as i64,
+            };
+            for prefix in scopes.keys().filter(|p| key.starts_with(p.as_str())) {
+                let total = deltas.entry(prefix.as_str()).or_default();
+                total.keys += delta.keys;
+                total.bytes += delta.bytes;
+            }
+        }
+        for (prefix, delta) in &deltas {
+            let scope = &scopes[*prefix];
+            let mut after = scope.usage;
+            after.apply(*delta);
+            let over = |resource, limit: Option<u64>, grows: bool, requested: u64| match limit {
+                Some(limit) if grows && requested > limit => Some(QuotaExceeded { scope: prefix.to_string(), resource, limit, requested }),
+                _ => None,
+            };
+            let exceeded = over(QuotaResource::Keys, scope.quota.max_keys, delta.keys > 0, after.keys)
+                .or_else(|| over(QuotaResource::Bytes, scope.quota.max_bytes, delta.bytes > 0, after.bytes));
+            if let Some(err) = exceeded {
+                #[cfg(feature = "dev_metrics")] counter!("memory_hub.quota.rejected", "scope" => err.scope.clone()).increment(1);
+                return Err(err);
+            }
+        }
+        let deltas: Vec<(String, Delta)> = deltas.into_iter().map(|(p, d)| (p.to_string(), d)).collect();
+        for (prefix, delta) in &deltas {
+            if let Some(scope) = scopes.get_mut(prefix) {
+                scope.usage.apply(*delta);
+                export(prefix, scope.usage);
+            }
+        }
+        for (key, _, new_len) in &sizes {
+            set_size(&mut known, key, *new_len);
+        }
+        Ok(Charge { deltas, sizes })
+    }
+
+    /// Undo a reservation whose operation stored nothing. Sizes a later
+    /// booking replaced meanwhile are left alone.
+    pub(crate) fn refund(&self, charge: Charge) {
+        let mut scopes = self.scopes.write().expect("quota lock");
+        for (prefix, delta) in charge.deltas {
+            if let Some(scope) = scopes.get_mut(&prefix) {
+                scope.usage.apply(Delta { keys: -delta.keys, bytes: -delta.bytes });
+                export(&prefix, scope.usage);
+            }
+        }
+        let mut known = self.sizes.lock().expect("sizes lock");
+        for (key, old_len, new_len) in charge.sizes {
+            if known.get(&key).copied() == new_len {
+                set_size(&mut known, &key, old_len);
+            }
+        }
+    }
+}
+
+fn set_size(sizes: &mut HashMap<String, usize>, key: &str, len: Option<usize>) {
+    match len {
+        Some(len) => { sizes.insert(key.to_string(), len); }
+        None => { sizes.remove(key); }
+    }
+}
+
+/// Publish a scope's usage as gauges.
+#[cfg_attr(not(feature = "dev_metrics"), allow(unused_variables))]
+fn export(prefix: &str, usage: Usage) {
+    #[cfg(feature = "dev_metrics")]
+    {
+        gauge!("memory_hub.quota.keys", "scope" => prefix.to_string()).set(usage.keys as f64);
+        gauge!("memory_hub.quota.bytes", "scope" => prefix.to_string()).set(usage.bytes as f64);
+    }
+}
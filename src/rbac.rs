@@ -0,0 +1,411 @@
+//! Built-in role- and attribute-based [`PolicyEngine`].
+//!
+//! Rules are plain data, loaded from JSON or (feature `policy_toml`) TOML:
+//!
+//! ```toml
+//! [roles.reader]
+//! grants = [{ actions = ["read", "scan"], keys = ["__ns/*"] }]
+//!
+//! [roles.editor]
+//! inherits = ["reader"]
+//! grants = [{ actions = ["write", "delete"], keys = ["__ns/*"], when = [
+//!     { op = "same_as", field = "namespace", other = "principal.attributes.tenant" },
+//!     { op = "time_window", from = "06:00", to = "22:00" },
+//! ] }]
+//!
+//! [principals.alice]
+//! roles = ["editor"]
+//!
+//! [[deny]]
+//! name = "audit-is-append-only"
+//! actions = ["delete"]
+//! keys = ["audit/*"]
+//! ```
+//!
+//! A request is allowed if a grant of one of the caller's roles matches it
+//! and no deny rule does: denies override grants. Roles come from the
+//! principal in the context plus the `principals` table. Conditions read
+//! the context described on [`PolicyEngine`] by dotted path.
+use crate::backend::HubResult;
+use crate::error::HubError;
+use crate::policy::{Action, PolicyEngine};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::ops::Bound;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Declarative rules of a [`RulePolicy`].
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RuleSet {
+    #[serde(default)]
+    pub roles: BTreeMap<String, RoleDef>,
+    /// Roles granted to principal ids, on top of those the caller carries.
+    #[serde(default)]
+    pub principals: BTreeMap<String, PrincipalDef>,
+    #[serde(default)]
+    pub deny: Vec<DenyRule>,
+    /// Log refusals but allow everything; see [`RulePolicy::set_dry_run`].
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RoleDef {
+    /// Roles whose grants this role includes.
+    #[serde(default)]
+    pub inherits: Vec<String>,
+    #[serde(default)]
+    pub grants: Vec<Grant>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PrincipalDef {
+    #[serde(default)]
+    pub roles: Vec<String>,
+}
+
+/// Permission to run `actions` on keys matching `keys` when every
+/// condition holds. Empty `actions` or `keys` match everything.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Grant {
+    #[serde(default)]
+    pub actions: Vec<Action>,
+    /// Exact keys, or prefixes followed by `*`. A scan is granted only if
+    /// one of them covers its whole range; full scans only match `*` or an
+    /// empty list.
+    #[serde(default)]
+    pub keys: Vec<String>,
+    #[serde(default)]
+    pub when: Vec<Condition>,
+}
+
+/// Refusal overriding any grant. Applies to everyone unless `roles` or
+/// `principals` narrow it down.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DenyRule {
+    /// Reported in [`Verdict::Denied`]; defaults to `deny[<index>]`.
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub roles: Vec<String>,
+    #[serde(default)]
+    pub principals: Vec<String>,
+    #[serde(default)]
+    pub actions: Vec<Action>,
+    /// As for [`Grant::keys`], except that a scan is refused as soon as its
+    /// range overlaps one of them.
+    #[serde(default)]
+    pub keys: Vec<String>,
+    #[serde(default)]
+    pub when: Vec<Condition>,
+}
+
+/// Attribute condition on the policy context. `field` and `other` are
+/// dotted paths such as `namespace` or `principal.attributes.tenant`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Condition {
+    Equals { field: String, value: Value },
+    OneOf { field: String, values: Vec<Value> },
+    /// Both fields are present and equal.
+    SameAs { field: String, other: String },
+    /// Numeric field not above `max`, e.g. `size`.
+    AtMost { field: String, max: f64 },
+    /// UTC time of day in `[from, to)`, as `HH:MM`; wraps past midnight when
+    /// `from` is later than `to`. Optionally limited to some weekdays, those
+    /// on which the window opens: a Friday `22:00`-`06:00` window includes
+    /// Saturday 02:00.
+    TimeWindow {
+        from: String,
+        to: String,
+        #[serde(default)]
+        days: Vec<Weekday>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Weekday {
+    Mon,
+    Tue,
+    Wed,
+    Thu,
+    Fri,
+    Sat,
+    Sun,
+}
+
+/// Outcome of a [`RulePolicy`] check, as returned by [`RulePolicy::explain`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    /// A grant of `role` matched.
+    Granted { role: String },
+    /// The deny rule `rule` matched; `overrode` is the role whose grant it
+    /// overrode, if any.
+    Denied { rule: String, overrode: Option<String> },
+    /// No grant matched.
+    NoGrant,
+}
+
+impl Verdict {
+    pub fn is_allowed(&self) -> bool {
+        matches!(self, Verdict::Granted { .. })
+    }
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Verdict::Granted { role } => write!(f, "granted by role {role}"),
+            Verdict::Denied { rule, overrode: Some(role) } => write!(f, "denied by rule {rule}, overriding role {role}"),
+            Verdict::Denied { rule, overrode: None } => write!(f, "denied by rule {rule}"),
+            Verdict::NoGrant => f.write_str("no matching grant"),
+        }
+    }
+}
+
+/// [`PolicyEngine`] evaluating a [`RuleSet`]; see the [module docs](self).
+#[derive(Debug)]
+pub struct RulePolicy {
+    rules: RuleSet,
+    /// Every role with the roles it inherits, transitively.
+    expanded: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl RulePolicy {
+    /// Validate `rules`: inherited and assigned roles must exist and times
+    /// must be `HH:MM`.
+    pub fn new(rules: RuleSet) -> HubResult<Self> {
+        let known = |role: &String, whose: &str| {
+            if rules.roles.contains_key(role) {
+                Ok(())
+            } else {
+                Err(HubError::InvalidInput(format!("{whose} refers to unknown role {role}")))
+            }
+        };
+        for (name, role) in &rules.roles {
+            role.inherits.iter().try_for_each(|r| known(r, &format!("role {name}")))?;
+            role.grants.iter().flat_map(|g| &g.when).try_for_each(check_condition)?;
+        }
+        for (id, principal) in &rules.principals {
+            principal.roles.iter().try_for_each(|r| known(r, &format!("principal {id}")))?;
+        }
+        rules.deny.iter().flat_map(|d| &d.when).try_for_each(check_condition)?;
+        let expanded = rules.roles.keys().map(|name| {
+            let mut seen = BTreeSet::new();
+            let mut todo = vec![name.clone()];
+            while let Some(role) = todo.pop() {
+                if seen.insert(role.clone()) {
+                    todo.extend(rules.roles[&role].inherits.iter().cloned());
+                }
+            }
+            (name.clone(), seen)
+        }).collect();
+        Ok(Self { rules, expanded })
+    }
+
+    pub fn from_json(text: &str) -> HubResult<Self> {
+        Self::new(serde_json::from_str(text).map_err(|e| HubError::InvalidInput(format!("policy rules: {e}")))?)
+    }
+
+    #[cfg(feature = "policy_toml")]
+    pub fn from_toml(text: &str) -> HubResult<Self> {
+        Self::new(toml::from_str(text).map_err(|e| HubError::InvalidInput(format!("policy rules: {e}")))?)
+    }
+
+    /// Load rules from a `.json` file, or a `.toml` file with feature `policy_toml`.
+    pub fn from_file(path: impl AsRef<Path>) -> HubResult<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)?;
+        match path.extension().and_then(|e| e.to_str()) {
+            #[cfg(feature = "policy_toml")]
+            Some("toml") => Self::from_toml(&text),
+            Some("json") => Self::from_json(&text),
+            _ => Err(HubError::Unsupported(format!("policy rule file {}: expected .json{}", path.display(), if cfg!(feature = "policy_toml") { " or .toml" } else { "" }))),
+        }
+    }
+
+    /// In dry-run mode refusals are logged at `warn` level and the request
+    /// is allowed anyway, to try out new rules on live traffic.
+    pub fn set_dry_run(&mut self, enabled: bool) {
+        self.rules.dry_run = enabled;
+    }
+
+    pub fn rules(&self) -> &RuleSet {
+        &self.rules
+    }
+
+    /// Decide `action` on `context_json` and say why, ignoring dry-run mode.
+    pub fn explain(&self, action: &str, context_json: &Value) -> Verdict {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        self.verdict_at(action, context_json, now)
+    }
+
+    pub(crate) fn verdict_at(&self, action: &str, ctx: &Value, unix_secs: u64) -> Verdict {
+        let Ok(action) = action.parse::<Action>() else { return Verdict::NoGrant };
+        let span = Span::of(ctx);
+        let id = ctx.pointer("/principal/id").and_then(Value::as_str).unwrap_or_default();
+        let roles = self.roles_of(id, ctx);
+        let applies = |actions: &[Action], keys: &[String], when: &[Condition], matches: fn(&str, &Span) -> bool| {
+            (actions.is_empty() || actions.contains(&action))
+                && (keys.is_empty() || keys.iter().any(|p| matches(p, &span)))
+                && when.iter().all(|c| holds(c, ctx, unix_secs))
+        };
+        let granted = roles.iter().find(|role| {
+            self.rules.roles[*role].grants.iter().any(|g| applies(&g.actions, &g.keys, &g.when, covers))
+        });
+        let denied = self.rules.deny.iter().enumerate().find(|(_, d)| {
+            let targeted = (d.roles.is_empty() && d.principals.is_empty())
+                || d.roles.iter().any(|r| roles.contains(r))
+                || d.principals.iter().any(|p| p == id);
+            targeted && applies(&d.actions, &d.keys, &d.when, overlaps)
+        });
+        match (denied, granted) {
+            (Some((idx, d)), granted) => Verdict::Denied {
+                rule: d.name.clone().unwrap_or_else(|| format!("deny[{idx}]")),
+                overrode: granted.cloned(),
+            },
+            (None, Some(role)) => Verdict::Granted { role: role.clone() },
+            (None, None) => Verdict::NoGrant,
+        }
+    }
+
+    /// Roles of the caller, inherited ones included. Unknown roles carried
+    /// by the principal are ignored.
+    fn roles_of(&self, id: &str, ctx: &Value) -> BTreeSet<String> {
+        let carried = ctx.pointer("/principal/roles").and_then(Value::as_array).into_iter().flatten().filter_map(Value::as_str);
+        let assigned = self.rules.principals.get(id).into_iter().flat_map(|p| p.roles.iter().map(String::as_str));
+        carried.chain(assigned).filter_map(|r| self.expanded.get(r)).flatten().cloned().collect()
+    }
+}
+
+impl PolicyEngine for RulePolicy {
+    fn allow(&self, action: &str, context_json: &Value) -> bool {
+        let verdict = self.explain(action, context_json);
+        if verdict.is_allowed() {
+            return true;
+        }
+        if self.rules.dry_run {
+            let principal = context_json.pointer("/principal/id").and_then(Value::as_str).unwrap_or_default();
+            let key = context_json.get("key").and_then(Value::as_str).unwrap_or("*");
+            log::warn!("policy dry run: would deny {action} by {principal} on {key}: {verdict}");
+            return true;
+        }
+        false
+    }
+}
+
+/// Keys a request touches: one key, or the range of a scan as described
+/// on [`PolicyEngine`].
+enum Span<'a> {
+    All,
+    Key(&'a str),
+    Prefix(&'a str),
+    Range(Bound<&'a str>, Bound<&'a str>),
+}
+
+impl<'a> Span<'a> {
+    fn of(ctx: &'a Value) -> Self {
+        let bound = |range: &'a Value, side: &str| match range.get(side).and_then(Value::as_str) {
+            Some(key) if range[format!("{side}_inclusive")] == true => Bound::Included(key),
+            Some(key) => Bound::Excluded(key),
+            None => Bound::Unbounded,
+        };
+        match ctx.get("range") {
+            Some(range) if range.is_object() => match range.get("prefix").and_then(Value::as_str) {
+                Some(prefix) => Span::Prefix(prefix),
+                None => Span::Range(bound(range, "start"), bound(range, "end")),
+            },
+            _ => ctx.get("key").and_then(Value::as_str).map_or(Span::All, Span::Key),
+        }
+    }
+}
+
+/// Whether every key of `span` matches `pattern`.
+fn covers(pattern: &str, span: &Span) -> bool {
+    match (pattern.strip_suffix('*'), span) {
+        (Some(""), _) => true,
+        (Some(prefix), Span::Key(key) | Span::Prefix(key)) => key.starts_with(prefix),
+        // the keys between two keys with a common prefix share it
+        (Some(prefix), Span::Range(Bound::Included(start) | Bound::Excluded(start), Bound::Included(end) | Bound::Excluded(end))) => {
+            start.starts_with(prefix) && end.starts_with(prefix)
+        }
+        (None, Span::Key(key)) => *key == pattern,
+        (None, Span::Range(Bound::Included(start), Bound::Included(end))) => *start == pattern && *end == pattern,
+        _ => false,
+    }
+}
+
+/// Whether some key of `span` may match `pattern`.
+fn overlaps(pattern: &str, span: &Span) -> bool {
+    match (pattern.strip_suffix('*'), span) {
+        (Some(""), _) | (_, Span::All) => true,
+        (Some(prefix), Span::Key(key)) => key.starts_with(prefix),
+        (Some(prefix), Span::Prefix(other)) => other.starts_with(prefix) || prefix.starts_with(other),
+        (Some(prefix), Span::Range(start, end)) => {
+            // keys with the prefix run from the prefix itself up to, not
+            // including, the first key after them
+            let starts_early = match start {
+                Bound::Included(start) | Bound::Excluded(start) => *start < prefix || start.starts_with(prefix),
+                Bound::Unbounded => true,
+            };
+            let ends_late = match end {
+                Bound::Included(end) => *end >= prefix,
+                Bound::Excluded(end) => *end > prefix,
+                Bound::Unbounded => true,
+            };
+            starts_early && ends_late
+        }
+        (None, Span::Key(key)) => *key == pattern,
+        (None, Span::Prefix(prefix)) => pattern.starts_with(prefix),
+        (None, Span::Range(start, end)) => {
+            let range = crate::backend::ScanRange::Range(start.map(str::to_string), end.map(str::to_string));
+            range.contains(pattern)
+        }
+    }
+}
+
+fn field<'a>(ctx: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.').try_fold(ctx, |v, part| v.get(part)).filter(|v| !v.is_null())
+}
+
+fn holds(condition: &Condition, ctx: &Value, unix_secs: u64) -> bool {
+    match condition {
+        Condition::Equals { field: f, value } => field(ctx, f) == Some(value),
+        Condition::OneOf { field: f, values } => field(ctx, f).is_some_and(|v| values.contains(v)),
+        Condition::SameAs { field: f, other } => field(ctx, f).is_some_and(|v| field(ctx, other) == Some(v)),
+        Condition::AtMost { field: f, max } => field(ctx, f).and_then(Value::as_f64).is_some_and(|v| v <= *max),
+        Condition::TimeWindow { from, to, days } => {
+            let (Some(from), Some(to)) = (minutes(from), minutes(to)) else { return false };
+            let now = (unix_secs / 60 % 1440) as u32;
+            let in_window = if from <= to { from <= now && now < to } else { now >= from || now < to };
+            // the hours after midnight belong to the window opened the day
+            // before, six days on in the week
+            let opened = unix_secs / 86_400 + if from > to && now < to { 6 } else { 0 };
+            // 1970-01-01 was a Thursday
+            let day = [Weekday::Thu, Weekday::Fri, Weekday::Sat, Weekday::Sun, Weekday::Mon, Weekday::Tue, Weekday::Wed][(opened % 7) as usize];
+            in_window && (days.is_empty() || days.contains(&day))
+        }
+    }
+}
+
+/// Minutes since midnight of an `HH:MM` time.
+fn minutes(time: &str) -> Option<u32> {
+    let (h, m) = time.split_once(':')?;
+    let (h, m): (u32, u32) = (h.parse().ok()?, m.parse().ok()?);
+    (h < 24 && m < 60).then_some(h * 60 + m)
+}
+
+fn check_condition(condition: &Condition) -> HubResult<()> {
+    if let Condition::TimeWindow { from, to, .. } = condition {
+        for time in [from, to] {
+            if minutes(time).is_none() {
+                return Err(HubError::InvalidInput(format!("time {time:?} is not HH:MM")));
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,174 @@
+//! Metadata envelope stored by the built-in backends alongside every value.
+//!
+//! Values written before envelopes existed are still readable: they surface
+//! as [`Record::legacy`] with version `0` and no timestamps.
+#[cfg(any(feature = "longmem_sled", feature = "detailmem_fs"))]
+use crate::backend::HubResult;
+#[cfg(any(feature = "longmem_sled", feature = "detailmem_fs"))]
+use crate::error::HubError;
+use crate::ttl::now_millis;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Metadata kept for a stored value.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordMeta {
+    /// Per-key version, higher for every later write. Writes through the
+    /// hub store the same version on every backend. `0` for legacy values.
+    pub version: u64,
+    /// First write of the key, in epoch milliseconds (`0` if unknown).
+    pub created_at: u64,
+    /// Latest write of the key, in epoch milliseconds (`0` if unknown).
+    pub updated_at: u64,
+    /// MIME-style content type supplied by the writer.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    /// CRC-32 of the value.
+    pub checksum: u32,
+    /// Free-form user tags.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: BTreeMap<String, String>,
+    /// Expiry deadline in epoch milliseconds, if the value was written with one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+}
+
+impl RecordMeta {
+    /// Metadata for a new write of `value`, following `prev` if the key existed.
+    pub(crate) fn next(prev: Option<&RecordMeta>, value: &[u8], meta: &WriteMeta) -> Self {
+        let now = now_millis();
+        Self {
+            version: meta.version.unwrap_or_else(|| prev.map_or(1, |p| p.version + 1)),
+            created_at: prev.map_or(now, |p| if p.created_at == 0 { now } else { p.created_at }),
+            updated_at: now,
+            content_type: meta.content_type.clone(),
+            checksum: crc32fast::hash(value),
+            tags: meta.tags.clone(),
+            expires_at: meta.expires_at,
+        }
+    }
+}
+
+/// Caller-supplied part of the metadata for [`MemoryBackend::write_with_meta`](crate::MemoryBackend::write_with_meta).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WriteMeta {
+    pub content_type: Option<String>,
+    pub tags: BTreeMap<String, String>,
+    /// Version to store instead of the backend's next one, so that copies
+    /// of a value carry the same version on every backend.
+    pub version: Option<u64>,
+    /// Expiry deadline in epoch milliseconds; `None` clears any expiry.
+    pub expires_at: Option<u64>,
+}
+
+impl WriteMeta {
+    /// The writer-controlled part of `meta`, to copy a record as it is.
+    pub fn of(meta: &RecordMeta) -> Self {
+        Self {
+            content_type: meta.content_type.clone(),
+            tags: meta.tags.clone(),
+            version: Some(meta.version),
+            expires_at: meta.expires_at,
+        }
+    }
+}
+
+/// A value together with its metadata.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub value: Vec<u8>,
+    pub meta: RecordMeta,
+}
+
+impl Record {
+    /// Wrap a value that was stored without an envelope.
+    pub fn legacy(value: Vec<u8>) -> Self {
+        let meta = RecordMeta { checksum: crc32fast::hash(&value), ..Default::default() };
+        Self { value, meta }
+    }
+
+    /// Whether the stored checksum matches the value.
+    pub fn verify(&self) -> bool {
+        crc32fast::hash(&self.value) == self.meta.checksum
+    }
+
+    /// Fail with an integrity error if the checksum does not match.
+    #[cfg(any(feature = "longmem_sled", feature = "detailmem_fs"))]
+    pub(crate) fn checked(self, key: &str) -> HubResult<Self> {
+        if self.verify() {
+            return Ok(self);
+        }
+        Err(HubError::Corruption(format!("checksum mismatch for {key}")))
+    }
+}
+
+/// Condition for [`MemoryBackend::compare_and_swap`](crate::MemoryBackend::compare_and_swap).
+/// Expired entries count as absent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expected {
+    /// The key must not exist.
+    Absent,
+    /// The key must exist with this record version (`0` for legacy values).
+    Version(u64),
+    /// The key must exist with exactly these bytes.
+    Bytes(Vec<u8>),
+}
+
+impl Expected {
+    /// Whether `current` satisfies the condition.
+    pub fn matches(&self, current: Option<&Record>) -> bool {
+        match (self, current) {
+            (Expected::Absent, None) => true,
+            (Expected::Version(v), Some(r)) => r.meta.version == *v,
+            (Expected::Bytes(b), Some(r)) => r.value == *b,
+            _ => false,
+        }
+    }
+}
+
+/// Result of a conditional write.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CasOutcome {
+    /// The value was stored under the given new version.
+    Swapped { version: u64 },
+    /// The condition did not hold; carries the record stored now.
+    Conflict { current: Option<Record> },
+}
+
+impl CasOutcome {
+    pub fn is_swapped(&self) -> bool {
+        matches!(self, CasOutcome::Swapped { .. })
+    }
+}
+
+/// Leading bytes of an encoded envelope.
+#[cfg(feature = "longmem_sled")]
+const MAGIC: &[u8; 4] = b"CVR\x01";
+
+/// Binary envelope: magic, big-endian header length, JSON header, value.
+#[cfg(feature = "longmem_sled")]
+pub(crate) fn encode(meta: &RecordMeta, value: &[u8]) -> HubResult<Vec<u8>> {
+    let header = serde_json::to_vec(meta)?;
+    let mut out = Vec::with_capacity(MAGIC.len() + 4 + header.len() + value.len());
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&(header.len() as u32).to_be_bytes());
+    out.extend_from_slice(&header);
+    out.extend_from_slice(value);
+    Ok(out)
+}
+
+/// Inverse of [`encode`]. Bytes without a valid envelope are legacy values.
+#[cfg(feature = "longmem_sled")]
+pub(crate) fn decode(bytes: Vec<u8>) -> Record {
+    let parsed = (|| {
+        let rest = bytes.strip_prefix(MAGIC)?;
+        let len = u32::from_be_bytes(rest.get(..4)?.try_into().ok()?) as usize;
+        let header = rest.get(4..4 + len)?;
+        let meta: RecordMeta = serde_json::from_slice(header).ok()?;
+        Some((meta, MAGIC.len() + 4 + len))
+    })();
+    match parsed {
+        Some((meta, start)) => Record { value: bytes[start..].to_vec(), meta },
+        None => Record::legacy(bytes),
+    }
+}
@@ -0,0 +1,202 @@
+//! Read-repair and anti-entropy between hub backends.
+//!
+//! Read-repair runs after a successful hub read and copies the winning record
+//! to every backend holding an older copy or none. Anti-entropy (feature
+//! `merkle_log`) walks all backends, compares per-prefix Merkle digests and
+//! reconciles only the buckets that differ. Both write conditionally on the
+//! copy they observed, so a write racing the repair is never overwritten, and
+//! keep the winner's version, metadata and expiry.
+use crate::backend::{HubResult, MemoryBackend};
+use crate::record::{Expected, Record, WriteMeta};
+use crate::runtime;
+use crate::strategy::{Named, ReadStrategy};
+use std::sync::Arc;
+
+/// Whether `current`, one backend's copy of a key, should give way to
+/// `winner`: under [`ReadStrategy::NewestWins`] only older copies do,
+/// otherwise any copy holding a different value.
+fn is_stale(strategy: ReadStrategy, winner: &Record, current: Option<&Record>) -> bool {
+    match current {
+        None => true,
+        Some(c) if strategy == ReadStrategy::NewestWins => c.meta.version < winner.meta.version,
+        Some(c) => c.value != winner.value,
+    }
+}
+
+/// Replace `current`, the copy of `key` observed on `be`, with `winner`.
+/// Returns `false` if the copy changed since it was observed.
+async fn repair_copy(be: &Arc<dyn MemoryBackend>, key: String, winner: &Record, current: Option<&Record>) -> HubResult<bool> {
+    let expected = match current {
+        None => Expected::Absent,
+        Some(c) if c.meta.version > 0 => Expected::Version(c.meta.version),
+        Some(c) => Expected::Bytes(c.value.clone()),
+    };
+    let outcome = be.compare_and_swap_with_meta(key, expected, winner.value.clone(), WriteMeta::of(&winner.meta)).await?;
+    Ok(outcome.is_swapped())
+}
+
+/// Copy `winner` in the background to backends whose copy of `key` is stale.
+/// Only sensible with a strategy that resolves versions or votes,
+/// [`ReadStrategy::NewestWins`] or [`ReadStrategy::Quorum`].
+pub(crate) fn spawn_read_repair(backends: Vec<Named>, key: String, winner: Record, strategy: ReadStrategy) {
+    runtime::spawn(async move {
+        for (_, be) in backends {
+            // errors and lost races are left for the next read or anti-entropy pass
+            if let Ok(current) = be.read_record(key.clone()).await
+                && is_stale(strategy, &winner, current.as_ref())
+            {
+                let _ = repair_copy(&be, key.clone(), &winner, current.as_ref()).await;
+            }
+        }
+    });
+}
+
+/// Outcome of one anti-entropy pass.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RepairReport {
+    /// Distinct key-prefix buckets seen across backends.
+    pub buckets_compared: usize,
+    /// Buckets whose digest differed on at least one backend.
+    pub buckets_mismatched: usize,
+    /// Backend copies rewritten with the winning value.
+    pub keys_repaired: usize,
+    /// Keys that could not be resolved or written.
+    pub errors: usize,
+}
+
+#[cfg(feature = "merkle_log")]
+pub(crate) use anti_entropy::run as anti_entropy;
+
+#[cfg(feature = "merkle_log")]
+mod anti_entropy {
+    use super::{is_stale, repair_copy, RepairReport};
+    use crate::backend::{HubResult, MemoryBackend, ScanRange};
+    use crate::merkle;
+    use crate::strategy::{self, Named, ReadStrategy};
+    use futures::stream::StreamExt;
+    use std::collections::{BTreeMap, BTreeSet};
+    use std::sync::Arc;
+
+    /// Bucket of a key: its first `prefix_len` characters.
+    fn bucket_of(key: &str, prefix_len: usize) -> String {
+        key.chars().take(prefix_len).collect()
+    }
+
+    /// Leaf hash covering both key and value, so renamed keys are detected too.
+    fn entry_hash(key: &str, value: &[u8]) -> [u8; 32] {
+        let mut buf = Vec::with_capacity(8 + key.len() + value.len());
+        buf.extend_from_slice(&(key.len() as u64).to_be_bytes());
+        buf.extend_from_slice(key.as_bytes());
+        buf.extend_from_slice(value);
+        merkle::leaf(&buf)
+    }
+
+    /// Merkle root per bucket. Scans are key-ordered, so every bucket is a
+    /// contiguous run and only one bucket's leaves are held at a time.
+    async fn bucket_digests(be: &Arc<dyn MemoryBackend>, prefix_len: usize, owned: impl Fn(&str) -> bool) -> HubResult<BTreeMap<String, [u8; 32]>> {
+        let mut digests = BTreeMap::new();
+        let mut current: Option<(String, Vec<[u8; 32]>)> = None;
+        let mut entries = be.scan(ScanRange::All);
+        while let Some(item) = entries.next().await {
+            let (key, value) = item?;
+            if !owned(&key) {
+                continue;
+            }
+            let bucket = bucket_of(&key, prefix_len);
+            match current.as_mut() {
+                Some((b, leaves)) if *b == bucket => leaves.push(entry_hash(&key, &value)),
+                _ => {
+                    if let Some((b, leaves)) = current.take() {
+                        digests.insert(b, merkle::root_of(leaves));
+                    }
+                    current = Some((bucket, vec![entry_hash(&key, &value)]));
+                }
+            }
+        }
+        if let Some((b, leaves)) = current {
+            digests.insert(b, merkle::root_of(leaves));
+        }
+        Ok(digests)
+    }
+
+    /// Per-key hashes of a single bucket.
+    async fn bucket_entries(be: &Arc<dyn MemoryBackend>, bucket: &str, prefix_len: usize, owned: impl Fn(&str) -> bool) -> HubResult<BTreeMap<String, [u8; 32]>> {
+        let mut out = BTreeMap::new();
+        let mut entries = be.scan(ScanRange::Prefix(bucket.to_string()));
+        while let Some(item) = entries.next().await {
+            let (key, value) = item?;
+            if bucket_of(&key, prefix_len) == bucket && owned(&key) {
+                out.insert(key.clone(), entry_hash(&key, &value));
+            }
+        }
+        Ok(out)
+    }
+
+    /// Compare all backends bucket by bucket and rewrite differing keys with
+    /// the value chosen by `strategy`. `owns(i, key)` tells whether backend `i`
+    /// is meant to hold `key`; other copies are ignored.
+    pub(crate) async fn run(
+        backends: &[Named],
+        strategy: ReadStrategy,
+        prefix_len: usize,
+        owns: &(dyn Fn(usize, &str) -> bool + Send + Sync),
+    ) -> HubResult<RepairReport> {
+        let mut report = RepairReport::default();
+        if backends.len() < 2 {
+            return Ok(report);
+        }
+        let mut digests = Vec::with_capacity(backends.len());
+        for (idx, (name, be)) in backends.iter().enumerate() {
+            digests.push(bucket_digests(be, prefix_len, |key| owns(idx, key)).await.map_err(|e| e.in_backend(name.as_str()))?);
+        }
+        let buckets: BTreeSet<&String> = digests.iter().flat_map(|d| d.keys()).collect();
+        report.buckets_compared = buckets.len();
+
+        for bucket in buckets {
+            let first = digests[0].get(bucket);
+            if digests.iter().all(|d| d.get(bucket) == first) {
+                continue;
+            }
+            report.buckets_mismatched += 1;
+
+            let mut per_backend = Vec::with_capacity(backends.len());
+            for (idx, (name, be)) in backends.iter().enumerate() {
+                per_backend.push(bucket_entries(be, bucket, prefix_len, |key| owns(idx, key)).await.map_err(|e| e.in_backend(name.as_str()))?);
+            }
+            let keys: BTreeSet<&String> = per_backend.iter().flat_map(|m| m.keys()).collect();
+            for key in keys {
+                let owners: Vec<usize> = (0..backends.len()).filter(|&idx| owns(idx, key)).collect();
+                let first = owners.first().and_then(|&idx| per_backend[idx].get(key));
+                if owners.iter().all(|&idx| per_backend[idx].get(key) == first) {
+                    continue;
+                }
+                let sources: Vec<Named> = owners.iter().map(|&idx| backends[idx].clone()).collect();
+                let winner = match strategy::read_record(strategy, &sources, key.clone()).await {
+                    Ok(Some(r)) => r,
+                    // deletions are not propagated; unresolved keys wait for a later pass
+                    Ok(None) => continue,
+                    Err(_) => { report.errors += 1; continue; }
+                };
+                let expected = entry_hash(key, &winner.value);
+                for &idx in &owners {
+                    let ((_, be), entries) = (&backends[idx], &per_backend[idx]);
+                    if entries.get(key) == Some(&expected) {
+                        continue;
+                    }
+                    let repaired = match be.read_record(key.clone()).await {
+                        Ok(current) if is_stale(strategy, &winner, current.as_ref()) => repair_copy(be, key.clone(), &winner, current.as_ref()).await,
+                        Ok(_) => Ok(false),
+                        Err(e) => Err(e),
+                    };
+                    match repaired {
+                        Ok(true) => report.keys_repaired += 1,
+                        // a newer copy, or one written since it was read, is left for a later pass
+                        Ok(false) => {}
+                        Err(_) => report.errors += 1,
+                    }
+                }
+            }
+        }
+        Ok(report)
+    }
+}
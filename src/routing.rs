@@ -0,0 +1,121 @@
+//! Key-prefix routing rules for [`MemoryHub`](crate::MemoryHub).
+//!
+//! Rules are plain data, so they can be loaded from any serde format:
+//!
+//! ```json
+//! { "rules": [
+//!     { "pattern": "emb/*",  "backends": ["longmem", "ann"] },
+//!     { "pattern": "blob/*", "backends": ["detailmem"], "fallback": ["longmem"] },
+//!     { "pattern": "sess/*", "backends": ["shortmem"] }
+//! ] }
+//! ```
+//!
+//! The longest matching prefix wins. Keys no rule matches go to every backend.
+use crate::backend::HubResult;
+use crate::error::HubError;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Declarative routing configuration.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RoutingConfig {
+    #[serde(default)]
+    pub rules: Vec<RouteRule>,
+}
+
+/// Send keys matching `pattern` to the named backends.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RouteRule {
+    /// Key prefix, optionally followed by `*` (`emb/*` and `emb/` are the same rule).
+    pub pattern: String,
+    /// Backends holding matching keys.
+    pub backends: Vec<String>,
+    /// Tried in order when none of `backends` is usable; the first usable one
+    /// takes the key alone.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fallback: Vec<String>,
+}
+
+impl RouteRule {
+    fn prefix(&self) -> &str {
+        self.pattern.strip_suffix('*').unwrap_or(&self.pattern)
+    }
+}
+
+/// Where a key is routed, as reported by [`MemoryHub::explain`](crate::MemoryHub::explain).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RouteExplanation {
+    /// Pattern of the matching rule; `None` if the key goes to every backend.
+    pub rule: Option<String>,
+    /// Usable backends the key goes to right now, in priority order.
+    pub backends: Vec<String>,
+    /// Whether the rule's backends were all unusable and the fallback chain was used.
+    pub via_fallback: bool,
+}
+
+#[derive(Debug, Default)]
+struct Node {
+    children: BTreeMap<u8, usize>,
+    rule: Option<usize>,
+}
+
+/// Compiled rules: a byte-wise prefix trie over the rule prefixes.
+#[derive(Debug)]
+pub(crate) struct Router {
+    config: RoutingConfig,
+    nodes: Vec<Node>,
+}
+
+impl Default for Router {
+    fn default() -> Self {
+        Self { config: RoutingConfig::default(), nodes: vec![Node::default()] }
+    }
+}
+
+impl Router {
+    /// Validate and compile `config`.
+    pub(crate) fn new(config: RoutingConfig) -> HubResult<Self> {
+        let mut nodes = vec![Node::default()];
+        for (idx, rule) in config.rules.iter().enumerate() {
+            let prefix = rule.prefix();
+            if prefix.contains('*') {
+                return Err(HubError::InvalidInput(format!("routing pattern {}: `*` is only allowed at the end", rule.pattern)));
+            }
+            if rule.backends.is_empty() {
+                return Err(HubError::InvalidInput(format!("routing rule {} names no backends", rule.pattern)));
+            }
+            let mut node = 0;
+            for byte in prefix.bytes() {
+                node = match nodes[node].children.get(&byte) {
+                    Some(&next) => next,
+                    None => {
+                        nodes.push(Node::default());
+                        let next = nodes.len() - 1;
+                        nodes[node].children.insert(byte, next);
+                        next
+                    }
+                };
+            }
+            if let Some(prev) = nodes[node].rule.replace(idx) {
+                return Err(HubError::InvalidInput(format!("routing patterns {} and {} overlap", config.rules[prev].pattern, rule.pattern)));
+            }
+        }
+        Ok(Self { config, nodes })
+    }
+
+    pub(crate) fn config(&self) -> &RoutingConfig {
+        &self.config
+    }
+
+    /// Rule with the longest prefix of `key`.
+    pub(crate) fn lookup(&self, key: &str) -> Option<&RouteRule> {
+        let mut node = &self.nodes[0];
+        let mut found = node.rule;
+        for byte in key.bytes() {
+            let Some(&next) = node.children.get(&byte) else { break };
+            node = &self.nodes[next];
+            found = node.rule.or(found);
+        }
+        found.map(|idx| &self.config.rules[idx])
+    }
+}
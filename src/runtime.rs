@@ -0,0 +1,31 @@
+//! Minimal runtime shim so background work (retries, sweepers) does not tie
+//! the crate to one executor. Follows the `runtime_*` feature selection.
+use std::future::Future;
+use std::time::Duration;
+
+/// Spawn a detached background task on the selected runtime.
+pub(crate) fn spawn<F>(fut: F)
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    #[cfg(feature = "runtime_async_std")]
+    { async_std::task::spawn(fut); }
+    #[cfg(all(feature = "runtime_tokio", not(feature = "runtime_async_std")))]
+    { tokio::spawn(fut); }
+    #[cfg(not(any(feature = "runtime_async_std", feature = "runtime_tokio")))]
+    { std::thread::spawn(move || futures::executor::block_on(fut)); }
+}
+
+/// Sleep without blocking the executor.
+pub(crate) async fn sleep(dur: Duration) {
+    #[cfg(feature = "runtime_async_std")]
+    { async_std::task::sleep(dur).await; }
+    #[cfg(all(feature = "runtime_tokio", not(feature = "runtime_async_std")))]
+    { tokio::time::sleep(dur).await; }
+    #[cfg(not(any(feature = "runtime_async_std", feature = "runtime_tokio")))]
+    {
+        let (tx, rx) = futures::channel::oneshot::channel::<()>();
+        std::thread::spawn(move || { std::thread::sleep(dur); let _ = tx.send(()); });
+        let _ = rx.await;
+    }
+}
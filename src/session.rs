@@ -0,0 +1,109 @@
+//! Hub operations on behalf of a [`Principal`].
+use crate::backend::{EntryStream, HubResult, KeyStream, ScanRange};
+use crate::hub::MemoryHub;
+use crate::namespace::Namespace;
+use crate::policy::Principal;
+use crate::record::{CasOutcome, Expected, Record, WriteMeta};
+use crate::strategy::{WriteConcern, WriteReport};
+use crate::txn::Transaction;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Handle running every operation as one principal, so the hub's
+/// [`PolicyEngine`](crate::PolicyEngine) and audit sink see who is calling.
+/// Created by [`MemoryHub::session`]. Cheap to clone.
+#[derive(Clone)]
+pub struct Session {
+    hub: Arc<MemoryHub>,
+    principal: Principal,
+}
+
+impl Session {
+    pub(crate) fn new(hub: Arc<MemoryHub>, principal: Principal) -> Self {
+        Self { hub, principal }
+    }
+
+    pub fn principal(&self) -> &Principal {
+        &self.principal
+    }
+
+    /// Tenant handle acting as this session's principal; see [`MemoryHub::namespace`].
+    pub fn namespace(&self, name: &str) -> HubResult<Namespace> {
+        self.hub.namespace_as(name, self.principal.clone())
+    }
+
+    /// See [`MemoryHub::write`].
+    pub async fn write(&self, key: String, value: Vec<u8>) -> HubResult<()> {
+        self.hub.write_as(&self.principal, key, value).await
+    }
+
+    /// See [`MemoryHub::write_with`].
+    pub async fn write_with(&self, key: String, value: Vec<u8>, concern: WriteConcern) -> HubResult<WriteReport> {
+        self.hub.write_with_as(&self.principal, key, value, concern).await
+    }
+
+    /// See [`MemoryHub::write_with_ttl`].
+    pub async fn write_with_ttl(&self, key: String, value: Vec<u8>, ttl: Duration) -> HubResult<()> {
+        self.hub.write_with_ttl_as(&self.principal, key, value, ttl).await
+    }
+
+    /// See [`MemoryHub::write_with_meta`].
+    pub async fn write_with_meta(&self, key: String, value: Vec<u8>, meta: WriteMeta) -> HubResult<()> {
+        self.hub.write_with_meta_as(&self.principal, key, value, meta).await
+    }
+
+    /// See [`MemoryHub::compare_and_swap`].
+    pub async fn compare_and_swap(&self, key: String, expected: Expected, new: Vec<u8>) -> HubResult<CasOutcome> {
+        self.hub.compare_and_swap_as(&self.principal, key, expected, new).await
+    }
+
+    /// See [`MemoryHub::put_if_absent`].
+    pub async fn put_if_absent(&self, key: String, value: Vec<u8>) -> HubResult<bool> {
+        Ok(self.compare_and_swap(key, Expected::Absent, value).await?.is_swapped())
+    }
+
+    /// See [`MemoryHub::commit`].
+    pub async fn commit(&self, tx: Transaction) -> HubResult<()> {
+        self.hub.commit_as(&self.principal, tx).await
+    }
+
+    /// See [`MemoryHub::write_many`].
+    pub async fn write_many(&self, items: &[(String, Vec<u8>)]) -> Vec<HubResult<()>> {
+        self.hub.write_many_as(&self.principal, items).await
+    }
+
+    /// See [`MemoryHub::read`].
+    pub async fn read(&self, key: String) -> HubResult<Option<Vec<u8>>> {
+        self.hub.read_as(&self.principal, key).await
+    }
+
+    /// See [`MemoryHub::read_with_meta`].
+    pub async fn read_with_meta(&self, key: String) -> HubResult<Option<Record>> {
+        self.hub.read_with_meta_as(&self.principal, key).await
+    }
+
+    /// See [`MemoryHub::read_many`].
+    pub async fn read_many(&self, keys: &[String]) -> Vec<HubResult<Option<Vec<u8>>>> {
+        self.hub.read_many_as(&self.principal, keys).await
+    }
+
+    /// See [`MemoryHub::delete`].
+    pub async fn delete(&self, key: String) -> HubResult<bool> {
+        self.hub.delete_as(&self.principal, key).await
+    }
+
+    /// See [`MemoryHub::contains`].
+    pub async fn contains(&self, key: String) -> HubResult<bool> {
+        self.hub.contains_as(&self.principal, key).await
+    }
+
+    /// See [`MemoryHub::scan`].
+    pub fn scan(&self, range: ScanRange) -> EntryStream {
+        self.hub.scan_as(&self.principal, range)
+    }
+
+    /// See [`MemoryHub::scan_keys`].
+    pub fn scan_keys(&self, range: ScanRange) -> KeyStream {
+        self.hub.scan_keys_as(&self.principal, range)
+    }
+}
@@ -1,22 +1,361 @@
-use crate::backend::{HubResult, MemoryBackend};
+use crate::backend::{EntryStream, HubResult, MemoryBackend, ScanRange};
+use crate::error::HubError;
+use crate::health::Capabilities;
+use crate::eviction::{CacheStats, EvictionListener, EvictionPolicy, Tracker};
+use crate::record::{CasOutcome, Expected, Record, RecordMeta, WriteMeta};
+use crate::ttl::{deadline_millis, now_millis, TimerWheel};
 use async_trait::async_trait;
 use dashmap::DashMap;
-use std::sync::Arc;
+use dashmap::mapref::entry::Entry;
+use futures::stream::{self, StreamExt};
+use crate::txn::{Transaction, TxOp};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
 
-/// Simple in-memory LRU-less backend intended mainly for caching & testing.
+/// In-memory backend intended mainly for caching & testing.
+///
+/// `ShortMem::default()` is unbounded. [`ShortMem::with_capacity`] caps the
+/// total key+value bytes and the entry count and evicts according to an
+/// [`EvictionPolicy`]; evicted entries can be handed to a listener. A write
+/// the cache does not keep, because admission was refused or the entry is
+/// too big for the budget, fails with [`HubError::Rejected`].
+/// Entries written with a TTL are hidden once expired and reclaimed by
+/// `sweep_expired`, driven by a timer wheel.
+/// Transactions commit under per-key lock stripes, so readers of single keys
+/// never observe half of a commit.
 #[derive(Default)]
 pub struct ShortMem {
     inner: Arc<DashMap<String, Vec<u8>>>,
+    stripes: Stripes,
+    tracker: Option<Arc<Mutex<Tracker>>>,
+    metas: DashMap<String, RecordMeta>,
+    expiries: DashMap<String, Instant>,
+    wheel: Mutex<TimerWheel>,
+    listener: Option<EvictionListener>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+    rejected: AtomicU64,
+}
+
+const STRIPES: usize = 64;
+
+/// Lock stripes guarding keys: single-key operations take one stripe,
+/// transaction commits take all stripes they touch in index order.
+struct Stripes(Vec<RwLock<()>>);
+
+impl Default for Stripes {
+    fn default() -> Self {
+        Self((0..STRIPES).map(|_| RwLock::new(())).collect())
+    }
+}
+
+impl Stripes {
+    fn index(key: &str) -> usize {
+        let mut h = DefaultHasher::new();
+        key.hash(&mut h);
+        h.finish() as usize % STRIPES
+    }
+
+    fn read(&self, key: &str) -> RwLockReadGuard<'_, ()> {
+        self.0[Self::index(key)].read().expect("stripe lock")
+    }
+
+    fn write(&self, key: &str) -> RwLockWriteGuard<'_, ()> {
+        self.0[Self::index(key)].write().expect("stripe lock")
+    }
+
+    fn write_all<'a>(&self, keys: impl IntoIterator<Item = &'a str>) -> Vec<RwLockWriteGuard<'_, ()>> {
+        let mut idx: Vec<usize> = keys.into_iter().map(Self::index).collect();
+        idx.sort_unstable();
+        idx.dedup();
+        idx.into_iter().map(|i| self.0[i].write().expect("stripe lock")).collect()
+    }
+}
+
+impl ShortMem {
+    /// Create a bounded cache holding at most `max_bytes` of keys+values and `max_entries` entries.
+    pub fn with_capacity(max_bytes: usize, max_entries: usize, policy: EvictionPolicy) -> Self {
+        Self {
+            tracker: Some(Arc::new(Mutex::new(Tracker::new(policy, max_bytes, max_entries)))),
+            ..Default::default()
+        }
+    }
+
+    /// Call `listener` with every entry evicted (or refused admission) by the capacity bound.
+    pub fn with_eviction_listener(mut self, listener: EvictionListener) -> Self {
+        self.listener = Some(listener);
+        self
+    }
+
+    /// Hit, miss and eviction counters plus current occupancy.
+    pub fn stats(&self) -> CacheStats {
+        let (entries, bytes) = match &self.tracker {
+            Some(t) => {
+                let t = t.lock().expect("tracker lock");
+                (t.len(), t.bytes())
+            }
+            None => (self.inner.len(), self.inner.iter().map(|e| e.key().len() + e.value().len()).sum()),
+        };
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed),
+            entries,
+            bytes,
+        }
+    }
+
+    /// Store the entry. Fails with [`HubError::Rejected`] if the capacity
+    /// bound dropped it again at once.
+    fn put(&self, key: String, value: Vec<u8>, meta: &WriteMeta) -> HubResult<()> {
+        let next = RecordMeta::next(self.metas.get(&key).as_deref(), &value, meta);
+        self.metas.insert(key.clone(), next);
+        self.set_expiry(&key, meta.expires_at);
+        let Some(tracker) = &self.tracker else {
+            self.inner.insert(key, value);
+            return Ok(());
+        };
+        // map and tracker change under one lock so they never disagree
+        let evicted = {
+            let mut t = tracker.lock().expect("tracker lock");
+            let victims = t.insert(&key, key.len() + value.len());
+            self.inner.insert(key.clone(), value);
+            self.drop_victims(victims)
+        };
+        self.notify(&key, evicted)
+    }
+
+    /// Set or clear the deadline of `key` from an epoch-milliseconds expiry.
+    fn set_expiry(&self, key: &str, expires_at: Option<u64>) {
+        match expires_at {
+            Some(at) => {
+                let at = Instant::now() + Duration::from_millis(at.saturating_sub(now_millis()));
+                self.expiries.insert(key.to_string(), at);
+                self.wheel.lock().expect("wheel lock").schedule(key.to_string(), at);
+            }
+            None if !self.expiries.is_empty() => { self.expiries.remove(key); }
+            None => {}
+        }
+    }
+
+    /// Store `value` with `meta` only if the live entry matches `expected`.
+    /// Fails with [`HubError::Rejected`] like [`put`](Self::put).
+    fn swap(&self, key: String, expected: &Expected, value: Vec<u8>, meta: &WriteMeta) -> HubResult<CasOutcome> {
+        // same lock order as `put`: tracker first, then the map shard
+        let mut tracker = self.tracker.as_ref().map(|t| t.lock().expect("tracker lock"));
+        let size = key.len() + value.len();
+        let version = {
+            let entry = self.inner.entry(key.clone());
+            let current = match &entry {
+                Entry::Occupied(e) if !self.is_expired(&key) => Some(match self.metas.get(&key) {
+                    Some(meta) => Record { value: e.get().clone(), meta: meta.clone() },
+                    None => Record::legacy(e.get().clone()),
+                }),
+                _ => None,
+            };
+            if !expected.matches(current.as_ref()) {
+                return Ok(CasOutcome::Conflict { current });
+            }
+            let next = RecordMeta::next(current.as_ref().map(|r| &r.meta), &value, meta);
+            let version = next.version;
+            self.metas.insert(key.clone(), next);
+            entry.insert(value);
+            version
+        };
+        self.set_expiry(&key, meta.expires_at);
+        let evicted = match tracker.as_mut() {
+            Some(t) => {
+                let victims = t.insert(&key, size);
+                self.drop_victims(victims)
+            }
+            None => Vec::new(),
+        };
+        drop(tracker);
+        self.notify(&key, evicted)?;
+        Ok(CasOutcome::Swapped { version })
+    }
+
+    /// Remove evicted keys from the map. Caller holds the tracker lock.
+    fn drop_victims(&self, victims: Vec<String>) -> Vec<(String, Vec<u8>)> {
+        victims.iter().filter_map(|k| {
+            self.expiries.remove(k);
+            self.metas.remove(k);
+            self.inner.remove(k)
+        }).collect()
+    }
+
+    /// Count and report entries evicted by a write of `written`, which fails
+    /// if the written entry is among them.
+    fn notify(&self, written: &str, evicted: Vec<(String, Vec<u8>)>) -> HubResult<()> {
+        if evicted.is_empty() {
+            return Ok(());
+        }
+        let rejected = evicted.iter().any(|(k, _)| k == written);
+        let counter = if rejected { &self.rejected } else { &self.evictions };
+        counter.fetch_add(1, Ordering::Relaxed);
+        self.evictions.fetch_add(evicted.len() as u64 - 1, Ordering::Relaxed);
+        if let Some(listener) = &self.listener {
+            for (k, v) in evicted {
+                listener(k, v);
+            }
+        }
+        if rejected {
+            return Err(HubError::Rejected(format!("{written} was not kept by the cache")));
+        }
+        Ok(())
+    }
+
+    fn is_expired(&self, key: &str) -> bool {
+        !self.expiries.is_empty() && self.expiries.get(key).is_some_and(|at| *at <= Instant::now())
+    }
+
+    /// Drop the entry from the map and all bookkeeping.
+    fn remove_entry(&self, key: &str) -> Option<Vec<u8>> {
+        self.expiries.remove(key);
+        self.metas.remove(key);
+        let Some(tracker) = &self.tracker else {
+            return self.inner.remove(key).map(|(_, v)| v);
+        };
+        let mut t = tracker.lock().expect("tracker lock");
+        t.remove(key);
+        self.inner.remove(key).map(|(_, v)| v)
+    }
+
+    fn get(&self, key: &str) -> Option<Vec<u8>> {
+        let mut value = self.inner.get(key).map(|v| v.value().clone());
+        if value.is_some() && self.is_expired(key) {
+            self.remove_entry(key);
+            value = None;
+        }
+        let counter = if value.is_some() { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+        if let Some(tracker) = &self.tracker {
+            let mut t = tracker.lock().expect("tracker lock");
+            if value.is_some() { t.touch(key) } else { t.miss(key) }
+        }
+        value
+    }
 }
 
 #[async_trait]
 impl MemoryBackend for ShortMem {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            scan: true,
+            ttl: true,
+            compare_and_swap: true,
+            transactions: true,
+            metadata: true,
+            ..Default::default()
+        }
+    }
+
     async fn write(&self, key: String, value: Vec<u8>) -> HubResult<()> {
-        self.inner.insert(key, value);
+        let _stripe = self.stripes.write(&key);
+        self.put(key, value, &WriteMeta::default())
+    }
+
+    async fn write_with_ttl(&self, key: String, value: Vec<u8>, ttl: Duration) -> HubResult<()> {
+        let _stripe = self.stripes.write(&key);
+        self.put(key, value, &WriteMeta { expires_at: Some(deadline_millis(ttl)), ..Default::default() })
+    }
+
+    async fn sweep_expired(&self) -> HubResult<usize> {
+        let now = Instant::now();
+        let due = self.wheel.lock().expect("wheel lock").advance(now);
+        let mut removed = 0;
+        for (key, scheduled) in due {
+            // skip keys rewritten since they were scheduled
+            let _stripe = self.stripes.write(&key);
+            if self.expiries.get(&key).is_some_and(|at| *at == scheduled) && self.remove_entry(&key).is_some() {
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    async fn write_with_meta(&self, key: String, value: Vec<u8>, meta: WriteMeta) -> HubResult<()> {
+        let _stripe = self.stripes.write(&key);
+        self.put(key, value, &meta)
+    }
+
+    async fn compare_and_swap(&self, key: String, expected: Expected, new: Vec<u8>) -> HubResult<CasOutcome> {
+        self.compare_and_swap_with_meta(key, expected, new, WriteMeta::default()).await
+    }
+
+    async fn compare_and_swap_with_meta(&self, key: String, expected: Expected, new: Vec<u8>, meta: WriteMeta) -> HubResult<CasOutcome> {
+        let _stripe = self.stripes.write(&key);
+        self.swap(key, &expected, new, &meta)
+    }
+
+    async fn commit(&self, tx: Transaction) -> HubResult<()> {
+        let _stripes = self.stripes.write_all(tx.keys());
+        let meta = WriteMeta { version: tx.version(), ..Default::default() };
+        for op in tx.into_ops() {
+            match op {
+                // the commit applies as a whole; a put the capacity bound drops
+                // again only shows in the listener and stats
+                TxOp::Put { key, value } => { let _ = self.put(key, value, &meta); }
+                TxOp::Delete { key } => { self.remove_entry(&key); }
+            }
+        }
         Ok(())
     }
 
     async fn read(&self, key: String) -> HubResult<Option<Vec<u8>>> {
-        Ok(self.inner.get(&key).map(|v| v.value().clone()))
+        let _stripe = self.stripes.read(&key);
+        Ok(self.get(&key))
+    }
+
+    async fn read_record(&self, key: String) -> HubResult<Option<Record>> {
+        let _stripe = self.stripes.read(&key);
+        Ok(self.get(&key).map(|value| match self.metas.get(&key) {
+            Some(meta) => Record { value, meta: meta.clone() },
+            None => Record::legacy(value),
+        }))
+    }
+
+    async fn write_many(&self, items: &[(String, Vec<u8>)]) -> Vec<HubResult<()>> {
+        self.write_many_with_meta(items, WriteMeta::default()).await
+    }
+
+    async fn write_many_with_meta(&self, items: &[(String, Vec<u8>)], meta: WriteMeta) -> Vec<HubResult<()>> {
+        items.iter().map(|(k, v)| {
+            let _stripe = self.stripes.write(k);
+            self.put(k.clone(), v.clone(), &meta)
+        }).collect()
+    }
+
+    async fn read_many(&self, keys: &[String]) -> Vec<HubResult<Option<Vec<u8>>>> {
+        keys.iter().map(|k| {
+            let _stripe = self.stripes.read(k);
+            Ok(self.get(k))
+        }).collect()
+    }
+
+    async fn delete(&self, key: String) -> HubResult<bool> {
+        let _stripe = self.stripes.write(&key);
+        let expired = self.is_expired(&key);
+        Ok(self.remove_entry(&key).is_some() && !expired)
+    }
+
+    async fn contains(&self, key: String) -> HubResult<bool> {
+        let _stripe = self.stripes.read(&key);
+        Ok(self.inner.contains_key(&key) && !self.is_expired(&key))
+    }
+
+    fn scan(&self, range: ScanRange) -> EntryStream {
+        // DashMap has no ordering: snapshot matching entries and sort them.
+        let mut entries: Vec<(String, Vec<u8>)> = self.inner.iter()
+            .filter(|e| range.contains(e.key()) && !self.is_expired(e.key()))
+            .map(|e| (e.key().clone(), e.value().clone()))
+            .collect();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        stream::iter(entries.into_iter().map(Ok)).boxed()
     }
-} 
\ No newline at end of file
+}
@@ -0,0 +1,364 @@
+//! Read merge strategies and write consistency modes used by [`MemoryHub`](crate::MemoryHub).
+use crate::backend::{HubResult, MemoryBackend};
+use crate::error::HubError;
+use crate::record::{Record, WriteMeta};
+use crate::runtime;
+use dashmap::DashMap;
+use futures::future::{join_all, BoxFuture, FutureExt};
+use futures::stream::{FuturesUnordered, StreamExt};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How the hub picks a result when several backends answer a read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReadStrategy {
+    /// Return as soon as any backend has the key; slower backends are not awaited.
+    /// Errors are only reported when no backend has the key.
+    FirstResponder,
+    /// Wait for every backend and take the hit from the lowest tier
+    /// (registration order within a tier). Errors ahead of the hit are returned.
+    #[default]
+    Priority,
+    /// Return once `n` backends agree on the same value (absence counts as a value).
+    /// Fails with a conflict error if responses disagree and none reaches `n`.
+    Quorum(usize),
+    /// Take the value with the highest record version; ties fall back to priority.
+    /// Values from backends that keep no metadata (version `0`) rank lowest.
+    NewestWins,
+}
+
+/// Run a read against `backends` (given in priority order) using `strategy`.
+/// Backend errors are reported as [`HubError::Backend`] under the backend's name.
+pub(crate) async fn read(strategy: ReadStrategy, backends: &[Named], key: String) -> HubResult<Option<Vec<u8>>> {
+    Ok(read_record(strategy, backends, key).await?.map(|r| r.value))
+}
+
+/// Like [`read`], but keeps the winning value's metadata.
+pub(crate) async fn read_record(strategy: ReadStrategy, backends: &[Named], key: String) -> HubResult<Option<Record>> {
+    match strategy {
+        ReadStrategy::FirstResponder => first_responder(backends, key).await,
+        ReadStrategy::Priority => priority(backends, key).await,
+        ReadStrategy::Quorum(n) => quorum(backends, key, n.max(1)).await,
+        ReadStrategy::NewestWins => newest(backends, key).await,
+    }
+}
+
+async fn read_one((name, be): &Named, key: String) -> HubResult<Option<Record>> {
+    be.read_record(key).await.map_err(|e| e.in_backend(name.as_str()))
+}
+
+async fn first_responder(backends: &[Named], key: String) -> HubResult<Option<Record>> {
+    let mut pending: FuturesUnordered<_> = backends.iter().map(|be| read_one(be, key.clone())).collect();
+    let mut errors = Vec::new();
+    while let Some(res) = pending.next().await {
+        match res {
+            Ok(Some(r)) => return Ok(Some(r)),
+            Ok(None) => {}
+            Err(e) => errors.push(e),
+        }
+    }
+    HubError::aggregate(errors).map_or(Ok(None), Err)
+}
+
+async fn priority(backends: &[Named], key: String) -> HubResult<Option<Record>> {
+    let results = join_all(backends.iter().map(|be| read_one(be, key.clone()))).await;
+    let mut errors = Vec::new();
+    for res in results {
+        match res {
+            Ok(Some(_)) if !errors.is_empty() => break,
+            Ok(Some(r)) => return Ok(Some(r)),
+            Ok(None) => {}
+            Err(e) => errors.push(e),
+        }
+    }
+    HubError::aggregate(errors).map_or(Ok(None), Err)
+}
+
+async fn quorum(backends: &[Named], key: String, n: usize) -> HubResult<Option<Record>> {
+    let mut pending: FuturesUnordered<_> = backends.iter().map(|be| read_one(be, key.clone())).collect();
+    // votes are cast on the value only; metadata may differ between backends
+    let mut votes: Vec<(Option<Record>, usize)> = Vec::new();
+    let mut errors = Vec::new();
+    while let Some(res) = pending.next().await {
+        match res {
+            Ok(record) => {
+                let value = record.as_ref().map(|r| &r.value);
+                let idx = match votes.iter().position(|(v, _)| v.as_ref().map(|r| &r.value) == value) {
+                    Some(idx) => idx,
+                    None => { votes.push((record, 0)); votes.len() - 1 }
+                };
+                votes[idx].1 += 1;
+                if votes[idx].1 >= n {
+                    return Ok(votes.swap_remove(idx).0);
+                }
+            }
+            Err(e) => errors.push(e),
+        }
+    }
+    if votes.len() > 1 {
+        return Err(HubError::Conflict(format!("read of {key}: {} distinct values, none reached quorum of {n}", votes.len())));
+    }
+    match HubError::aggregate(errors) {
+        Some(e) => Err(HubError::Unavailable(format!("read quorum of {n} not reached for {key}: {e}"))),
+        None => Err(HubError::Unavailable(format!("read quorum of {n} not reached for {key}: only {} backends", backends.len()))),
+    }
+}
+
+async fn newest(backends: &[Named], key: String) -> HubResult<Option<Record>> {
+    let results = join_all(backends.iter().map(|be| read_one(be, key.clone()))).await;
+    let mut best: Option<Record> = None;
+    let mut errors = Vec::new();
+    for res in results {
+        let candidate = match res {
+            Ok(Some(candidate)) => candidate,
+            Ok(None) => continue,
+            Err(e) => { errors.push(e); continue; }
+        };
+        // strict comparison keeps the higher-priority value on ties
+        if best.as_ref().is_none_or(|b| candidate.meta.version > b.meta.version) {
+            best = Some(candidate);
+        }
+    }
+    HubError::aggregate(errors).map_or(Ok(best), Err)
+}
+
+/// When a hub write is acknowledged to the caller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WriteConcern {
+    /// Every backend must store the value.
+    #[default]
+    All,
+    /// At least `n` backends must store the value.
+    Quorum(usize),
+    /// One backend storing the value is enough.
+    Any,
+    /// The highest-priority backend must store the value; the rest are written asynchronously.
+    PrimaryAsync,
+}
+
+/// Background retry of writes that failed or were still pending when the caller got its answer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Attempts per backend, the original write included. `1` disables retries.
+    pub max_attempts: u32,
+    /// Delay before the first retry; doubled after every failed attempt.
+    pub base_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self { max_attempts: 3, base_delay: Duration::from_millis(50) }
+    }
+}
+
+/// Outcome of a hub write. Backends are identified by the name they were registered under.
+#[derive(Debug, Default)]
+pub struct WriteReport {
+    /// Backends that stored the value.
+    pub succeeded: Vec<String>,
+    /// Backends that returned an error. They are retried in the background.
+    pub failed: Vec<(String, HubError)>,
+    /// Backends still writing when the concern was decided. They finish in the background.
+    pub pending: Vec<String>,
+    /// Whether the requested [`WriteConcern`] was met.
+    pub satisfied: bool,
+}
+
+impl WriteReport {
+    /// Some backend stored the value or may still do so.
+    pub(crate) fn stored(&self) -> bool {
+        !self.succeeded.is_empty() || !self.pending.is_empty()
+    }
+
+    /// Convert into a plain result if the concern was not met: the backend
+    /// errors attributed to their backends, or a summary if none failed.
+    pub fn into_result(self) -> HubResult<()> {
+        if self.satisfied {
+            return Ok(());
+        }
+        let errors = self.failed.into_iter().map(|(name, e)| e.in_backend(name)).collect();
+        Err(HubError::aggregate(errors).unwrap_or_else(|| {
+            HubError::Unavailable(format!("write concern not met: {} succeeded, {} pending", self.succeeded.len(), self.pending.len()))
+        }))
+    }
+}
+
+/// Per-key order of the mutations a hub issued, so that background retries
+/// of a write stop once a newer write or delete of the same key was issued.
+///
+/// Sequence numbers come from a clock that never repeats a value and stays
+/// ahead of the wall clock in microseconds, so they keep growing across
+/// restarts and serve as the record versions of the hub's writes.
+#[derive(Default)]
+pub(crate) struct Sequencer {
+    clock: AtomicU64,
+    /// Latest sequence number per key and the number of live tickets for it.
+    keys: DashMap<String, (u64, usize)>,
+}
+
+impl Sequencer {
+    /// Next value of the clock: the current time in microseconds, or one
+    /// past the previous value if that is not behind it.
+    pub(crate) fn stamp(&self) -> u64 {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_micros() as u64);
+        let prev = self.clock.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |last| Some(now.max(last + 1)))
+            .unwrap_or_else(|last| last);
+        now.max(prev + 1)
+    }
+
+    /// No mutation of `key` is in flight: every ticket for it was dropped.
+    pub(crate) fn is_idle(&self, key: &str) -> bool {
+        !self.keys.contains_key(key)
+    }
+
+    /// Issue the next sequence number for `key`, superseding earlier tickets.
+    pub(crate) fn issue(self: &Arc<Self>, key: &str) -> Ticket {
+        let mut entry = self.keys.entry(key.to_string()).or_insert((0, 0));
+        // numbered under the entry lock so the latest issued ticket holds the highest number
+        let seq = self.stamp();
+        *entry = (seq, entry.1 + 1);
+        drop(entry);
+        Ticket { seqs: Arc::clone(self), key: key.to_string(), seq }
+    }
+}
+
+/// One issued mutation of a key; see [`Sequencer`].
+pub(crate) struct Ticket {
+    seqs: Arc<Sequencer>,
+    key: String,
+    seq: u64,
+}
+
+impl Ticket {
+    /// Record version the mutation stores on every backend.
+    pub(crate) fn version(&self) -> u64 {
+        self.seq
+    }
+
+    /// No newer mutation of the key was issued since this one.
+    pub(crate) fn is_latest(&self) -> bool {
+        self.seqs.keys.get(&self.key).is_some_and(|entry| entry.0 == self.seq)
+    }
+}
+
+impl Drop for Ticket {
+    fn drop(&mut self) {
+        // the entry stays while any ticket is alive, so older ones keep seeing a newer sequence
+        self.seqs.keys.remove_if_mut(&self.key, |_, entry| {
+            entry.1 -= 1;
+            entry.1 == 0
+        });
+    }
+}
+
+/// A backend together with its registration name.
+pub(crate) type Named = (String, Arc<dyn MemoryBackend>);
+
+type IndexedWrite = BoxFuture<'static, (usize, HubResult<()>)>;
+
+/// Fan a write out to `targets` (priority order) and return once `concern` is decided.
+/// Every backend stores the value with `meta`, which carries the version and
+/// expiry, so all copies agree on both. Remaining and failed writes are handed
+/// to a background task driven by `retry`, which gives up once `ticket` is
+/// superseded by a newer mutation of the key.
+pub(crate) async fn write(
+    concern: WriteConcern,
+    retry: RetryPolicy,
+    targets: &[Named],
+    key: String,
+    value: Vec<u8>,
+    meta: WriteMeta,
+    ticket: Ticket,
+) -> WriteReport {
+    let total = targets.len();
+    if total == 0 {
+        return WriteReport { satisfied: concern == WriteConcern::All, ..Default::default() };
+    }
+    let mut pending: FuturesUnordered<IndexedWrite> = targets.iter().enumerate().map(|(idx, (_, be))| {
+        let (be, k, v, meta) = (Arc::clone(be), key.clone(), value.clone(), meta.clone());
+        async move { (idx, be.write_with_meta(k, v, meta).await) }.boxed()
+    }).collect();
+
+    let mut succeeded: Vec<usize> = Vec::new();
+    let mut failed: Vec<(usize, HubError)> = Vec::new();
+    let mut satisfied = false;
+    while let Some((idx, res)) = pending.next().await {
+        match res {
+            Ok(()) => succeeded.push(idx),
+            Err(e) => failed.push((idx, e)),
+        }
+        if let Some(outcome) = decide(concern, total, &succeeded, &failed) {
+            satisfied = outcome;
+            break;
+        }
+    }
+    let in_flight: Vec<usize> = (0..total)
+        .filter(|i| !succeeded.contains(i) && !failed.iter().any(|(f, _)| f == i))
+        .collect();
+    let report = WriteReport {
+        succeeded: succeeded.iter().map(|&i| targets[i].0.clone()).collect(),
+        pending: in_flight.iter().map(|&i| targets[i].0.clone()).collect(),
+        satisfied,
+        ..Default::default()
+    };
+    if pending.is_empty() && failed.is_empty() {
+        return report;
+    }
+
+    // Background work: retries of failed writes plus the writes still in flight.
+    // An in-flight write that fails resolves to its index and gets a retry queued.
+    let backends: Vec<Arc<dyn MemoryBackend>> = targets.iter().map(|(_, be)| Arc::clone(be)).collect();
+    let ticket = Arc::new(ticket);
+    let retry_of = move |idx: usize| -> BoxFuture<'static, Option<usize>> {
+        let (be, ticket) = (Arc::clone(&backends[idx]), Arc::clone(&ticket));
+        retry_write(be, key.clone(), value.clone(), meta.clone(), retry, ticket).map(|_| None).boxed()
+    };
+    let mut work: FuturesUnordered<BoxFuture<'static, Option<usize>>> =
+        failed.iter().map(|(idx, _)| retry_of(*idx)).collect();
+    for fut in pending {
+        work.push(fut.map(|(idx, res)| res.err().map(|_| idx)).boxed());
+    }
+    runtime::spawn(async move {
+        while let Some(done) = work.next().await {
+            if let Some(idx) = done {
+                work.push(retry_of(idx));
+            }
+        }
+    });
+    WriteReport { failed: failed.into_iter().map(|(i, e)| (targets[i].0.clone(), e)).collect(), ..report }
+}
+
+/// `Some(outcome)` once the concern is met or can no longer be met.
+fn decide(concern: WriteConcern, total: usize, succeeded: &[usize], failed: &[(usize, HubError)]) -> Option<bool> {
+    if concern == WriteConcern::PrimaryAsync {
+        if succeeded.contains(&0) { return Some(true); }
+        if failed.iter().any(|(idx, _)| *idx == 0) { return Some(false); }
+        return None;
+    }
+    let needed = match concern {
+        WriteConcern::All => total,
+        WriteConcern::Quorum(n) => n.max(1),
+        _ => 1,
+    };
+    if succeeded.len() >= needed { return Some(true); }
+    if total - failed.len() < needed { return Some(false); }
+    None
+}
+
+/// Retry a failed write with exponential backoff; the first attempt already happened.
+/// Stops without writing once a newer mutation of the key was issued, so a
+/// late retry never rolls the key back.
+async fn retry_write(be: Arc<dyn MemoryBackend>, key: String, value: Vec<u8>, meta: WriteMeta, retry: RetryPolicy, ticket: Arc<Ticket>) {
+    let mut delay = retry.base_delay;
+    for _ in 1..retry.max_attempts {
+        runtime::sleep(delay).await;
+        if !ticket.is_latest() {
+            return;
+        }
+        if be.write_with_meta(key.clone(), value.clone(), meta.clone()).await.is_ok() {
+            return;
+        }
+        delay *= 2;
+    }
+}
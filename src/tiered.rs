@@ -0,0 +1,323 @@
+//! Tiered placement between a fast, a medium and a slow backend.
+//!
+//! Unlike [`MemoryHub`](crate::MemoryHub), which mirrors every write to every
+//! backend, [`TieredHub`] keeps each value in exactly one tier. Writes are
+//! placed by size class (and promoted straight to the hot tier for keys that
+//! are read often), lower-tier read hits are promoted to the hot tier, and hot
+//! entries that went idle are demoted by a background task.
+use crate::backend::{HubResult, MemoryBackend};
+use crate::cancellation::CancellationToken;
+use crate::runtime;
+use async_lock::{Mutex, MutexGuard};
+use dashmap::DashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Storage tier, fastest first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Tier {
+    /// RAM, e.g. `ShortMem`.
+    Hot,
+    /// Persistent key/value store, e.g. `LongMem`.
+    Warm,
+    /// Object storage for large blobs, e.g. `DetailMem`.
+    Cold,
+}
+
+const TIERS: [Tier; 3] = [Tier::Hot, Tier::Warm, Tier::Cold];
+
+/// Number of key locks; keys hashing to the same one share it.
+const STRIPES: usize = 64;
+
+/// Placement rules for [`TieredHub`].
+#[derive(Debug, Clone)]
+pub struct TierPolicy {
+    /// Values up to this size are written to the hot tier.
+    pub hot_max_bytes: usize,
+    /// Values up to this size are written to the warm tier; larger ones go cold.
+    pub warm_max_bytes: usize,
+    /// Reads after which a key counts as frequently accessed: lower-tier hits
+    /// are promoted and rewrites go straight to the hot tier.
+    pub promote_after_hits: u32,
+    /// Values larger than this never enter the hot tier through promotion.
+    pub promote_max_bytes: usize,
+    /// Hot entries not accessed for this long are demoted.
+    pub demote_after: Duration,
+}
+
+impl Default for TierPolicy {
+    fn default() -> Self {
+        Self {
+            hot_max_bytes: 4 * 1024,
+            warm_max_bytes: 1024 * 1024,
+            promote_after_hits: 1,
+            promote_max_bytes: 1024 * 1024,
+            demote_after: Duration::from_secs(300),
+        }
+    }
+}
+
+impl TierPolicy {
+    /// Tier a value of `size` bytes belongs to by size alone.
+    fn size_class(&self, size: usize) -> Tier {
+        if size <= self.hot_max_bytes {
+            Tier::Hot
+        } else if size <= self.warm_max_bytes {
+            Tier::Warm
+        } else {
+            Tier::Cold
+        }
+    }
+
+    /// Tier a hot entry moves to when demoted.
+    fn demotion_target(&self, size: usize) -> Tier {
+        self.size_class(size).max(Tier::Warm)
+    }
+}
+
+/// Occupancy of a single tier.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TierUsage {
+    pub entries: usize,
+    pub bytes: usize,
+}
+
+/// Occupancy of all tiers, as tracked by the hub since start-up.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TierStats {
+    pub hot: TierUsage,
+    pub warm: TierUsage,
+    pub cold: TierUsage,
+    pub promotions: u64,
+    pub demotions: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Placement {
+    tier: Tier,
+    size: usize,
+    hits: u32,
+    last_access: Instant,
+}
+
+/// Hub that places every value in exactly one of three tiers.
+///
+/// Writes, deletes, promotions and demotions of a key hold its stripe lock,
+/// so a value moving between tiers cannot overtake or drop a newer write.
+pub struct TieredHub {
+    tiers: [Arc<dyn MemoryBackend>; 3],
+    policy: TierPolicy,
+    index: DashMap<String, Placement>,
+    stripes: Vec<Mutex<()>>,
+    promotions: AtomicU64,
+    demotions: AtomicU64,
+}
+
+impl TieredHub {
+    /// Build a tiered hub from hot, warm and cold backends.
+    pub fn new(hot: Box<dyn MemoryBackend>, warm: Box<dyn MemoryBackend>, cold: Box<dyn MemoryBackend>, policy: TierPolicy) -> Self {
+        Self {
+            tiers: [hot.into(), warm.into(), cold.into()],
+            policy,
+            index: DashMap::new(),
+            stripes: (0..STRIPES).map(|_| Mutex::new(())).collect(),
+            promotions: Default::default(),
+            demotions: Default::default(),
+        }
+    }
+
+    fn backend(&self, tier: Tier) -> &Arc<dyn MemoryBackend> {
+        &self.tiers[tier as usize]
+    }
+
+    /// Lock serializing the moves of `key`.
+    async fn lock(&self, key: &str) -> MutexGuard<'_, ()> {
+        let mut h = DefaultHasher::new();
+        key.hash(&mut h);
+        self.stripes[h.finish() as usize % STRIPES].lock().await
+    }
+
+    /// Store the value in the tier chosen by size class and access frequency,
+    /// removing any copy held by another tier.
+    pub async fn write(&self, key: String, value: Vec<u8>) -> HubResult<()> {
+        let _lock = self.lock(&key).await;
+        let size = value.len();
+        let hits = self.index.get(&key).map_or(0, |p| p.hits);
+        let tier = if hits >= self.policy.promote_after_hits && size <= self.policy.promote_max_bytes {
+            Tier::Hot
+        } else {
+            self.policy.size_class(size)
+        };
+        let previous = self.index.get(&key).map(|p| p.tier);
+        self.backend(tier).write(key.clone(), value).await?;
+        self.index.insert(key.clone(), Placement { tier, size, hits, last_access: Instant::now() });
+        // an unindexed key (e.g. after restart) may sit in any other tier
+        let stale: Vec<Tier> = match previous {
+            Some(old) => vec![old],
+            None => TIERS.to_vec(),
+        };
+        for old in stale.into_iter().filter(|t| *t != tier) {
+            self.drop_copy(&key, old).await;
+        }
+        Ok(())
+    }
+
+    /// Read the value from whichever tier holds it. A hit outside the hot tier
+    /// is promoted once the key has been read `promote_after_hits` times.
+    pub async fn read(&self, key: String) -> HubResult<Option<Vec<u8>>> {
+        let known = self.index.get(&key).map(|p| p.tier);
+        let found = match known {
+            Some(tier) => self.backend(tier).read(key.clone()).await?.map(|v| (tier, v)),
+            None => None,
+        };
+        let (tier, value) = match found {
+            Some(hit) => hit,
+            // not indexed yet (e.g. after restart) or index is stale
+            None => {
+                let _lock = self.lock(&key).await;
+                let Some((tier, value)) = self.probe(&key).await? else {
+                    self.index.remove(&key);
+                    return Ok(None);
+                };
+                self.index.entry(key.clone())
+                    .and_modify(|p| { p.tier = tier; p.size = value.len(); })
+                    .or_insert(Placement { tier, size: value.len(), hits: 0, last_access: Instant::now() });
+                (tier, value)
+            }
+        };
+        let hits = self.touch(&key);
+        if tier != Tier::Hot && hits >= self.policy.promote_after_hits && value.len() <= self.policy.promote_max_bytes {
+            self.promote(&key, tier).await?;
+        }
+        Ok(Some(value))
+    }
+
+    /// Move the key from `from` to the hot tier, unless a write moved or
+    /// replaced it since it was read.
+    async fn promote(&self, key: &str, from: Tier) -> HubResult<()> {
+        let _lock = self.lock(key).await;
+        if self.index.get(key).is_none_or(|p| p.tier != from) {
+            return Ok(());
+        }
+        let Some(value) = self.backend(from).read(key.to_string()).await? else { return Ok(()) };
+        if value.len() > self.policy.promote_max_bytes {
+            return Ok(());
+        }
+        self.move_entry(key, &value, from, Tier::Hot).await?;
+        self.promotions.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Remove the key from every tier. Returns `true` if any tier held it.
+    pub async fn delete(&self, key: String) -> HubResult<bool> {
+        let _lock = self.lock(&key).await;
+        let mut found = false;
+        for tier in TIERS {
+            found |= self.backend(tier).delete(key.clone()).await?;
+        }
+        self.index.remove(&key);
+        Ok(found)
+    }
+
+    /// Move hot entries idle for longer than `demote_after` to their lower tier.
+    /// Returns the number of demoted entries.
+    pub async fn demote_idle(&self) -> HubResult<usize> {
+        let idle = |p: &Placement| p.tier == Tier::Hot && Instant::now().duration_since(p.last_access) >= self.policy.demote_after;
+        let candidates: Vec<String> = self.index.iter().filter(|p| idle(p)).map(|p| p.key().clone()).collect();
+        let mut demoted = 0;
+        for key in candidates {
+            let _lock = self.lock(&key).await;
+            // written or read since it was picked
+            if !self.index.get(&key).is_some_and(|p| idle(&p)) {
+                continue;
+            }
+            let Some(value) = self.backend(Tier::Hot).read(key.clone()).await? else {
+                self.index.remove(&key);
+                continue;
+            };
+            self.move_entry(&key, &value, Tier::Hot, self.policy.demotion_target(value.len())).await?;
+            // the key has to earn its way back into the hot tier
+            if let Some(mut p) = self.index.get_mut(&key) { p.hits = 0; }
+            demoted += 1;
+        }
+        self.demotions.fetch_add(demoted as u64, Ordering::Relaxed);
+        Ok(demoted)
+    }
+
+    /// Run [`demote_idle`](Self::demote_idle) every `interval` until the returned token is cancelled.
+    pub fn spawn_demoter(self: &Arc<Self>, interval: Duration) -> CancellationToken {
+        let token = CancellationToken::new();
+        let (hub, stop) = (Arc::clone(self), token.clone());
+        runtime::spawn(async move {
+            while !stop.is_cancelled() {
+                runtime::sleep(interval).await;
+                if stop.is_cancelled() { break; }
+                // a failed pass is retried on the next tick
+                let _ = hub.demote_idle().await;
+            }
+        });
+        token
+    }
+
+    /// Occupancy per tier and promotion/demotion counters.
+    pub fn stats(&self) -> TierStats {
+        let mut stats = TierStats {
+            promotions: self.promotions.load(Ordering::Relaxed),
+            demotions: self.demotions.load(Ordering::Relaxed),
+            ..Default::default()
+        };
+        for p in self.index.iter() {
+            let usage = match p.tier {
+                Tier::Hot => &mut stats.hot,
+                Tier::Warm => &mut stats.warm,
+                Tier::Cold => &mut stats.cold,
+            };
+            usage.entries += 1;
+            usage.bytes += p.size;
+        }
+        stats
+    }
+
+    /// Look the key up tier by tier, fastest first.
+    async fn probe(&self, key: &str) -> HubResult<Option<(Tier, Vec<u8>)>> {
+        for tier in TIERS {
+            if let Some(v) = self.backend(tier).read(key.to_string()).await? {
+                return Ok(Some((tier, v)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Record a read hit; returns the updated hit count, `0` if the key was
+    /// deleted meanwhile.
+    fn touch(&self, key: &str) -> u32 {
+        let Some(mut p) = self.index.get_mut(key) else { return 0 };
+        p.hits = p.hits.saturating_add(1);
+        p.last_access = Instant::now();
+        p.hits
+    }
+
+    /// Copy the value into `to`, point the index at it and drop the copy in
+    /// `from`. Caller holds the key's lock.
+    async fn move_entry(&self, key: &str, value: &[u8], from: Tier, to: Tier) -> HubResult<()> {
+        self.backend(to).write(key.to_string(), value.to_vec()).await?;
+        if let Some(mut p) = self.index.get_mut(key) {
+            p.tier = to;
+            p.size = value.len();
+        }
+        self.drop_copy(key, from).await;
+        Ok(())
+    }
+
+    /// Delete the copy of `key` in `tier`, which the index no longer points
+    /// at. Best effort: a copy left behind is only found again by a probe
+    /// after a restart, and the next write of the key removes it.
+    async fn drop_copy(&self, key: &str, tier: Tier) {
+        if let Err(e) = self.backend(tier).delete(key.to_string()).await {
+            log::warn!("tiered hub: stale copy of {key} left in {tier:?}: {e}");
+        }
+    }
+}
@@ -0,0 +1,74 @@
+//! Expiry helpers shared by the backends.
+//!
+//! Persistent backends store absolute deadlines as milliseconds since the Unix
+//! epoch. `ShortMem` keeps monotonic deadlines and schedules them on a hashed
+//! [`TimerWheel`] so the sweeper only visits slots that are due.
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Wall-clock milliseconds since the Unix epoch.
+pub(crate) fn now_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64)
+}
+
+/// Absolute deadline, in epoch milliseconds, `ttl` from now.
+pub(crate) fn deadline_millis(ttl: Duration) -> u64 {
+    now_millis().saturating_add(ttl.as_millis() as u64)
+}
+
+const SLOTS: usize = 512;
+
+/// Hashed timer wheel. Each slot covers `resolution`; deadlines further out
+/// than one revolution stay in their slot until their round comes up.
+pub(crate) struct TimerWheel {
+    start: Instant,
+    resolution: Duration,
+    /// Next tick to be processed.
+    cursor: u64,
+    slots: Vec<Vec<(String, Instant)>>,
+}
+
+impl Default for TimerWheel {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(1))
+    }
+}
+
+impl TimerWheel {
+    pub(crate) fn new(resolution: Duration) -> Self {
+        Self {
+            start: Instant::now(),
+            resolution,
+            cursor: 0,
+            slots: vec![Vec::new(); SLOTS],
+        }
+    }
+
+    fn tick_of(&self, at: Instant) -> u64 {
+        (at.saturating_duration_since(self.start).as_nanos() / self.resolution.as_nanos().max(1)) as u64
+    }
+
+    /// Schedule `key` to fire at `deadline`.
+    pub(crate) fn schedule(&mut self, key: String, deadline: Instant) {
+        // never schedule behind the cursor, or the slot would be skipped
+        let tick = self.tick_of(deadline).max(self.cursor);
+        self.slots[(tick % SLOTS as u64) as usize].push((key, deadline));
+    }
+
+    /// Advance to `now` and return keys whose scheduled deadline has passed.
+    /// Callers must re-check the key's current deadline: it may have been
+    /// rewritten since it was scheduled.
+    pub(crate) fn advance(&mut self, now: Instant) -> Vec<(String, Instant)> {
+        let target = self.tick_of(now);
+        let mut due = Vec::new();
+        // one revolution visits every slot, so more ticks than that are redundant
+        let last = target.min(self.cursor + SLOTS as u64 - 1);
+        for tick in self.cursor..=last {
+            let slot = &mut self.slots[(tick % SLOTS as u64) as usize];
+            let (ready, later): (Vec<_>, Vec<_>) = slot.drain(..).partition(|(_, d)| *d <= now);
+            *slot = later;
+            due.extend(ready);
+        }
+        self.cursor = target;
+        due
+    }
+}
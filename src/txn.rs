@@ -0,0 +1,75 @@
+//! Multi-key transactions applied atomically by a single backend.
+
+/// One operation inside a [`Transaction`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TxOp {
+    Put { key: String, value: Vec<u8> },
+    Delete { key: String },
+}
+
+impl TxOp {
+    pub fn key(&self) -> &str {
+        match self {
+            TxOp::Put { key, .. } | TxOp::Delete { key } => key,
+        }
+    }
+}
+
+/// Ordered list of puts and deletes committed all-or-nothing through
+/// [`MemoryBackend::commit`](crate::MemoryBackend::commit). Later operations on
+/// the same key win; puts behave like plain writes and clear any expiry.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Transaction {
+    ops: Vec<TxOp>,
+    version: Option<u64>,
+}
+
+impl Transaction {
+    /// Start an empty transaction.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue a write of `value` under `key`.
+    pub fn put(&mut self, key: String, value: Vec<u8>) -> &mut Self {
+        self.ops.push(TxOp::Put { key, value });
+        self
+    }
+
+    /// Queue removal of `key`.
+    pub fn delete(&mut self, key: String) -> &mut Self {
+        self.ops.push(TxOp::Delete { key });
+        self
+    }
+
+    /// Version every put is stored under, set by the hub so that all
+    /// backends agree. `None` lets each backend count on from the stored one.
+    pub fn version(&self) -> Option<u64> {
+        self.version
+    }
+
+    pub(crate) fn with_version(mut self, version: u64) -> Self {
+        self.version = Some(version);
+        self
+    }
+
+    pub fn ops(&self) -> &[TxOp] {
+        &self.ops
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// Distinct keys touched, sorted. Backends lock in this order.
+    pub(crate) fn keys(&self) -> Vec<&str> {
+        let mut keys: Vec<&str> = self.ops.iter().map(TxOp::key).collect();
+        keys.sort_unstable();
+        keys.dedup();
+        keys
+    }
+
+    pub(crate) fn into_ops(self) -> Vec<TxOp> {
+        self.ops
+    }
+}